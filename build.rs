@@ -1,11 +1,38 @@
 fn main() {
-	#[cfg(not(feature = "mock_vc_ffi"))]
+	println!("cargo:rerun-if-env-changed=VIDEOCORE_GENCMD_NO_LINK");
+	println!("cargo:rustc-check-cfg=cfg(global_instance_available)");
+	println!("cargo:rustc-check-cfg=cfg(native_vchiq_instance)");
+
+	#[cfg(feature = "mock_vc_ffi")]
+	println!("cargo:rustc-cfg=global_instance_available");
+
+	// the mock takes precedence so that tests keep working with all features enabled
+	#[cfg(all(feature = "native_vchiq", not(feature = "mock_vc_ffi")))]
 	{
+		println!("cargo:rustc-cfg=native_vchiq_instance");
+		println!("cargo:rustc-cfg=global_instance_available");
+	}
+
+	// the libraries are loaded at runtime
+	#[cfg(all(
+		feature = "dlopen_vc_ffi",
+		not(any(feature = "mock_vc_ffi", feature = "native_vchiq"))
+	))]
+	println!("cargo:rustc-cfg=global_instance_available");
+
+	// allows building without the vc libraries when only backends that don't need them are used
+	#[cfg(not(any(
+		feature = "mock_vc_ffi",
+		feature = "native_vchiq",
+		feature = "dlopen_vc_ffi"
+	)))]
+	if std::env::var_os("VIDEOCORE_GENCMD_NO_LINK").is_none() {
 		println!("cargo:rustc-link-lib=vchiq_arm");
 		println!("cargo:rustc-link-lib=vcos");
 		println!("cargo:rustc-link-lib=bcm_host");
 		println!("cargo:rustc-link-search=/opt/vc/lib");
 		println!("cargo:rustc-link-arg=-Wl,-rpath=/opt/vc/lib");
+		println!("cargo:rustc-cfg=global_instance_available");
 	}
 
 	#[cfg(feature = "run_bindgen")]
@@ -0,0 +1,559 @@
+//! Mock backend answering commands from memory.
+//!
+//! The canned responses are shared with the `mock_vc_ffi` C ABI mock, so this backend behaves the same
+//! as the mocked FFI but can be used in the same build as the real bindings.
+//!
+//! Responses can be configured at runtime with a [`MockGencmd`], which is what the `mock_vc_ffi` mock answers from as well
+//! (see `ffi::mock::MOCK_GENCMD`).
+
+use std::{
+	ffi::CStr,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use super::GencmdBackend;
+use crate::error::GencmdCmdError;
+
+pub(crate) const RESPONSE_ERROR_1: &[u8] = b"error=1 error_msg=\"command not registered\"\0";
+const RESPONSE_ERROR_2: &[u8] = b"error=2 error_msg=\"invalid arguments\"\0";
+const RESPONSE_COMMANDS: &[u8] = b"commands=\"vcos, ap_output_control, ap_output_post_processing, vchi_test_init, vchi_test_exit, pm_set_policy, pm_get_status, pm_show_stats, pm_start_logging, pm_stop_logging, version, commands, set_vll_dir, set_backlight, set_logging, get_lcd_info, arbiter, cache_flush, otp_dump, test_result, codec_enabled, get_camera, get_mem, measure_clock, measure_volts, enable_clock, scaling_kernel, scaling_sharpness, get_hvs_asserts, get_throttled, measure_temp, get_config, hdmi_ntsc_freqs, hdmi_adjust_clock, hdmi_status_show, hvs_update_fields, pwm_speedup, force_audio, hdmi_stream_channels, hdmi_channel_map, display_power, read_ring_osc, memtest, dispmanx_list, get_rsts, schmoo, render_bar, disk_notify, inuse_notify, sus_suspend, sus_status, sus_is_enabled, sus_stop_test_thread, egl_platform_switch, mem_validate, mem_oom, mem_reloc_stats, hdmi_cvt, hdmi_timings, readmr, pmicrd, pmicwr, bootloader_version, bootloader_config, file, vctest_memmap, vctest_start, vctest_stop, vctest_set, vctest_get\"\0";
+const RESPONSE_GET_THROTTLED: &[u8] = b"throttled=0x0\0";
+const RESPONSE_MEASURE_CLOCK_ARM: &[u8] = b"frequency(48)=6000000\0";
+const RESPONSE_MEASURE_TEMP: &[u8] = b"temp=45.6'C\0";
+const RESPONSE_VCOS_VERSION: &[u8] = b"Mar 17 2023 10:50:39 \nCopyright (c) 2011 Broadcom\nversion 82f3750a65fadae9a38077e3c2e217ad158c8d54 (clean)\nhost buildbot\n\0";
+const RESPONSE_VCOS_LOG_STATUS: &[u8] =
+	b"mmal      - error\nvchiq     - warn\nhdmi      - info\nvcos_cmd  - never\n\0";
+const RESPONSE_VCOS_HEAP: &[u8] = b"total=8388608 used=2097152 free=6291456 largest_free=6029312\0";
+const RESPONSE_VERSION: &[u8] = b"Mar 17 2023 10:52:42 \nCopyright (c) 2012 Broadcom\nversion 82f3750a65fadae9a38077e3c2e217ad158c8d54 (clean) (release) (start)\0";
+const RESPONSE_OTP_DUMP: &[u8] = b"08:00000000\n09:00000000\n10:00000000\n11:00000000\n12:00000000\n13:00000000\n14:00000000\n15:00000000\n16:00280000\n17:1020000a\n18:1020000a\n19:ffffffff\n20:ffffffff\n21:ffffffff\n22:ffffffff\n23:ffffffff\n24:ffffffff\n25:ffffffff\n26:ffffffff\n27:00001f1f\n28:8c2b5d3a\n29:73d4a2c5\n30:00a020d3\n31:00000000\n32:00000000\n33:00000000\n34:00000000\n35:00000000\n36:00000000\n37:00000000\n38:00000000\n39:00000000\n40:00000000\n41:00000000\n42:00000000\n43:00000000\n44:00000000\n45:00000000\n46:00000000\n47:00000000\n48:00000000\n49:00000000\n50:00000000\n51:00000000\n52:00000000\n53:00000000\n54:00000000\n55:00000000\n56:00000000\n57:00000000\n58:00000000\n59:00000000\n60:00000000\n61:00000000\n62:00000000\n63:00000000\n64:00000000\n65:00000000\n66:00000000\0";
+const RESPONSE_GET_CONFIG_INT: &[u8] = b"aphy_params_current=819\narm_freq=1400\narm_freq_min=600\naudio_pwm_mode=514\nconfig_hdmi_boost=5\ncore_freq=400\ncore_freq_min=250\ndisable_commandline_tags=2\ndisable_l2cache=1\ndisplay_hdmi_rotate=-1\ndisplay_lcd_rotate=-1\ndphy_params_current=547\nenable_tvout=1\nforce_eeprom_read=1\nforce_pwm_open=1\nframebuffer_ignore_alpha=1\nframebuffer_swap=1\ngpu_freq=300\ngpu_freq_min=250\ngpu_mem=76\ninit_uart_clock=0x2dc6c00\nhdmi_force_cec_address:0=65535\nhdmi_force_cec_address:1=65535\nhdmi_pixel_freq_limit=0x9a7ec80\nignore_lcd=1\nmax_framebuffers=2\nover_voltage_avs=31250\nover_voltage_avs_boost=0x1e848\npause_burst_frames=1\nprogram_serial_random=1\nsdram_freq=500\ntotal_mem=1024\nhdmi_force_cec_address=65535\0";
+const RESPONSE_GET_CONFIG_STR: &[u8] = b"device_tree=-\nkernel=kernel7.img\0";
+const RESPONSE_MEM_OOM: &[u8] = b"oom events: 0\nlifetime oom required: 0 Mbytes\ntotal time in oom handler: 0 ms\nmax time spent in oom handler: 0 ms\0";
+const RESPONSE_MEM_RELOC_STATS: &[u8] =
+	b"alloc failures:     0\ncompactions:        0\nlegacy block fails: 0\0";
+const RESPONSE_BOOTLOADER_VERSION: &[u8] = b"Sep 10 2019 10:41:50\nversion f626c772b15ba1b7e0532a8d50a761b3ccbdf3bb (release)\ntimestamp 1568112110\0";
+const RESPONSE_BOOTLOADER_CONFIG: &[u8] =
+	b"[all]\nBOOT_UART=0\nWAKE_ON_GPIO=1\nPOWER_OFF_ON_HALT=0\nFREEZE_VERSION=0\0";
+const RESPONSE_DISPMANX_LIST: &[u8] = b"display:2 format:XRGB8888 transform:0 layer:-127 src:0,0,1920,1080 dst:0,0,1920,1080 cost:1015 lbm:0\0";
+
+/// Returns the canned response to `command`, including the null terminator.
+///
+/// The responses are those of a Raspberry Pi 3 Model B+, including the common argument variants.
+///
+/// Not every command in `RESPONSE_COMMANDS` has been captured: the commands which only act on the firmware
+/// (such as `cache_flush` or `vchi_test_init`), the power management, arbiter, test and `vctest` commands and all commands
+/// taking arguments other than those listed. Rather than making up output, those answer with the invalid arguments error
+/// the firmware returns for registered commands it cannot serve. Unknown commands are not registered.
+pub(crate) fn canned_response(command: &str) -> &'static [u8] {
+	match command {
+		"commands" => RESPONSE_COMMANDS,
+		"version" => RESPONSE_VERSION,
+		"get_throttled" => RESPONSE_GET_THROTTLED,
+		"measure_temp" => RESPONSE_MEASURE_TEMP,
+
+		"measure_clock" => RESPONSE_ERROR_2,
+		"measure_clock arm" => RESPONSE_MEASURE_CLOCK_ARM,
+		"measure_clock core" => b"frequency(1)=400000000\0",
+		"measure_clock h264" => b"frequency(28)=0\0",
+		"measure_clock isp" => b"frequency(45)=0\0",
+		"measure_clock v3d" => b"frequency(46)=300000000\0",
+		"measure_clock uart" => b"frequency(22)=48000000\0",
+		"measure_clock pwm" => b"frequency(25)=0\0",
+		"measure_clock emmc" => b"frequency(47)=250000000\0",
+		"measure_clock pixel" => b"frequency(29)=0\0",
+		"measure_clock vec" => b"frequency(10)=0\0",
+		"measure_clock hdmi" => b"frequency(9)=0\0",
+		"measure_clock dpi" => b"frequency(4)=0\0",
+
+		"measure_volts" | "measure_volts core" => b"volt=1.2000V\0",
+		"measure_volts sdram_c" | "measure_volts sdram_i" => b"volt=1.2000V\0",
+		"measure_volts sdram_p" => b"volt=1.2250V\0",
+
+		"get_config" => RESPONSE_ERROR_2,
+		"get_config int" => RESPONSE_GET_CONFIG_INT,
+		"get_config str" => RESPONSE_GET_CONFIG_STR,
+		"get_config arm_freq" => b"arm_freq=1400\0",
+		"get_config core_freq" => b"core_freq=400\0",
+		"get_config gpu_freq" => b"gpu_freq=300\0",
+		"get_config sdram_freq" => b"sdram_freq=500\0",
+		"get_config gpu_mem" => b"gpu_mem=76\0",
+		"get_config total_mem" => b"total_mem=1024\0",
+
+		"get_mem" => RESPONSE_ERROR_2,
+		"get_mem arm" => b"arm=948M\0",
+		"get_mem gpu" => b"gpu=76M\0",
+
+		"codec_enabled" => RESPONSE_ERROR_2,
+		"codec_enabled H264" => b"H264=enabled\0",
+		"codec_enabled MPG2" => b"MPG2=disabled\0",
+		"codec_enabled WVC1" => b"WVC1=disabled\0",
+		"codec_enabled MPG4" => b"MPG4=enabled\0",
+		"codec_enabled MJPG" => b"MJPG=enabled\0",
+		"codec_enabled WMV9" => b"WMV9=disabled\0",
+		"codec_enabled H263" => b"H263=enabled\0",
+		"codec_enabled VP6" => b"VP6=enabled\0",
+		"codec_enabled VP8" => b"VP8=enabled\0",
+		"codec_enabled THRA" => b"THRA=enabled\0",
+
+		"display_power" | "display_power 1" | "display_power -1" => b"display_power=1\0",
+		"display_power 0" => b"display_power=0\0",
+
+		"vcos" | "vcos log" => RESPONSE_ERROR_2,
+		"vcos version" => RESPONSE_VCOS_VERSION,
+		"vcos log status" => RESPONSE_VCOS_LOG_STATUS,
+		"vcos heap" => RESPONSE_VCOS_HEAP,
+
+		"otp_dump" => RESPONSE_OTP_DUMP,
+		"get_camera" => b"supported=0 detected=0, libcamera interfaces=0\0",
+		"get_lcd_info" => b"1920 1080 24\0",
+		"get_rsts" => b"get_rsts=1000\0",
+		"read_ring_osc" => b"ring_osc(1)=3.479MHz (@1.2000V) (45.6'C)\0",
+		"hdmi_timings" => b"hdmi_timings=0 1 0 0 0 0 1 0 0 0 0 0 0 0 0 0 0\0",
+		"hdmi_stream_channels" => b"hdmi_stream_channels=0\0",
+		"hdmi_channel_map" => b"hdmi_channel_map=0x13fac688\0",
+		"scaling_kernel" => b"scaling_kernel=0\0",
+		"scaling_sharpness" => b"scaling_sharpness=0\0",
+		"sus_status" => b"sus_status=0\0",
+		"sus_is_enabled" => b"sus_is_enabled=0\0",
+		"dispmanx_list" => RESPONSE_DISPMANX_LIST,
+		"mem_oom" => RESPONSE_MEM_OOM,
+		"mem_reloc_stats" => RESPONSE_MEM_RELOC_STATS,
+		"bootloader_version" => RESPONSE_BOOTLOADER_VERSION,
+		"bootloader_config" => RESPONSE_BOOTLOADER_CONFIG,
+		"readmr" | "pmicrd" | "pmicwr" | "file" => RESPONSE_ERROR_2,
+		"readmr 0" => b"0x00000006\0",
+		"pmicrd 0" => b"0x00\0",
+
+		command if is_registered(command) => RESPONSE_ERROR_2,
+		_ => RESPONSE_ERROR_1,
+	}
+}
+
+/// Returns true if the name of `command` is in `RESPONSE_COMMANDS`.
+fn is_registered(command: &str) -> bool {
+	let name = command.split(' ').next().unwrap_or_default();
+	let commands = std::str::from_utf8(RESPONSE_COMMANDS).unwrap();
+
+	commands
+		.trim_start_matches("commands=\"")
+		.trim_end_matches("\"\0")
+		.split(", ")
+		.any(|registered| registered == name)
+}
+
+/// Selects the commands a [`MockGencmd`] rule applies to.
+pub enum MockPattern {
+	/// The whole command, including arguments.
+	Exact(String),
+	Prefix(String),
+	#[cfg(feature = "mock_regex")]
+	Regex(regex::Regex),
+	Predicate(Box<dyn Fn(&str) -> bool + Send>),
+}
+impl MockPattern {
+	pub fn matches(&self, command: &str) -> bool {
+		match self {
+			MockPattern::Exact(exact) => command == exact,
+			MockPattern::Prefix(prefix) => command.starts_with(prefix.as_str()),
+			#[cfg(feature = "mock_regex")]
+			MockPattern::Regex(regex) => regex.is_match(command),
+			MockPattern::Predicate(predicate) => predicate(command),
+		}
+	}
+}
+impl From<&str> for MockPattern {
+	fn from(command: &str) -> Self {
+		MockPattern::Exact(command.to_string())
+	}
+}
+impl From<String> for MockPattern {
+	fn from(command: String) -> Self {
+		MockPattern::Exact(command)
+	}
+}
+#[cfg(feature = "mock_regex")]
+impl From<regex::Regex> for MockPattern {
+	fn from(regex: regex::Regex) -> Self {
+		MockPattern::Regex(regex)
+	}
+}
+
+struct MockRule {
+	pattern: MockPattern,
+	responses: Vec<String>,
+	next: usize,
+}
+
+struct MockState {
+	rules: Vec<MockRule>,
+	log: Vec<String>,
+}
+
+/// Runtime configurable responses of the mocks.
+///
+/// Commands are answered by the most recently added rule whose pattern matches, falling back to the canned responses.
+/// Every received command is logged so that tests can assert on what was sent.
+///
+/// ```
+/// use std::sync::Arc;
+/// use videocore_gencmd::{backend::mock::{MockBackend, MockGencmd, MockPattern}, prelude::*};
+///
+/// let mock = Arc::new(MockGencmd::new());
+/// mock.set_response("measure_temp", "temp=71.2'C");
+/// mock.set_sequence(
+///     MockPattern::Prefix("get_throttled".to_string()),
+///     ["throttled=0x0", "throttled=0x50005"],
+/// );
+///
+/// let mut gencmd = GencmdUnique::from_backend(MockBackend::with_mock(mock.clone()));
+/// assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 71.2);
+/// assert_eq!(mock.commands(), vec!["measure_temp"]);
+/// ```
+pub struct MockGencmd {
+	state: Mutex<MockState>,
+}
+impl MockGencmd {
+	pub const fn new() -> Self {
+		MockGencmd {
+			state: Mutex::new(MockState {
+				rules: Vec::new(),
+				log: Vec::new(),
+			}),
+		}
+	}
+
+	fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+		self.state.lock().expect("mutex poisoned")
+	}
+
+	/// Answers `command` with `response`, replacing any previous response set for the exact same command.
+	pub fn set_response(&self, command: &str, response: impl Into<String>) {
+		let mut state = self.lock();
+		state.rules.retain(
+			|rule| !matches!(rule.pattern, MockPattern::Exact(ref exact) if exact == command),
+		);
+		state.rules.push(MockRule {
+			pattern: MockPattern::Exact(command.to_string()),
+			responses: vec![response.into()],
+			next: 0,
+		});
+	}
+
+	/// Answers commands matching `pattern` with `response`.
+	pub fn set_pattern_response(
+		&self,
+		pattern: impl Into<MockPattern>,
+		response: impl Into<String>,
+	) {
+		self.set_sequence(pattern, [response]);
+	}
+
+	/// Answers commands matching `pattern` with `responses` in order. Once exhausted, the last response is repeated.
+	///
+	/// ### Panic
+	/// Panics if `responses` is empty.
+	pub fn set_sequence(
+		&self,
+		pattern: impl Into<MockPattern>,
+		responses: impl IntoIterator<Item = impl Into<String>>,
+	) {
+		let responses: Vec<String> = responses.into_iter().map(Into::into).collect();
+		assert!(!responses.is_empty(), "response sequence must not be empty");
+
+		self.lock().rules.push(MockRule {
+			pattern: pattern.into(),
+			responses,
+			next: 0,
+		});
+	}
+
+	/// Returns the commands received so far.
+	pub fn commands(&self) -> Vec<String> {
+		self.lock().log.clone()
+	}
+
+	pub fn clear_log(&self) {
+		self.lock().log.clear();
+	}
+
+	/// Removes all rules and clears the log.
+	pub fn reset(&self) {
+		let mut state = self.lock();
+		state.rules.clear();
+		state.log.clear();
+	}
+
+	/// Logs `command` and returns its response.
+	pub fn respond(&self, command: &str) -> String {
+		let mut state = self.lock();
+		state.log.push(command.to_string());
+
+		match state
+			.rules
+			.iter_mut()
+			.rev()
+			.find(|rule| rule.pattern.matches(command))
+		{
+			Some(rule) => {
+				let response = rule.responses[rule.next].clone();
+				rule.next = (rule.next + 1).min(rule.responses.len() - 1);
+
+				response
+			}
+			None => {
+				let response = canned_response(command);
+				String::from_utf8_lossy(&response[..response.len() - 1]).into_owned()
+			}
+		}
+	}
+}
+impl Default for MockGencmd {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+type Responder = Box<dyn FnMut(&str) -> String + Send>;
+
+/// Backend which answers commands without any communication with the firmware.
+///
+/// By default it answers with the same canned responses as the `mock_vc_ffi` mock. A custom responder can be
+/// provided with [`with_responder`](Self::with_responder).
+pub struct MockBackend {
+	responder: Option<Responder>,
+	response: Vec<u8>,
+}
+impl MockBackend {
+	pub fn new() -> Self {
+		MockBackend {
+			responder: None,
+			response: RESPONSE_ERROR_1.to_vec(),
+		}
+	}
+
+	/// Creates a backend which answers each command with the return value of `responder`.
+	pub fn with_responder(responder: impl FnMut(&str) -> String + Send + 'static) -> Self {
+		MockBackend {
+			responder: Some(Box::new(responder)),
+			response: RESPONSE_ERROR_1.to_vec(),
+		}
+	}
+
+	/// Creates a backend which answers from `mock`.
+	pub fn with_mock(mock: Arc<MockGencmd>) -> Self {
+		Self::with_responder(move |command| mock.respond(command))
+	}
+}
+impl Default for MockBackend {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+impl GencmdBackend for MockBackend {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		log::trace!("mock backend command: {:?}", command);
+
+		let command = match command.to_str() {
+			Ok(command) => command,
+			Err(err) => {
+				log::warn!("Unsupported command bytes for mock backend: {}", err);
+				self.response = RESPONSE_ERROR_1.to_vec();
+				return Ok(());
+			}
+		};
+
+		self.response = match self.responder {
+			None => canned_response(command).to_vec(),
+			Some(ref mut responder) => {
+				let mut response = responder(command).into_bytes();
+				response.push(0);
+				response
+			}
+		};
+
+		Ok(())
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		if buffer.len() < self.response.len() {
+			return Err(GencmdCmdError::Read);
+		}
+
+		buffer[..self.response.len()].copy_from_slice(&self.response);
+
+		Ok(self.response.len() - 1)
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		_timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		// canned responses are available right away
+		self.retrieve_response(buffer)
+	}
+
+	fn supports_timeout(&self) -> bool {
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::sync::Arc;
+
+	use super::{MockBackend, MockGencmd, MockPattern};
+	use crate::{
+		error::{GencmdCmdError, GencmdErrorResponse},
+		gencmd::{
+			commands::{CmdCommands, CmdMeasureTemp, CmdVcosHeap},
+			unique::GencmdUnique,
+		},
+	};
+
+	#[test]
+	fn answers_canned_responses() {
+		let mut gencmd = GencmdUnique::from_backend(MockBackend::new());
+
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 45.6);
+		assert!(gencmd.send_cmd_raw("not_a_command").is_err());
+	}
+
+	#[test]
+	fn answers_every_advertised_command() {
+		let mut gencmd = GencmdUnique::from_backend(MockBackend::new());
+
+		let commands: Vec<String> = gencmd
+			.send_cmd::<CmdCommands>()
+			.unwrap()
+			.into_iter()
+			.map(String::from)
+			.collect();
+		assert_eq!(commands.len(), 70);
+
+		// the firmware refuses these without arguments
+		const TAKE_ARGUMENTS: &[&str] = &[
+			"vcos",
+			"ap_output_control",
+			"ap_output_post_processing",
+			"pm_set_policy",
+			"set_vll_dir",
+			"set_backlight",
+			"set_logging",
+			"codec_enabled",
+			"get_mem",
+			"measure_clock",
+			"enable_clock",
+			"get_config",
+			"hdmi_adjust_clock",
+			"hvs_update_fields",
+			"force_audio",
+			"schmoo",
+			"render_bar",
+			"disk_notify",
+			"inuse_notify",
+			"egl_platform_switch",
+			"hdmi_cvt",
+			"readmr",
+			"pmicrd",
+			"pmicwr",
+			"file",
+			"vctest_memmap",
+			"vctest_set",
+		];
+
+		for command in commands {
+			let response = gencmd.send_cmd_raw(&command);
+
+			if TAKE_ARGUMENTS.contains(&command.as_str()) {
+				assert!(
+					matches!(
+						response,
+						Err(GencmdCmdError::ErrorResponse(
+							GencmdErrorResponse::InvalidArguments
+						))
+					),
+					"{} answered without arguments",
+					command
+				);
+			} else {
+				// commands without a captured response answer with the error of the firmware
+				match response {
+					Ok(response) => assert!(!response.is_empty(), "{} answered nothing", command),
+					Err(GencmdCmdError::ErrorResponse(GencmdErrorResponse::InvalidArguments)) => (),
+					Err(err) => panic!("{} failed: {}", command, err),
+				}
+			}
+		}
+
+		assert!(matches!(
+			gencmd.send_cmd_raw("set_backlight 255"),
+			Err(GencmdCmdError::ErrorResponse(
+				GencmdErrorResponse::InvalidArguments
+			))
+		));
+		assert!(matches!(
+			gencmd.send_cmd_raw("not_a_command 1"),
+			Err(GencmdCmdError::ErrorResponse(
+				GencmdErrorResponse::CommandNotRegistered
+			))
+		));
+
+		assert_eq!(gencmd.send_cmd_raw("get_mem arm").unwrap(), "arm=948M");
+		assert!(gencmd
+			.send_cmd_raw("get_config int")
+			.unwrap()
+			.contains("arm_freq=1400"));
+	}
+
+	#[test]
+	fn answers_with_responder() {
+		let mut gencmd =
+			GencmdUnique::from_backend(MockBackend::with_responder(|command| match command {
+				"measure_temp" => "temp=80.0'C".to_string(),
+				_ => "error=2 error_msg=\"invalid arguments\"".to_string(),
+			}));
+
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 80.0);
+		assert!(gencmd.send_cmd::<CmdVcosHeap>().is_err());
+	}
+
+	#[test]
+	fn answers_from_mock_gencmd() {
+		let mock = Arc::new(MockGencmd::new());
+		mock.set_response("measure_temp", "temp=50.0'C");
+		mock.set_response("measure_temp", "temp=60.0'C");
+		mock.set_pattern_response(
+			MockPattern::Predicate(Box::new(|command| command.ends_with("heap"))),
+			"error=2 error_msg=\"invalid arguments\"",
+		);
+		mock.set_sequence(MockPattern::Prefix("my_".to_string()), ["first", "second"]);
+
+		let mut gencmd = GencmdUnique::from_backend(MockBackend::with_mock(mock.clone()));
+
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 60.0);
+		assert!(gencmd.send_cmd::<CmdVcosHeap>().is_err());
+		assert_eq!(gencmd.send_cmd_raw("my_command").unwrap(), "first");
+		assert_eq!(gencmd.send_cmd_raw("my_command 1").unwrap(), "second");
+		assert_eq!(gencmd.send_cmd_raw("my_command").unwrap(), "second");
+		// falls back to canned responses
+		assert!(gencmd.send_cmd_raw("vcos version").is_ok());
+
+		assert_eq!(
+			mock.commands(),
+			vec![
+				"measure_temp",
+				"vcos heap",
+				"my_command",
+				"my_command 1",
+				"my_command",
+				"vcos version"
+			]
+		);
+
+		mock.reset();
+		assert!(mock.commands().is_empty());
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 45.6);
+	}
+}
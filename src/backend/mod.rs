@@ -0,0 +1,113 @@
+//! Backends transport gencmd commands to the firmware and responses back.
+//!
+//! The default backend is the [`GlobalInstance`](crate::global::GlobalInstance), which uses the VideoCore FFI.
+//! Other backends can be used interchangeably with [`Gencmd`](crate::gencmd::Gencmd) and its wrappers.
+
+use std::{ffi::CStr, sync::OnceLock, time::Duration};
+
+use crate::{error::GencmdCmdError, gencmd::capabilities::Capabilities};
+
+pub mod mock;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod simulated;
+pub mod subprocess;
+pub mod sysfs;
+pub mod transcript;
+#[cfg(feature = "native_vchiq")]
+pub mod vchiq;
+#[cfg(feature = "vcio_backend")]
+pub mod vcio;
+
+/// Transport of gencmd commands.
+pub trait GencmdBackend {
+	/// Sends a command.
+	///
+	/// ### Safety
+	/// The response must be retrieved using [`retrieve_response`](Self::retrieve_response) before another command is sent.
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError>;
+
+	/// Retrieves the response to the last sent command.
+	///
+	/// The response is written into `buffer` with a null terminator. Returns number of bytes read into `buffer` (excluding the null terminator).
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError>;
+
+	/// Retrieves the response to the last sent command, failing with [`GencmdCmdError::Timeout`] if it does not arrive within `timeout`.
+	///
+	/// Only called if [`supports_timeout`](Self::supports_timeout) returns true. The default implementation fails with
+	/// [`GencmdCmdError::TimeoutUnsupported`], leaving the response to be retrieved.
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		let _ = (buffer, timeout);
+
+		Err(GencmdCmdError::TimeoutUnsupported)
+	}
+
+	/// Returns true if [`retrieve_response_timeout`](Self::retrieve_response_timeout) is implemented.
+	///
+	/// Checked before sending a command with a timeout, so that it is not sent at all if the response could block forever.
+	/// The default implementation returns false.
+	fn supports_timeout(&self) -> bool {
+		false
+	}
+
+	/// Capabilities of the firmware shared by everything using this backend, fetched once on first use.
+	///
+	/// The default implementation returns `None`, in which case capabilities are only kept per [`Gencmd`](crate::gencmd::Gencmd).
+	fn shared_capabilities(&self) -> Option<&OnceLock<Capabilities>> {
+		None
+	}
+}
+impl<B: GencmdBackend + ?Sized> GencmdBackend for &mut B {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		(**self).send_command(command)
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		(**self).retrieve_response(buffer)
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		(**self).retrieve_response_timeout(buffer, timeout)
+	}
+
+	fn supports_timeout(&self) -> bool {
+		(**self).supports_timeout()
+	}
+
+	fn shared_capabilities(&self) -> Option<&OnceLock<Capabilities>> {
+		(**self).shared_capabilities()
+	}
+}
+impl<B: GencmdBackend + ?Sized> GencmdBackend for Box<B> {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		(**self).send_command(command)
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		(**self).retrieve_response(buffer)
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		(**self).retrieve_response_timeout(buffer, timeout)
+	}
+
+	fn supports_timeout(&self) -> bool {
+		(**self).supports_timeout()
+	}
+
+	fn shared_capabilities(&self) -> Option<&OnceLock<Capabilities>> {
+		(**self).shared_capabilities()
+	}
+}
@@ -0,0 +1,256 @@
+//! Backend sending commands to a [`GencmdServer`](crate::remote::server::GencmdServer).
+
+use std::{
+	ffi::CStr,
+	io::{Read, Write},
+	net::{TcpStream, ToSocketAddrs},
+	time::{Duration, Instant},
+};
+
+use super::GencmdBackend;
+use crate::{
+	error::{GencmdCmdError, GencmdInitError},
+	remote::{
+		protocol::{split_frame, ProtocolError, RemoteError, Request, Response},
+		ReadTimeout,
+	},
+};
+
+/// Client of a remote gencmd server.
+///
+/// A response which timed out is skipped once it arrives, before the response to the next command is read.
+pub struct RemoteBackend<S: Read + Write = TcpStream> {
+	stream: S,
+	command: String,
+	/// Received bytes of frames not handled yet.
+	received: Vec<u8>,
+	/// Number of responses the server still has to send.
+	pending: usize,
+}
+impl RemoteBackend {
+	/// Connects to the server at `address`, authenticating with `secret` if provided.
+	pub fn connect(
+		address: impl ToSocketAddrs,
+		secret: Option<&str>,
+	) -> Result<Self, GencmdInitError> {
+		let stream = TcpStream::connect(address).map_err(GencmdInitError::Connect)?;
+		stream.set_nodelay(true).map_err(GencmdInitError::Connect)?;
+
+		Self::from_stream(stream, secret)
+	}
+}
+#[cfg(unix)]
+impl RemoteBackend<std::os::unix::net::UnixStream> {
+	/// Connects to the local daemon listening on `path`, authenticating with `secret` if provided.
+	///
+	/// See [`DEFAULT_SOCKET_PATH`](crate::remote::protocol::DEFAULT_SOCKET_PATH) for the default path.
+	pub fn connect_unix(
+		path: impl AsRef<std::path::Path>,
+		secret: Option<&str>,
+	) -> Result<Self, GencmdInitError> {
+		let stream =
+			std::os::unix::net::UnixStream::connect(path).map_err(GencmdInitError::Connect)?;
+
+		Self::from_stream(stream, secret)
+	}
+}
+impl<S: Read + Write> RemoteBackend<S> {
+	/// Uses an already connected `stream`, authenticating with `secret` if provided.
+	pub fn from_stream(mut stream: S, secret: Option<&str>) -> Result<Self, GencmdInitError> {
+		if let Some(secret) = secret {
+			let response = Request::Auth(secret.to_string())
+				.write_to(&mut stream)
+				.and_then(|_| Response::read_from(&mut stream));
+
+			match response {
+				Ok(Response::Ok(_)) => (),
+				Ok(Response::Error(RemoteError::Unauthorized)) => {
+					return Err(GencmdInitError::Unauthorized)
+				}
+				// the server closes the connection after a failed authentication
+				Err(ProtocolError::Io(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+					return Err(GencmdInitError::Unauthorized)
+				}
+				Ok(Response::Error(error)) => {
+					return Err(GencmdInitError::Connect(std::io::Error::other(format!(
+						"{:?}",
+						error
+					))))
+				}
+				Err(err) => return Err(GencmdInitError::Connect(std::io::Error::other(err))),
+			}
+		}
+
+		Ok(RemoteBackend {
+			stream,
+			command: String::new(),
+			received: Vec::new(),
+			pending: 0,
+		})
+	}
+
+	pub fn stream(&self) -> &S {
+		&self.stream
+	}
+}
+impl<S: Read + Write + ReadTimeout> RemoteBackend<S> {
+	/// Receives the next response, failing with a [`TimedOut`](std::io::ErrorKind::TimedOut) error once `deadline` has passed.
+	///
+	/// A partially received response is kept, so that receiving can be resumed later.
+	fn receive(&mut self, deadline: Option<Instant>) -> Result<Response, ProtocolError> {
+		loop {
+			if let Some((payload, len)) = split_frame(&self.received)? {
+				let response = Response::decode(payload);
+				self.received.drain(..len);
+				self.pending -= 1;
+
+				return response;
+			}
+
+			let timeout = match deadline {
+				Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+					Some(remaining) if !remaining.is_zero() => Some(remaining),
+					_ => return Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into()),
+				},
+				None => None,
+			};
+			self.stream.set_read_timeout(timeout)?;
+
+			let mut chunk = [0u8; 4096];
+			match self.stream.read(&mut chunk) {
+				Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+				Ok(len) => self.received.extend_from_slice(&chunk[..len]),
+				Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+				Err(err) => return Err(err.into()),
+			}
+		}
+	}
+
+	fn retrieve_response_with(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Option<Duration>,
+	) -> Result<usize, GencmdCmdError> {
+		if self.pending == 0 {
+			return Err(GencmdCmdError::Read);
+		}
+
+		let response = match self.receive(timeout.map(|timeout| Instant::now() + timeout)) {
+			Ok(response) => response,
+			Err(ProtocolError::Io(err))
+				if matches!(
+					err.kind(),
+					std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+				) =>
+			{
+				return Err(GencmdCmdError::Timeout(timeout.unwrap_or_default()));
+			}
+			Err(err) => {
+				log::error!("failed to read remote response: {}", err);
+				return Err(GencmdCmdError::Read);
+			}
+		};
+
+		let response = match response {
+			Response::Ok(response) => response,
+			Response::Error(error) => {
+				return Err(match error {
+					RemoteError::Send => GencmdCmdError::Send,
+					RemoteError::Read => GencmdCmdError::Read,
+					RemoteError::CommandTooLong => GencmdCmdError::CommandTooLong,
+					RemoteError::NotAllowed => GencmdCmdError::Unsupported(self.command.clone()),
+					RemoteError::Unauthorized => GencmdCmdError::Remote("unauthorized".to_string()),
+					RemoteError::Other(message) => GencmdCmdError::Remote(message),
+				})
+			}
+		};
+
+		if response.len() + 1 > buffer.len() {
+			return Err(GencmdCmdError::Read);
+		}
+
+		buffer[..response.len()].copy_from_slice(&response);
+		buffer[response.len()] = 0;
+
+		Ok(response.len())
+	}
+}
+impl<S: Read + Write + ReadTimeout> GencmdBackend for RemoteBackend<S> {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		let command = command.to_str()?;
+
+		// responses which timed out are still on their way
+		while self.pending > 0 {
+			if let Err(err) = self.receive(None) {
+				log::error!("failed to skip late remote response: {}", err);
+				return Err(GencmdCmdError::Send);
+			}
+		}
+
+		if let Err(err) = Request::Command(command.to_string()).write_to(&mut self.stream) {
+			log::error!("failed to send remote command: {}", err);
+			return Err(GencmdCmdError::Send);
+		}
+		self.command = command.to_string();
+		self.pending += 1;
+
+		Ok(())
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		self.retrieve_response_with(buffer, None)
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		self.retrieve_response_with(buffer, Some(timeout))
+	}
+
+	fn supports_timeout(&self) -> bool {
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{net::TcpListener, time::Duration};
+
+	use super::RemoteBackend;
+	use crate::{
+		error::GencmdCmdError,
+		gencmd::unique::GencmdUnique,
+		remote::protocol::{Request, Response},
+	};
+
+	#[test]
+	fn skips_response_which_timed_out() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		std::thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			while let Ok(Some(Request::Command(command))) = Request::read_from(&mut stream) {
+				if command == "slow" {
+					std::thread::sleep(Duration::from_millis(200));
+				}
+				Response::Ok(format!("{}=1", command).into_bytes())
+					.write_to(&mut stream)
+					.unwrap();
+			}
+		});
+
+		let mut gencmd = GencmdUnique::from_backend(RemoteBackend::connect(address, None).unwrap());
+		assert!(matches!(
+			gencmd.send_cmd_raw_timeout("slow", Duration::from_millis(50)),
+			Err(GencmdCmdError::Timeout(_))
+		));
+		assert_eq!(
+			gencmd
+				.send_cmd_raw_timeout("fast", Duration::from_secs(5))
+				.unwrap(),
+			"fast=1"
+		);
+	}
+}
@@ -0,0 +1,576 @@
+//! Simulated devices with dynamic sensor behaviour.
+//!
+//! A [`SimulatedDevice`] models a specific board ([`DeviceProfile`]): its command list, clocks, voltages and revision code,
+//! and a simple thermal model in which the temperature follows the load with an exponential response. Throttling bits are
+//! derived from the temperature and the supply voltage the same way the firmware sets them, including the sticky "occurred" bits.
+//!
+//! The [`SimulatedBackend`] answers commands from such a device, advancing the model either in real time or only when
+//! [`SimulatedDevice::advance`] is called, which makes it possible to test alerting logic deterministically.
+
+use std::{
+	ffi::CStr,
+	time::{Duration, Instant},
+};
+
+use super::{mock::canned_response, GencmdBackend};
+use crate::{error::GencmdCmdError, gencmd::commands::ThrottleFlag};
+
+#[cfg(feature = "serde_models")]
+use serde::{Deserialize, Serialize};
+
+/// Supply voltage under which the under-voltage condition is reported.
+pub const UNDER_VOLTAGE_THRESHOLD: f32 = 4.63;
+/// Nominal supply voltage.
+pub const NOMINAL_SUPPLY_VOLTAGE: f32 = 5.1;
+
+const LEGACY_COMMANDS: &[&str] = &[
+	"vcos",
+	"ap_output_control",
+	"ap_output_post_processing",
+	"vchi_test_init",
+	"vchi_test_exit",
+	"pm_set_policy",
+	"pm_get_status",
+	"pm_show_stats",
+	"pm_start_logging",
+	"pm_stop_logging",
+	"version",
+	"commands",
+	"set_vll_dir",
+	"set_backlight",
+	"set_logging",
+	"get_lcd_info",
+	"arbiter",
+	"cache_flush",
+	"otp_dump",
+	"test_result",
+	"codec_enabled",
+	"get_camera",
+	"get_mem",
+	"measure_clock",
+	"measure_volts",
+	"enable_clock",
+	"scaling_kernel",
+	"scaling_sharpness",
+	"get_hvs_asserts",
+	"get_throttled",
+	"measure_temp",
+	"get_config",
+	"hdmi_ntsc_freqs",
+	"hdmi_adjust_clock",
+	"hdmi_status_show",
+	"hvs_update_fields",
+	"pwm_speedup",
+	"force_audio",
+	"hdmi_stream_channels",
+	"hdmi_channel_map",
+	"display_power",
+	"read_ring_osc",
+	"memtest",
+	"dispmanx_list",
+	"get_rsts",
+	"schmoo",
+	"render_bar",
+	"disk_notify",
+	"inuse_notify",
+	"sus_suspend",
+	"sus_status",
+	"sus_is_enabled",
+	"sus_stop_test_thread",
+	"egl_platform_switch",
+	"mem_validate",
+	"mem_oom",
+	"mem_reloc_stats",
+	"hdmi_cvt",
+	"hdmi_timings",
+	"readmr",
+	"pmicrd",
+	"pmicwr",
+	"bootloader_version",
+	"bootloader_config",
+	"file",
+	"vctest_memmap",
+	"vctest_start",
+	"vctest_stop",
+	"vctest_set",
+	"vctest_get",
+];
+
+const PI5_COMMANDS: &[&str] = &[
+	"commands",
+	"version",
+	"set_logging",
+	"get_rsts",
+	"measure_clock",
+	"measure_volts",
+	"measure_temp",
+	"get_throttled",
+	"get_config",
+	"otp_dump",
+	"get_mem",
+	"hdmi_timings",
+	"display_power",
+	"bootloader_version",
+	"bootloader_config",
+	"mem_reloc_stats",
+	"readmr",
+	"pmic_read_adc",
+	"power_monitor",
+	"vcos",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub enum DeviceProfile {
+	Pi3BPlus,
+	Pi4B,
+	Pi5,
+	Zero2W,
+}
+impl DeviceProfile {
+	pub const ALL: [DeviceProfile; 4] = [
+		DeviceProfile::Pi3BPlus,
+		DeviceProfile::Pi4B,
+		DeviceProfile::Pi5,
+		DeviceProfile::Zero2W,
+	];
+
+	pub const fn spec(self) -> DeviceSpec {
+		match self {
+			DeviceProfile::Pi3BPlus => DeviceSpec {
+				name: "Raspberry Pi 3 Model B Plus Rev 1.3",
+				revision: 0xa020d3,
+				commands: LEGACY_COMMANDS,
+				arm_frequency_max: 1_400_000_000,
+				arm_frequency_min: 600_000_000,
+				arm_frequency_soft_limit: 1_200_000_000,
+				core_frequency: 400_000_000,
+				v3d_frequency: 300_000_000,
+				core_volts: 1.3438,
+				sdram_volts: 1.2500,
+				idle_temperature: 42.0,
+				full_load_temperature: 88.0,
+				thermal_time_constant: Duration::from_secs(90),
+				soft_temperature_limit: Some(60.0),
+				throttle_temperature: 80.0,
+			},
+			DeviceProfile::Pi4B => DeviceSpec {
+				name: "Raspberry Pi 4 Model B Rev 1.4",
+				revision: 0xc03114,
+				commands: LEGACY_COMMANDS,
+				arm_frequency_max: 1_800_000_000,
+				arm_frequency_min: 600_000_000,
+				arm_frequency_soft_limit: 1_800_000_000,
+				core_frequency: 500_000_000,
+				v3d_frequency: 500_000_000,
+				core_volts: 0.8500,
+				sdram_volts: 1.1000,
+				idle_temperature: 45.0,
+				full_load_temperature: 84.0,
+				thermal_time_constant: Duration::from_secs(120),
+				soft_temperature_limit: None,
+				throttle_temperature: 80.0,
+			},
+			DeviceProfile::Pi5 => DeviceSpec {
+				name: "Raspberry Pi 5 Model B Rev 1.0",
+				revision: 0xc04170,
+				commands: PI5_COMMANDS,
+				arm_frequency_max: 2_400_000_000,
+				arm_frequency_min: 1_500_000_000,
+				arm_frequency_soft_limit: 2_400_000_000,
+				core_frequency: 910_000_000,
+				v3d_frequency: 960_000_000,
+				core_volts: 0.7200,
+				sdram_volts: 0.6000,
+				idle_temperature: 50.0,
+				full_load_temperature: 86.0,
+				thermal_time_constant: Duration::from_secs(60),
+				soft_temperature_limit: None,
+				throttle_temperature: 85.0,
+			},
+			DeviceProfile::Zero2W => DeviceSpec {
+				name: "Raspberry Pi Zero 2 W Rev 1.0",
+				revision: 0x902120,
+				commands: LEGACY_COMMANDS,
+				arm_frequency_max: 1_000_000_000,
+				arm_frequency_min: 600_000_000,
+				arm_frequency_soft_limit: 1_000_000_000,
+				core_frequency: 400_000_000,
+				v3d_frequency: 300_000_000,
+				core_volts: 1.2000,
+				sdram_volts: 1.2000,
+				idle_temperature: 40.0,
+				full_load_temperature: 82.0,
+				thermal_time_constant: Duration::from_secs(45),
+				soft_temperature_limit: None,
+				throttle_temperature: 80.0,
+			},
+		}
+	}
+}
+
+/// Static properties of a simulated board.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceSpec {
+	pub name: &'static str,
+	/// The board revision code, as found in the OTP.
+	pub revision: u32,
+	/// Commands reported by the firmware.
+	pub commands: &'static [&'static str],
+	/// Arm clock under load, in Hz.
+	pub arm_frequency_max: u64,
+	/// Arm clock when idle or throttled, in Hz.
+	pub arm_frequency_min: u64,
+	/// Arm clock cap while the soft temperature limit is active, in Hz.
+	pub arm_frequency_soft_limit: u64,
+	pub core_frequency: u64,
+	pub v3d_frequency: u64,
+	pub core_volts: f32,
+	pub sdram_volts: f32,
+	/// Temperature the board settles at without load, in °C.
+	pub idle_temperature: f32,
+	/// Temperature the board settles at under full load without throttling, in °C.
+	pub full_load_temperature: f32,
+	/// Time constant of the exponential temperature response.
+	pub thermal_time_constant: Duration,
+	/// Temperature above which the soft temperature limit is active (`temp_soft_limit`), in °C.
+	pub soft_temperature_limit: Option<f32>,
+	/// Temperature above which the arm is throttled, in °C.
+	pub throttle_temperature: f32,
+}
+
+/// State of a simulated board.
+#[derive(Debug, Clone)]
+pub struct SimulatedDevice {
+	profile: DeviceProfile,
+	spec: DeviceSpec,
+	load: f32,
+	supply_voltage: f32,
+	temperature: f32,
+	occurred: u32,
+}
+impl SimulatedDevice {
+	/// Creates an idle device at its idle temperature with a nominal supply.
+	pub fn new(profile: DeviceProfile) -> Self {
+		let spec = profile.spec();
+
+		let mut device = SimulatedDevice {
+			profile,
+			spec,
+			load: 0.0,
+			supply_voltage: NOMINAL_SUPPLY_VOLTAGE,
+			temperature: spec.idle_temperature,
+			occurred: 0,
+		};
+		device.occurred = device.current_flags();
+
+		device
+	}
+
+	pub fn profile(&self) -> DeviceProfile {
+		self.profile
+	}
+
+	pub fn spec(&self) -> &DeviceSpec {
+		&self.spec
+	}
+
+	pub fn load(&self) -> f32 {
+		self.load
+	}
+
+	/// Sets the cpu load, clamped to `0.0..=1.0`.
+	pub fn set_load(&mut self, load: f32) {
+		self.load = load.clamp(0.0, 1.0);
+	}
+
+	pub fn supply_voltage(&self) -> f32 {
+		self.supply_voltage
+	}
+
+	/// Sets the supply voltage. Conditions it causes are reported immediately.
+	pub fn set_supply_voltage(&mut self, volts: f32) {
+		self.supply_voltage = volts;
+		self.occurred |= self.current_flags();
+	}
+
+	pub fn temperature(&self) -> f32 {
+		self.temperature
+	}
+
+	/// Overrides the current temperature.
+	pub fn set_temperature(&mut self, temperature: f32) {
+		self.temperature = temperature;
+		self.occurred |= self.current_flags();
+	}
+
+	/// Temperature the device converges to under the current load.
+	pub fn target_temperature(&self) -> f32 {
+		self.spec.idle_temperature
+			+ self.load * (self.spec.full_load_temperature - self.spec.idle_temperature)
+	}
+
+	/// Advances the thermal model by `elapsed`.
+	pub fn advance(&mut self, elapsed: Duration) {
+		let start = self.temperature;
+		let target = self.target_temperature();
+		let tau = self
+			.spec
+			.thermal_time_constant
+			.as_secs_f32()
+			.max(f32::EPSILON);
+
+		self.temperature = target + (start - target) * (-elapsed.as_secs_f32() / tau).exp();
+
+		// the temperature moves monotonically within the step, so the hottest point is at one of its ends
+		let hottest = start.max(self.temperature);
+		self.occurred |= self.flags_at(hottest);
+	}
+
+	fn flags_at(&self, temperature: f32) -> u32 {
+		let mut flags = 0;
+
+		if self.supply_voltage < UNDER_VOLTAGE_THRESHOLD {
+			flags |= ThrottleFlag::UnderVoltage.bit() | ThrottleFlag::Throttled.bit();
+		}
+		if self
+			.spec
+			.soft_temperature_limit
+			.is_some_and(|limit| temperature >= limit)
+		{
+			flags |= ThrottleFlag::SoftTemperatureLimit.bit();
+			if self.spec.arm_frequency_soft_limit < self.spec.arm_frequency_max {
+				flags |= ThrottleFlag::FrequencyCapped.bit();
+			}
+		}
+		if temperature >= self.spec.throttle_temperature {
+			flags |= ThrottleFlag::FrequencyCapped.bit() | ThrottleFlag::Throttled.bit();
+		}
+
+		flags
+	}
+
+	fn current_flags(&self) -> u32 {
+		self.flags_at(self.temperature)
+	}
+
+	/// The `get_throttled` bitfield.
+	pub fn throttled(&self) -> u32 {
+		let current = self.current_flags();
+
+		current | ((self.occurred | current) << 16)
+	}
+
+	/// Current arm clock in Hz.
+	pub fn arm_frequency(&self) -> u64 {
+		let current = self.current_flags();
+		if current & ThrottleFlag::Throttled.bit() != 0 || self.load < 0.1 {
+			return self.spec.arm_frequency_min;
+		}
+		if current & ThrottleFlag::SoftTemperatureLimit.bit() != 0 {
+			return self.spec.arm_frequency_soft_limit;
+		}
+
+		self.spec.arm_frequency_max
+	}
+
+	/// Returns the response to `command`.
+	pub fn respond(&self, command: &str) -> String {
+		let mut words = command.split_whitespace();
+		let name = words.next().unwrap_or_default();
+		let args: Vec<&str> = words.collect();
+
+		if !self.spec.commands.contains(&name) {
+			return "error=1 error_msg=\"Command not registered\"".to_string();
+		}
+
+		match (name, args.as_slice()) {
+			("commands", []) => format!("commands=\"{}\"", self.spec.commands.join(", ")),
+			("measure_temp", _) => format!("temp={:.1}'C", self.temperature),
+			("measure_clock", [clock]) => {
+				let (id, frequency) = match *clock {
+					"arm" => (48, self.arm_frequency()),
+					"core" => (1, self.spec.core_frequency),
+					"v3d" => (46, self.spec.v3d_frequency),
+					"uart" => (22, 48_000_000),
+					"emmc" => (50, 250_000_000),
+					_ => (0, 0),
+				};
+				format!("frequency({})={}", id, frequency)
+			}
+			("measure_volts", []) | ("measure_volts", ["core"]) => {
+				format!("volt={:.4}V", self.spec.core_volts)
+			}
+			("measure_volts", ["sdram_c" | "sdram_i" | "sdram_p"]) => {
+				format!("volt={:.4}V", self.spec.sdram_volts)
+			}
+			("get_throttled", _) => format!("throttled=0x{:x}", self.throttled()),
+			("otp_dump", []) => (8..=66)
+				.map(|row| {
+					let value = if row == 30 { self.spec.revision } else { 0 };
+					format!("{:02}:{:08x}", row, value)
+				})
+				.collect::<Vec<_>>()
+				.join("\n"),
+			("measure_clock" | "measure_volts", _) => {
+				"error=2 error_msg=\"Invalid arguments\"".to_string()
+			}
+			_ => {
+				let response = canned_response(command);
+				String::from_utf8_lossy(&response[..response.len() - 1]).into_owned()
+			}
+		}
+	}
+}
+
+/// Backend answering commands from a [`SimulatedDevice`].
+pub struct SimulatedBackend {
+	device: SimulatedDevice,
+	last_update: Option<Instant>,
+	response: Vec<u8>,
+}
+impl SimulatedBackend {
+	/// Creates a backend whose device advances in real time between commands.
+	pub fn new(profile: DeviceProfile) -> Self {
+		SimulatedBackend {
+			device: SimulatedDevice::new(profile),
+			last_update: Some(Instant::now()),
+			response: Vec::new(),
+		}
+	}
+
+	/// Creates a backend whose device only advances when [`SimulatedDevice::advance`] is called.
+	pub fn with_manual_clock(profile: DeviceProfile) -> Self {
+		SimulatedBackend {
+			device: SimulatedDevice::new(profile),
+			last_update: None,
+			response: Vec::new(),
+		}
+	}
+
+	pub fn device(&self) -> &SimulatedDevice {
+		&self.device
+	}
+
+	pub fn device_mut(&mut self) -> &mut SimulatedDevice {
+		&mut self.device
+	}
+}
+impl GencmdBackend for SimulatedBackend {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		let command = command.to_str()?;
+
+		if let Some(ref mut last_update) = self.last_update {
+			let now = Instant::now();
+			self.device.advance(now - *last_update);
+			*last_update = now;
+		}
+
+		log::trace!("simulated {:?} command: {}", self.device.profile, command);
+		self.response = self.device.respond(command).into_bytes();
+
+		Ok(())
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		if self.response.len() + 1 > buffer.len() {
+			return Err(GencmdCmdError::Read);
+		}
+
+		buffer[..self.response.len()].copy_from_slice(&self.response);
+		buffer[self.response.len()] = 0;
+
+		Ok(self.response.len())
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		_timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		// simulated responses are available right away
+		self.retrieve_response(buffer)
+	}
+
+	fn supports_timeout(&self) -> bool {
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::Duration;
+
+	use super::{DeviceProfile, SimulatedBackend, UNDER_VOLTAGE_THRESHOLD};
+	use crate::gencmd::{
+		commands::{CmdGetThrottled, CmdMeasureClockArm, CmdMeasureTemp, ThrottleFlag},
+		unique::GencmdUnique,
+	};
+
+	#[test]
+	fn profiles_answer_commands() {
+		for profile in DeviceProfile::ALL {
+			let mut gencmd =
+				GencmdUnique::from_backend(SimulatedBackend::with_manual_clock(profile));
+			let spec = profile.spec();
+
+			let capabilities = gencmd.load_capabilities().unwrap();
+			assert!(capabilities.supports::<CmdMeasureTemp>());
+			assert_eq!(capabilities.len(), spec.commands.len());
+
+			assert_eq!(
+				gencmd.send_cmd::<CmdMeasureTemp>().unwrap(),
+				spec.idle_temperature
+			);
+			assert_eq!(
+				gencmd.send_cmd::<CmdMeasureClockArm>().unwrap(),
+				spec.arm_frequency_min
+			);
+			assert_eq!(u32::from(gencmd.send_cmd::<CmdGetThrottled>().unwrap()), 0);
+			assert!(gencmd
+				.send_cmd_raw("otp_dump")
+				.unwrap()
+				.contains(&format!("30:{:08x}", spec.revision)));
+		}
+	}
+
+	#[test]
+	fn throttling_follows_temperature() {
+		let mut gencmd = GencmdUnique::from_backend(SimulatedBackend::with_manual_clock(
+			DeviceProfile::Pi3BPlus,
+		));
+
+		gencmd.1.device_mut().set_load(1.0);
+		gencmd.1.device_mut().advance(Duration::from_secs(35));
+		let warm = gencmd.send_cmd::<CmdMeasureTemp>().unwrap();
+		assert!(warm > 55.0 && warm < 65.0, "{}", warm);
+		assert_eq!(
+			gencmd.send_cmd::<CmdMeasureClockArm>().unwrap(),
+			1_400_000_000
+		);
+
+		gencmd.1.device_mut().advance(Duration::from_secs(600));
+		let throttled = gencmd.send_cmd::<CmdGetThrottled>().unwrap();
+		assert!(throttled.is_set(ThrottleFlag::Throttled));
+		assert!(throttled.is_set(ThrottleFlag::SoftTemperatureLimit));
+		assert_eq!(
+			gencmd.send_cmd::<CmdMeasureClockArm>().unwrap(),
+			600_000_000
+		);
+
+		gencmd.1.device_mut().set_load(0.0);
+		gencmd.1.device_mut().advance(Duration::from_secs(600));
+		let cooled = gencmd.send_cmd::<CmdGetThrottled>().unwrap();
+		assert!(!cooled.is_set(ThrottleFlag::Throttled));
+		assert!(cooled.is_set(ThrottleFlag::ThrottledOccurred));
+		assert!(cooled.is_set(ThrottleFlag::SoftTemperatureLimitOccurred));
+
+		gencmd
+			.1
+			.device_mut()
+			.set_supply_voltage(UNDER_VOLTAGE_THRESHOLD - 0.1);
+		let under_voltage = gencmd.send_cmd::<CmdGetThrottled>().unwrap();
+		assert!(under_voltage.is_set(ThrottleFlag::UnderVoltage));
+		assert!(under_voltage.is_set(ThrottleFlag::Throttled));
+	}
+}
@@ -0,0 +1,254 @@
+//! Backend delegating to an external `vcgencmd` executable.
+//!
+//! Each command runs the executable once and its output is handed to the parsers as the response.
+//! This is useful on systems where only the C tool is installed.
+
+use std::{
+	ffi::{CStr, OsString},
+	process::{Child, Command, Output, Stdio},
+	time::{Duration, Instant},
+};
+
+use super::GencmdBackend;
+use crate::error::GencmdCmdError;
+
+/// How often a running command is checked for completion while waiting with a timeout.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Backend running a `vcgencmd` executable per command.
+///
+/// A command which does not finish within the timeout is killed.
+#[derive(Debug)]
+pub struct SubprocessBackend {
+	program: OsString,
+	args: Vec<OsString>,
+	child: Option<Child>,
+}
+impl SubprocessBackend {
+	/// Program used by [`new`](Self::new), resolved using `PATH`.
+	pub const DEFAULT_PROGRAM: &'static str = "vcgencmd";
+
+	pub fn new() -> Self {
+		Self::with_program(Self::DEFAULT_PROGRAM)
+	}
+
+	pub fn with_program(program: impl Into<OsString>) -> Self {
+		SubprocessBackend {
+			program: program.into(),
+			args: Vec::new(),
+			child: None,
+		}
+	}
+
+	/// Adds an argument passed to the program before the command, such as when running it through a wrapper.
+	pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+		self.args.push(arg.into());
+		self
+	}
+
+	pub fn program(&self) -> &OsString {
+		&self.program
+	}
+
+	fn response_from(&self, output: Output, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		let stdout = trim_line_end(&output.stdout);
+		let stderr = trim_line_end(&output.stderr);
+
+		// the tool prints error responses to stderr and exits with a failure status
+		let response = if stdout.starts_with(b"error=") {
+			stdout
+		} else if stderr.starts_with(b"error=") {
+			stderr
+		} else if output.status.success() {
+			stdout
+		} else {
+			log::error!(
+				"{:?} exited with {}: {}",
+				self.program,
+				output.status,
+				String::from_utf8_lossy(stderr)
+			);
+			return Err(GencmdCmdError::Read);
+		};
+
+		if response.len() + 1 > buffer.len() {
+			return Err(GencmdCmdError::Read);
+		}
+
+		buffer[..response.len()].copy_from_slice(response);
+		buffer[response.len()] = 0;
+
+		Ok(response.len())
+	}
+}
+/// Clones the configuration, a running command is not shared.
+impl Clone for SubprocessBackend {
+	fn clone(&self) -> Self {
+		SubprocessBackend {
+			program: self.program.clone(),
+			args: self.args.clone(),
+			child: None,
+		}
+	}
+}
+impl Default for SubprocessBackend {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+impl GencmdBackend for SubprocessBackend {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		let command = command.to_str()?;
+
+		log::debug!("running {:?} {}", self.program, command);
+		let child = Command::new(&self.program)
+			.args(&self.args)
+			.args(command.split_whitespace())
+			.stdin(Stdio::null())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+			.map_err(|err| {
+				log::error!("failed to run {:?}: {}", self.program, err);
+				GencmdCmdError::Send
+			})?;
+		self.child = Some(child);
+
+		Ok(())
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		let child = self.child.take().ok_or(GencmdCmdError::Read)?;
+		let output = child.wait_with_output().map_err(|err| {
+			log::error!("failed to wait for {:?}: {}", self.program, err);
+			GencmdCmdError::Read
+		})?;
+
+		self.response_from(output, buffer)
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		let mut child = self.child.take().ok_or(GencmdCmdError::Read)?;
+		let deadline = Instant::now() + timeout;
+
+		// the output of the tool is small enough to fit into the pipes, so it does not block before exiting
+		loop {
+			match child.try_wait() {
+				Ok(Some(_)) => break,
+				Ok(None) => (),
+				Err(err) => {
+					log::error!("failed to wait for {:?}: {}", self.program, err);
+					return Err(GencmdCmdError::Read);
+				}
+			}
+
+			let now = Instant::now();
+			if now >= deadline {
+				log::warn!("killing {:?} after {:?}", self.program, timeout);
+				if let Err(err) = child.kill().and_then(|_| child.wait()) {
+					log::error!("failed to kill {:?}: {}", self.program, err);
+				}
+
+				return Err(GencmdCmdError::Timeout(timeout));
+			}
+			std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+		}
+
+		let output = child.wait_with_output().map_err(|err| {
+			log::error!("failed to read output of {:?}: {}", self.program, err);
+			GencmdCmdError::Read
+		})?;
+
+		self.response_from(output, buffer)
+	}
+
+	fn supports_timeout(&self) -> bool {
+		true
+	}
+}
+
+fn trim_line_end(mut output: &[u8]) -> &[u8] {
+	while let [rest @ .., b'\n' | b'\r'] = output {
+		output = rest;
+	}
+
+	output
+}
+
+#[cfg(test)]
+mod test {
+	use super::SubprocessBackend;
+	use crate::{
+		error::{GencmdCmdError, GencmdErrorResponse},
+		gencmd::{
+			commands::{CmdGetThrottled, CmdMeasureTemp},
+			unique::GencmdUnique,
+		},
+	};
+
+	/// Behaves like the C tool for a few commands.
+	const FAKE_VCGENCMD: &str = r#"
+		case "$*" in
+			"measure_temp") echo "temp=51.5'C" ;;
+			"get_throttled") echo "throttled=0x50000" ;;
+			"get_config int") printf 'arm_freq=1500\ncore_freq=500\n' ;;
+			"hang") sleep 10 ;;
+			"crash") echo "segfault" >&2; exit 139 ;;
+			*) echo 'error=1 error_msg="Command not registered"' >&2; exit 255 ;;
+		esac
+	"#;
+
+	fn fake_backend() -> SubprocessBackend {
+		SubprocessBackend::with_program("/bin/sh")
+			.arg("-c")
+			.arg(FAKE_VCGENCMD)
+			.arg("vcgencmd")
+	}
+
+	#[test]
+	fn parses_output() {
+		let mut gencmd = GencmdUnique::from_backend(fake_backend());
+
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 51.5);
+		assert_eq!(
+			u32::from(gencmd.send_cmd::<CmdGetThrottled>().unwrap()),
+			0x50000
+		);
+		assert_eq!(
+			gencmd.send_cmd_raw("get_config   int").unwrap(),
+			"arm_freq=1500\ncore_freq=500"
+		);
+	}
+
+	#[test]
+	fn maps_errors() {
+		let mut gencmd = GencmdUnique::from_backend(fake_backend());
+
+		assert!(matches!(
+			gencmd.send_cmd_raw("foo"),
+			Err(GencmdCmdError::ErrorResponse(
+				GencmdErrorResponse::CommandNotRegistered
+			))
+		));
+		assert!(matches!(
+			gencmd.send_cmd_raw("crash"),
+			Err(GencmdCmdError::Read)
+		));
+
+		assert!(matches!(
+			gencmd.send_cmd_raw_timeout("hang", std::time::Duration::from_millis(50)),
+			Err(GencmdCmdError::Timeout(_))
+		));
+
+		let mut missing =
+			GencmdUnique::from_backend(SubprocessBackend::with_program("/nonexistent/vcgencmd"));
+		assert!(matches!(
+			missing.send_cmd_raw("measure_temp"),
+			Err(GencmdCmdError::Send)
+		));
+	}
+}
@@ -0,0 +1,257 @@
+//! Backend answering a few commands from sysfs.
+//!
+//! When no VideoCore interface is available (such as in containers or on other hardware), the kernel still exposes
+//! the temperature, the cpu frequency and, on Raspberry Pi, the throttling state. This backend reads those files and
+//! synthesizes responses in the gencmd format so that the usual commands and parsers can be used.
+//!
+//! Supported commands are `commands`, `measure_temp`, `measure_clock arm` and `get_throttled`.
+
+use std::{
+	ffi::CStr,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use super::GencmdBackend;
+use crate::error::GencmdCmdError;
+
+/// Temperature in millidegrees Celsius, relative to the root.
+pub const THERMAL_PATH: &str = "sys/class/thermal/thermal_zone0/temp";
+/// Current frequency of the first cpu in kHz, relative to the root.
+pub const CPUFREQ_PATH: &str = "sys/devices/system/cpu/cpu0/cpufreq/scaling_cur_freq";
+/// Throttling bitfield in hex without prefix, relative to the root.
+pub const THROTTLED_PATH: &str = "sys/devices/platform/soc/soc:firmware/get_throttled";
+
+const RESPONSE_COMMANDS: &str = "commands=\"commands, measure_temp, measure_clock, get_throttled\"";
+const RESPONSE_NOT_REGISTERED: &str = "error=1 error_msg=\"Command not registered\"";
+const RESPONSE_INVALID_ARGUMENTS: &str = "error=2 error_msg=\"Invalid arguments\"";
+
+/// Backend synthesizing responses from sysfs files.
+#[derive(Debug, Clone)]
+pub struct SysfsBackend {
+	root: PathBuf,
+	response: Option<String>,
+}
+impl SysfsBackend {
+	pub const DEFAULT_ROOT: &'static str = "/";
+
+	pub fn new() -> Self {
+		Self::with_root(Self::DEFAULT_ROOT)
+	}
+
+	/// Creates the backend reading files relative to `root` instead of `/`.
+	pub fn with_root(root: impl Into<PathBuf>) -> Self {
+		SysfsBackend {
+			root: root.into(),
+			response: None,
+		}
+	}
+
+	pub fn root(&self) -> &Path {
+		&self.root
+	}
+
+	fn read(&self, path: &str) -> Result<String, GencmdCmdError> {
+		let path = self.root.join(path);
+
+		match std::fs::read_to_string(&path) {
+			Ok(content) => Ok(content.trim().to_string()),
+			Err(err) => {
+				log::error!("failed to read {}: {}", path.display(), err);
+				Err(GencmdCmdError::Read)
+			}
+		}
+	}
+
+	fn read_number(&self, path: &str, radix: u32) -> Result<u64, GencmdCmdError> {
+		let content = self.read(path)?;
+		let content = content.trim_start_matches("0x");
+
+		u64::from_str_radix(content, radix).map_err(|err| {
+			log::error!("invalid content of {}: {:?}", path, content);
+			GencmdCmdError::from_invalid_format(err)
+		})
+	}
+
+	/// Reads a decimal number which can be negative, e.g. the temperature below freezing.
+	fn read_signed(&self, path: &str) -> Result<i64, GencmdCmdError> {
+		let content = self.read(path)?;
+
+		content.parse().map_err(|err| {
+			log::error!("invalid content of {}: {:?}", path, content);
+			GencmdCmdError::from_invalid_format(err)
+		})
+	}
+
+	fn respond(&self, command: &str) -> Result<String, GencmdCmdError> {
+		let mut words = command.split_whitespace();
+		let name = words.next().unwrap_or_default();
+		let args: Vec<&str> = words.collect();
+
+		let response = match (name, args.as_slice()) {
+			("commands", []) => RESPONSE_COMMANDS.to_string(),
+			("measure_temp", []) => {
+				let millidegrees = self.read_signed(THERMAL_PATH)?;
+				format!("temp={:.1}'C", millidegrees as f64 / 1000.0)
+			}
+			("measure_clock", ["arm"]) => {
+				let khz = self.read_number(CPUFREQ_PATH, 10)?;
+				format!("frequency(48)={}", khz * 1000)
+			}
+			("get_throttled", []) => {
+				let value = self.read_number(THROTTLED_PATH, 16)?;
+				format!("throttled=0x{:x}", value)
+			}
+			("commands" | "measure_temp" | "measure_clock" | "get_throttled", _) => {
+				RESPONSE_INVALID_ARGUMENTS.to_string()
+			}
+			_ => RESPONSE_NOT_REGISTERED.to_string(),
+		};
+
+		Ok(response)
+	}
+}
+impl Default for SysfsBackend {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+impl GencmdBackend for SysfsBackend {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		let command = command.to_str()?;
+		self.response = None;
+
+		log::debug!("answering {:?} from sysfs", command);
+		self.response = Some(self.respond(command)?);
+
+		Ok(())
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		let response = self.response.take().ok_or(GencmdCmdError::Read)?;
+
+		if response.len() + 1 > buffer.len() {
+			return Err(GencmdCmdError::Read);
+		}
+
+		buffer[..response.len()].copy_from_slice(response.as_bytes());
+		buffer[response.len()] = 0;
+
+		Ok(response.len())
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		_timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		// the response is read from sysfs when sending the command
+		self.retrieve_response(buffer)
+	}
+
+	fn supports_timeout(&self) -> bool {
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::path::{Path, PathBuf};
+
+	use super::{SysfsBackend, CPUFREQ_PATH, THERMAL_PATH, THROTTLED_PATH};
+	use crate::{
+		error::{GencmdCmdError, GencmdErrorResponse},
+		gencmd::{
+			commands::{CmdGetThrottled, CmdMeasureClockArm, CmdMeasureTemp},
+			unique::GencmdUnique,
+		},
+	};
+
+	struct TempTree(PathBuf);
+	impl TempTree {
+		fn new(name: &str) -> Self {
+			let root = std::env::temp_dir().join(format!(
+				"videocore-gencmd-sysfs-{}-{}",
+				name,
+				std::process::id()
+			));
+			let _ = std::fs::remove_dir_all(&root);
+
+			TempTree(root)
+		}
+
+		fn write(&self, path: &str, content: &str) {
+			let path = self.0.join(path);
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(path, content).unwrap();
+		}
+
+		fn path(&self) -> &Path {
+			&self.0
+		}
+	}
+	impl Drop for TempTree {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_dir_all(&self.0);
+		}
+	}
+
+	#[test]
+	fn synthesizes_responses() {
+		let tree = TempTree::new("responses");
+		tree.write(THERMAL_PATH, "51540\n");
+		tree.write(CPUFREQ_PATH, "1500000\n");
+		tree.write(THROTTLED_PATH, "50005\n");
+
+		let mut gencmd = GencmdUnique::from_backend(SysfsBackend::with_root(tree.path()));
+		gencmd.load_capabilities().unwrap();
+
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 51.5);
+		assert_eq!(
+			gencmd.send_cmd::<CmdMeasureClockArm>().unwrap(),
+			1_500_000_000
+		);
+		assert_eq!(
+			u32::from(gencmd.send_cmd::<CmdGetThrottled>().unwrap()),
+			0x50005
+		);
+		assert!(matches!(
+			gencmd.send_cmd_raw("measure_clock core"),
+			Err(GencmdCmdError::ErrorResponse(
+				GencmdErrorResponse::InvalidArguments
+			))
+		));
+		assert!(matches!(
+			gencmd.send_cmd_raw("vcos version"),
+			Err(GencmdCmdError::Unsupported(_))
+		));
+	}
+
+	#[test]
+	fn reads_negative_temperature() {
+		let tree = TempTree::new("negative");
+		tree.write(THERMAL_PATH, "-5000\n");
+
+		let mut gencmd = GencmdUnique::from_backend(SysfsBackend::with_root(tree.path()));
+
+		assert_eq!(gencmd.send_cmd_raw("measure_temp").unwrap(), "temp=-5.0'C");
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), -5.0);
+	}
+
+	#[test]
+	fn fails_on_missing_files() {
+		let tree = TempTree::new("missing");
+		tree.write(THERMAL_PATH, "not a number");
+
+		let mut gencmd = GencmdUnique::from_backend(SysfsBackend::with_root(tree.path()));
+
+		assert!(matches!(
+			gencmd.send_cmd::<CmdMeasureTemp>(),
+			Err(GencmdCmdError::InvalidResponseFormat(_))
+		));
+		assert!(matches!(
+			gencmd.send_cmd::<CmdGetThrottled>(),
+			Err(GencmdCmdError::Read)
+		));
+	}
+}
@@ -0,0 +1,547 @@
+//! Recording of command/response pairs into transcripts and their deterministic replay.
+//!
+//! The [`RecordingBackend`] wraps another backend and writes every command along with its response or error to a transcript.
+//! The [`ReplayBackend`] later serves the responses from such a transcript in order, which allows reproducing
+//! sessions captured on a device without the hardware.
+//!
+//! The transcript is a line based text format. Each entry starts with the time of the command in seconds since the unix epoch
+//! and the command itself, followed by the response lines or an error:
+//! ```text
+//! # videocore-gencmd transcript
+//! > 1697630000.123 measure_temp
+//! < temp=51.5'C
+//! > 1697630000.140 vcos log status
+//! < mmal      - error
+//! < vchiq     - warn
+//! > 1697630000.152 measure_temp
+//! ! read
+//! ```
+
+use std::{
+	collections::VecDeque,
+	ffi::CStr,
+	fmt,
+	fs::File,
+	io::{BufWriter, Write},
+	path::Path,
+	str::FromStr,
+	time::{Duration, SystemTime},
+};
+
+use thiserror::Error;
+
+use super::GencmdBackend;
+use crate::error::GencmdCmdError;
+
+#[cfg(feature = "serde_models")]
+use serde::{Deserialize, Serialize};
+
+const HEADER: &str = "# videocore-gencmd transcript";
+
+#[derive(Error, Debug)]
+pub enum TranscriptError {
+	#[error("Failed to access transcript: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("Invalid transcript on line {line}: {message}")]
+	Parse { line: usize, message: String },
+}
+
+/// Backend error as recorded in a transcript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub enum RecordedError {
+	CommandTooLong,
+	Send,
+	Read,
+	/// Any other error, stored as its message.
+	Other(String),
+}
+impl From<&GencmdCmdError> for RecordedError {
+	fn from(error: &GencmdCmdError) -> Self {
+		match error {
+			GencmdCmdError::CommandTooLong => RecordedError::CommandTooLong,
+			GencmdCmdError::Send => RecordedError::Send,
+			GencmdCmdError::Read => RecordedError::Read,
+			error => RecordedError::Other(error.to_string()),
+		}
+	}
+}
+impl From<RecordedError> for GencmdCmdError {
+	fn from(error: RecordedError) -> Self {
+		match error {
+			RecordedError::CommandTooLong => GencmdCmdError::CommandTooLong,
+			RecordedError::Send => GencmdCmdError::Send,
+			RecordedError::Read => GencmdCmdError::Read,
+			RecordedError::Other(message) => GencmdCmdError::InvalidResponseFormat(message.into()),
+		}
+	}
+}
+impl fmt::Display for RecordedError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RecordedError::CommandTooLong => write!(f, "command_too_long"),
+			RecordedError::Send => write!(f, "send"),
+			RecordedError::Read => write!(f, "read"),
+			RecordedError::Other(message) => write!(f, "other {}", message),
+		}
+	}
+}
+impl FromStr for RecordedError {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let error = match s {
+			"command_too_long" => RecordedError::CommandTooLong,
+			"send" => RecordedError::Send,
+			"read" => RecordedError::Read,
+			s => match s.strip_prefix("other ") {
+				Some(message) => RecordedError::Other(message.to_string()),
+				None => return Err(format!("unknown error `{}`", s)),
+			},
+		};
+
+		Ok(error)
+	}
+}
+
+/// A single command and its outcome.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub struct TranscriptEntry {
+	/// Time of the command since the unix epoch, with millisecond precision.
+	pub timestamp: Duration,
+	pub command: String,
+	/// The raw response, including error responses of the firmware, or the error returned by the backend.
+	pub response: Result<String, RecordedError>,
+}
+impl fmt::Display for TranscriptEntry {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(
+			f,
+			"> {}.{:03} {}",
+			self.timestamp.as_secs(),
+			self.timestamp.subsec_millis(),
+			self.command
+		)?;
+
+		match self.response {
+			Ok(ref response) => {
+				for line in response.split('\n') {
+					writeln!(f, "< {}", line)?;
+				}
+			}
+			Err(ref error) => writeln!(f, "! {}", error)?,
+		}
+
+		Ok(())
+	}
+}
+
+/// A sequence of recorded entries.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub struct Transcript {
+	pub entries: Vec<TranscriptEntry>,
+}
+impl Transcript {
+	pub fn load(path: impl AsRef<Path>) -> Result<Self, TranscriptError> {
+		std::fs::read_to_string(path)?.parse()
+	}
+
+	pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TranscriptError> {
+		std::fs::write(path, self.to_string())?;
+
+		Ok(())
+	}
+
+	fn parse_command(line: &str) -> Result<(Duration, String), String> {
+		let (timestamp, command) = line
+			.split_once(' ')
+			.ok_or_else(|| "missing command".to_string())?;
+		let (seconds, millis) = timestamp.split_once('.').unwrap_or((timestamp, "0"));
+
+		let seconds: u64 = seconds
+			.parse()
+			.map_err(|err| format!("invalid timestamp: {}", err))?;
+		let millis: u64 = millis
+			.parse()
+			.map_err(|err| format!("invalid timestamp: {}", err))?;
+
+		Ok((
+			Duration::from_secs(seconds) + Duration::from_millis(millis),
+			command.to_string(),
+		))
+	}
+}
+impl FromStr for Transcript {
+	type Err = TranscriptError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut entries = Vec::<TranscriptEntry>::new();
+		// response lines of the last entry
+		let mut lines: Option<Vec<&str>> = None;
+
+		for (index, line) in s.lines().enumerate() {
+			let error = |message: String| TranscriptError::Parse {
+				line: index + 1,
+				message,
+			};
+
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			let (marker, rest) = line.split_at(1);
+			let rest = rest.strip_prefix(' ').unwrap_or(rest);
+			match marker {
+				">" => {
+					if let Some(lines) = lines.take() {
+						entries.last_mut().unwrap().response = Ok(lines.join("\n"));
+					}
+
+					let (timestamp, command) = Self::parse_command(rest).map_err(error)?;
+					entries.push(TranscriptEntry {
+						timestamp,
+						command,
+						response: Err(RecordedError::Read),
+					});
+					lines = Some(Vec::new());
+				}
+				"<" => match lines {
+					Some(ref mut lines) => lines.push(rest),
+					None => return Err(error("response without a command".to_string())),
+				},
+				"!" => match lines.take() {
+					Some(response) if response.is_empty() => {
+						entries.last_mut().unwrap().response = Err(rest.parse().map_err(error)?);
+					}
+					_ => return Err(error("unexpected error line".to_string())),
+				},
+				_ => return Err(error(format!("unknown line marker `{}`", marker))),
+			}
+		}
+
+		match lines {
+			Some(lines) if !lines.is_empty() => {
+				entries.last_mut().unwrap().response = Ok(lines.join("\n"));
+			}
+			Some(_) => {
+				return Err(TranscriptError::Parse {
+					line: s.lines().count(),
+					message: "last command has no response".to_string(),
+				})
+			}
+			None => (),
+		}
+
+		Ok(Transcript { entries })
+	}
+}
+impl fmt::Display for Transcript {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "{}", HEADER)?;
+		for entry in self.entries.iter() {
+			write!(f, "{}", entry)?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Backend wrapper writing every command and its outcome to a transcript.
+///
+/// Entries are written and flushed as soon as the outcome is known. Failures to write the transcript are logged
+/// and do not affect the commands.
+///
+/// The shared capabilities of the wrapped backend are not exposed, as loading them would record a `commands` exchange
+/// which a [`ReplayBackend`] does not expect.
+pub struct RecordingBackend<B: GencmdBackend, W: Write = BufWriter<File>> {
+	backend: B,
+	writer: W,
+	pending: Option<(Duration, String)>,
+}
+impl<B: GencmdBackend> RecordingBackend<B> {
+	/// Records into a newly created transcript file at `path`.
+	pub fn create(backend: B, path: impl AsRef<Path>) -> Result<Self, TranscriptError> {
+		let mut writer = BufWriter::new(File::create(path)?);
+		writeln!(writer, "{}", HEADER)?;
+
+		Ok(Self::new(backend, writer))
+	}
+}
+impl<B: GencmdBackend, W: Write> RecordingBackend<B, W> {
+	/// Records into `writer`. No header is written.
+	pub fn new(backend: B, writer: W) -> Self {
+		RecordingBackend {
+			backend,
+			writer,
+			pending: None,
+		}
+	}
+
+	pub fn backend(&self) -> &B {
+		&self.backend
+	}
+
+	pub fn into_inner(self) -> (B, W) {
+		(self.backend, self.writer)
+	}
+
+	fn record_response(&mut self, buffer: &[u8], result: &Result<usize, GencmdCmdError>) {
+		match *result {
+			Ok(len) => self.record(Ok(String::from_utf8_lossy(&buffer[..len]).into_owned())),
+			Err(ref err) => self.record(Err(err.into())),
+		}
+	}
+
+	fn record(&mut self, response: Result<String, RecordedError>) {
+		let (timestamp, command) = match self.pending.take() {
+			Some(pending) => pending,
+			None => return,
+		};
+		let entry = TranscriptEntry {
+			timestamp,
+			command,
+			response,
+		};
+
+		if let Err(err) = write!(self.writer, "{}", entry).and_then(|_| self.writer.flush()) {
+			log::error!("failed to write transcript: {}", err);
+		}
+	}
+}
+impl<B: GencmdBackend, W: Write> GencmdBackend for RecordingBackend<B, W> {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		let timestamp = SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap_or_default();
+		self.pending = Some((timestamp, command.to_string_lossy().into_owned()));
+
+		let result = self.backend.send_command(command);
+		if let Err(ref err) = result {
+			self.record(Err(err.into()));
+		}
+
+		result
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		let result = self.backend.retrieve_response(buffer);
+		self.record_response(buffer, &result);
+
+		result
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		let result = self.backend.retrieve_response_timeout(buffer, timeout);
+		self.record_response(buffer, &result);
+
+		result
+	}
+
+	fn supports_timeout(&self) -> bool {
+		self.backend.supports_timeout()
+	}
+}
+
+/// Backend serving the responses of a transcript in order.
+///
+/// Commands must be sent in the same order as they were recorded, otherwise [`GencmdCmdError::UnexpectedCommand`] is returned
+/// and the transcript does not advance.
+#[derive(Debug, Clone)]
+pub struct ReplayBackend {
+	entries: VecDeque<TranscriptEntry>,
+	response: Option<Result<String, RecordedError>>,
+}
+impl ReplayBackend {
+	pub fn new(transcript: Transcript) -> Self {
+		ReplayBackend {
+			entries: transcript.entries.into(),
+			response: None,
+		}
+	}
+
+	pub fn load(path: impl AsRef<Path>) -> Result<Self, TranscriptError> {
+		Ok(Self::new(Transcript::load(path)?))
+	}
+
+	/// Number of entries not replayed yet.
+	pub fn remaining(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Returns true if all entries have been replayed.
+	pub fn is_finished(&self) -> bool {
+		self.entries.is_empty()
+	}
+}
+impl GencmdBackend for ReplayBackend {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		let command = command.to_string_lossy();
+		self.response = None;
+
+		match self.entries.front() {
+			Some(entry) if entry.command == command => (),
+			Some(entry) => {
+				log::error!(
+					"replay expected command {:?} but got {:?}",
+					entry.command,
+					command
+				);
+				return Err(GencmdCmdError::UnexpectedCommand(command.into_owned()));
+			}
+			None => {
+				log::error!("replay transcript exhausted, got {:?}", command);
+				return Err(GencmdCmdError::UnexpectedCommand(command.into_owned()));
+			}
+		}
+
+		let entry = self.entries.pop_front().unwrap();
+		match entry.response {
+			Err(RecordedError::Read) | Ok(_) => {
+				self.response = Some(entry.response);
+				Ok(())
+			}
+			Err(error) => Err(error.into()),
+		}
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		let response = self.response.take().ok_or(GencmdCmdError::Read)??;
+
+		if response.len() + 1 > buffer.len() {
+			return Err(GencmdCmdError::Read);
+		}
+
+		buffer[..response.len()].copy_from_slice(response.as_bytes());
+		buffer[response.len()] = 0;
+
+		Ok(response.len())
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		_timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		// the recorded response is available right away
+		self.retrieve_response(buffer)
+	}
+
+	fn supports_timeout(&self) -> bool {
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{ffi::CStr, sync::OnceLock, time::Duration};
+
+	use super::{RecordedError, RecordingBackend, ReplayBackend, Transcript, TranscriptEntry};
+	use crate::{
+		backend::{mock::MockBackend, GencmdBackend},
+		error::GencmdCmdError,
+		gencmd::{
+			capabilities::Capabilities,
+			commands::{CmdMeasureTemp, CmdVcosLogStatus},
+			unique::GencmdUnique,
+		},
+	};
+
+	/// Mock backend with shared capabilities, like the global instance.
+	struct SharedCapabilitiesBackend(MockBackend, OnceLock<Capabilities>);
+	impl GencmdBackend for SharedCapabilitiesBackend {
+		unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+			self.0.send_command(command)
+		}
+
+		fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+			self.0.retrieve_response(buffer)
+		}
+
+		fn shared_capabilities(&self) -> Option<&OnceLock<Capabilities>> {
+			Some(&self.1)
+		}
+	}
+
+	#[test]
+	fn transcript_round_trips() {
+		let transcript = Transcript {
+			entries: vec![
+				TranscriptEntry {
+					timestamp: Duration::from_millis(1_697_630_000_123),
+					command: "vcos log status".to_string(),
+					response: Ok("mmal - error\nvchiq - warn\n".to_string()),
+				},
+				TranscriptEntry {
+					timestamp: Duration::from_millis(1_697_630_000_200),
+					command: "measure_temp".to_string(),
+					response: Ok(String::new()),
+				},
+				TranscriptEntry {
+					timestamp: Duration::from_millis(1_697_630_000_300),
+					command: "measure_temp".to_string(),
+					response: Err(RecordedError::Other("broken pipe".to_string())),
+				},
+			],
+		};
+
+		let text = transcript.to_string();
+		assert_eq!(text.parse::<Transcript>().unwrap(), transcript);
+
+		assert!("< temp=1.0'C\n".parse::<Transcript>().is_err());
+		assert!("> 1.0 measure_temp\n".parse::<Transcript>().is_err());
+	}
+
+	#[test]
+	fn replays_recorded_session() {
+		let mut recording =
+			GencmdUnique::from_backend(RecordingBackend::new(MockBackend::new(), Vec::new()));
+		let temperature = recording.send_cmd::<CmdMeasureTemp>().unwrap();
+		let categories = recording.send_cmd::<CmdVcosLogStatus>().unwrap().len();
+		assert!(recording.send_cmd_raw("foo").is_err());
+
+		let (_, transcript) = recording.1.into_inner();
+		let transcript: Transcript = String::from_utf8(transcript).unwrap().parse().unwrap();
+		assert_eq!(transcript.entries.len(), 3);
+
+		let mut replay = GencmdUnique::from_backend(ReplayBackend::new(transcript));
+		assert!(matches!(
+			replay.send_cmd_raw("vcos heap"),
+			Err(GencmdCmdError::UnexpectedCommand(_))
+		));
+		assert_eq!(replay.send_cmd::<CmdMeasureTemp>().unwrap(), temperature);
+		assert_eq!(
+			replay.send_cmd::<CmdVcosLogStatus>().unwrap().len(),
+			categories
+		);
+		assert!(matches!(
+			replay.send_cmd_raw("foo"),
+			Err(GencmdCmdError::ErrorResponse(_))
+		));
+		assert!(replay.1.is_finished());
+		assert!(matches!(
+			replay.send_cmd::<CmdMeasureTemp>(),
+			Err(GencmdCmdError::UnexpectedCommand(_))
+		));
+	}
+
+	#[test]
+	fn replays_session_recorded_over_shared_capabilities() {
+		let backend = SharedCapabilitiesBackend(MockBackend::new(), OnceLock::new());
+		let mut recording = GencmdUnique::from_backend(RecordingBackend::new(backend, Vec::new()));
+		let temperature = recording.send_cmd::<CmdMeasureTemp>().unwrap();
+
+		let (_, transcript) = recording.1.into_inner();
+		let transcript: Transcript = String::from_utf8(transcript).unwrap().parse().unwrap();
+		assert_eq!(transcript.entries.len(), 1);
+
+		let mut replay = GencmdUnique::from_backend(ReplayBackend::new(transcript));
+		assert_eq!(replay.send_cmd::<CmdMeasureTemp>().unwrap(), temperature);
+		assert!(replay.1.is_finished());
+	}
+}
@@ -0,0 +1,613 @@
+//! Backend implementing the VCHIQ userspace protocol directly over the `/dev/vchiq` ioctls.
+//!
+//! This replaces `libvchiq_arm`, `libvcos` and the gencmd client from `libbcm_host`. The gencmd service (`GCMD`) is opened
+//! as a vchi service, so that the kernel queues incoming messages for us to dequeue instead of invoking a callback.
+//!
+//! Commands are sent as null-terminated strings. Responses are a 32-bit little-endian status word followed by a null-terminated string.
+
+use std::{
+	ffi::CStr,
+	fs::{File, OpenOptions},
+	os::unix::io::AsRawFd,
+	path::Path,
+	time::{Duration, Instant},
+};
+
+use super::GencmdBackend;
+use crate::{
+	error::{GencmdCmdError, GencmdDeinitError, GencmdInitError},
+	ffi,
+};
+
+/// `MAKE_FOURCC("GCMD")`
+pub const GENCMD_FOURCC: u32 = u32::from_be_bytes(*b"GCMD");
+/// `VC_GENCMD_VER`
+pub const GENCMD_VERSION: i16 = 1;
+
+/// Size of the status word preceding the response string.
+const STATUS_SIZE: usize = std::mem::size_of::<i32>();
+
+/// The ioctl layer of vchiq.
+///
+/// Abstracted so that the backend can be tested against a fake kernel.
+pub trait VchiqDevice {
+	/// `VCHIQ_IOC_CONNECT`
+	fn connect(&mut self) -> std::io::Result<()>;
+	/// `VCHIQ_IOC_CREATE_SERVICE` with `is_open` and `is_vchi` set, returns the service handle.
+	fn open_service(&mut self, fourcc: u32, version: i16, version_min: i16)
+		-> std::io::Result<u32>;
+	/// `VCHIQ_IOC_CLOSE_SERVICE`
+	fn close_service(&mut self, handle: u32) -> std::io::Result<()>;
+	/// `VCHIQ_IOC_USE_SERVICE`
+	fn use_service(&mut self, handle: u32) -> std::io::Result<()>;
+	/// `VCHIQ_IOC_RELEASE_SERVICE`
+	fn release_service(&mut self, handle: u32) -> std::io::Result<()>;
+	/// `VCHIQ_IOC_QUEUE_MESSAGE` with a single element.
+	fn queue_message(&mut self, handle: u32, data: &[u8]) -> std::io::Result<()>;
+	/// Blocking `VCHIQ_IOC_DEQUEUE_MESSAGE`, returns the size of the message.
+	fn dequeue_message(&mut self, handle: u32, buffer: &mut [u8]) -> std::io::Result<usize>;
+	/// Non-blocking `VCHIQ_IOC_DEQUEUE_MESSAGE`, returns `None` if no message is queued.
+	fn try_dequeue_message(
+		&mut self,
+		handle: u32,
+		buffer: &mut [u8],
+	) -> std::io::Result<Option<usize>>;
+}
+
+mod sys {
+	use std::os::raw::{c_int, c_short, c_uint, c_void};
+
+	pub const IOC_MAGIC: u32 = 0xc4;
+
+	const fn io(nr: u32) -> u32 {
+		(IOC_MAGIC << 8) | nr
+	}
+	const fn iow<T>(nr: u32) -> u32 {
+		(1 << 30) | ((std::mem::size_of::<T>() as u32) << 16) | io(nr)
+	}
+	const fn iowr<T>(nr: u32) -> u32 {
+		(3 << 30) | ((std::mem::size_of::<T>() as u32) << 16) | io(nr)
+	}
+
+	pub const IOC_CONNECT: u32 = io(0);
+	pub const IOC_CREATE_SERVICE: u32 = iowr::<CreateService>(2);
+	pub const IOC_QUEUE_MESSAGE: u32 = iow::<QueueMessage>(4);
+	pub const IOC_DEQUEUE_MESSAGE: u32 = iowr::<DequeueMessage>(8);
+	pub const IOC_CLOSE_SERVICE: u32 = io(11);
+	pub const IOC_USE_SERVICE: u32 = io(12);
+	pub const IOC_RELEASE_SERVICE: u32 = io(13);
+
+	/// `VCHIQ_SERVICE_PARAMS_T`
+	#[repr(C)]
+	pub struct ServiceParams {
+		pub fourcc: c_int,
+		pub callback: Option<unsafe extern "C" fn()>,
+		pub userdata: *mut c_void,
+		pub version: c_short,
+		pub version_min: c_short,
+	}
+
+	/// `VCHIQ_CREATE_SERVICE_T`
+	#[repr(C)]
+	pub struct CreateService {
+		pub params: ServiceParams,
+		pub is_open: c_int,
+		pub is_vchi: c_int,
+		pub handle: c_uint,
+	}
+
+	/// `VCHIQ_ELEMENT_T`
+	#[repr(C)]
+	pub struct Element {
+		pub data: *const c_void,
+		pub size: c_uint,
+	}
+
+	/// `VCHIQ_QUEUE_MESSAGE_T`
+	#[repr(C)]
+	pub struct QueueMessage {
+		pub handle: c_uint,
+		pub count: c_uint,
+		pub elements: *const Element,
+	}
+
+	/// `VCHIQ_DEQUEUE_MESSAGE_T`
+	#[repr(C)]
+	pub struct DequeueMessage {
+		pub handle: c_uint,
+		pub blocking: c_int,
+		pub bufsize: c_uint,
+		pub buf: *mut c_void,
+	}
+}
+
+/// The `/dev/vchiq` character device.
+pub struct VchiqCharDevice {
+	file: File,
+}
+impl VchiqCharDevice {
+	pub const DEFAULT_PATH: &'static str = "/dev/vchiq";
+
+	pub fn open() -> Result<Self, GencmdInitError> {
+		Self::open_path(Self::DEFAULT_PATH)
+	}
+
+	pub fn open_path(path: impl AsRef<Path>) -> Result<Self, GencmdInitError> {
+		let file = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.open(path.as_ref())
+			.map_err(GencmdInitError::DeviceOpen)?;
+		log::debug!("opened vchiq device {}", path.as_ref().display());
+
+		Ok(VchiqCharDevice { file })
+	}
+
+	/// ### Safety
+	/// `arg` must be valid for the `request`.
+	unsafe fn ioctl(
+		&mut self,
+		request: u32,
+		arg: *mut std::os::raw::c_void,
+	) -> std::io::Result<i32> {
+		let result = libc::ioctl(self.file.as_raw_fd(), request as _, arg);
+		if result < 0 {
+			return Err(std::io::Error::last_os_error());
+		}
+
+		Ok(result)
+	}
+}
+impl VchiqDevice for VchiqCharDevice {
+	fn connect(&mut self) -> std::io::Result<()> {
+		unsafe { self.ioctl(sys::IOC_CONNECT, std::ptr::null_mut()) }.map(|_| ())
+	}
+
+	fn open_service(
+		&mut self,
+		fourcc: u32,
+		version: i16,
+		version_min: i16,
+	) -> std::io::Result<u32> {
+		let mut args = sys::CreateService {
+			params: sys::ServiceParams {
+				fourcc: fourcc as _,
+				// the kernel installs its own callback for vchi services
+				callback: None,
+				userdata: std::ptr::null_mut(),
+				version,
+				version_min,
+			},
+			is_open: 1,
+			is_vchi: 1,
+			handle: !0,
+		};
+
+		unsafe { self.ioctl(sys::IOC_CREATE_SERVICE, &mut args as *mut _ as *mut _) }?;
+
+		Ok(args.handle)
+	}
+
+	fn close_service(&mut self, handle: u32) -> std::io::Result<()> {
+		unsafe { self.ioctl(sys::IOC_CLOSE_SERVICE, handle as usize as *mut _) }.map(|_| ())
+	}
+
+	fn use_service(&mut self, handle: u32) -> std::io::Result<()> {
+		unsafe { self.ioctl(sys::IOC_USE_SERVICE, handle as usize as *mut _) }.map(|_| ())
+	}
+
+	fn release_service(&mut self, handle: u32) -> std::io::Result<()> {
+		unsafe { self.ioctl(sys::IOC_RELEASE_SERVICE, handle as usize as *mut _) }.map(|_| ())
+	}
+
+	fn queue_message(&mut self, handle: u32, data: &[u8]) -> std::io::Result<()> {
+		let element = sys::Element {
+			data: data.as_ptr() as *const _,
+			size: data.len() as _,
+		};
+		let mut args = sys::QueueMessage {
+			handle,
+			count: 1,
+			elements: &element,
+		};
+
+		unsafe { self.ioctl(sys::IOC_QUEUE_MESSAGE, &mut args as *mut _ as *mut _) }.map(|_| ())
+	}
+
+	fn dequeue_message(&mut self, handle: u32, buffer: &mut [u8]) -> std::io::Result<usize> {
+		self.dequeue(handle, buffer, true)
+	}
+
+	fn try_dequeue_message(
+		&mut self,
+		handle: u32,
+		buffer: &mut [u8],
+	) -> std::io::Result<Option<usize>> {
+		match self.dequeue(handle, buffer, false) {
+			Ok(size) => Ok(Some(size)),
+			Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+			Err(err) => Err(err),
+		}
+	}
+}
+impl VchiqCharDevice {
+	fn dequeue(
+		&mut self,
+		handle: u32,
+		buffer: &mut [u8],
+		blocking: bool,
+	) -> std::io::Result<usize> {
+		let mut args = sys::DequeueMessage {
+			handle,
+			blocking: blocking as _,
+			bufsize: buffer.len() as _,
+			buf: buffer.as_mut_ptr() as *mut _,
+		};
+
+		unsafe { self.ioctl(sys::IOC_DEQUEUE_MESSAGE, &mut args as *mut _ as *mut _) }
+			.map(|size| size as usize)
+	}
+}
+
+/// How often the queue is checked for the response while waiting with a timeout.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Backend talking to the gencmd service over vchiq without any C libraries.
+///
+/// A response which timed out is skipped once it arrives, before the response to the next command is read.
+pub struct VchiqBackend<D: VchiqDevice = VchiqCharDevice> {
+	device: D,
+	handle: Option<u32>,
+	message: Vec<u8>,
+	/// Number of responses which timed out and have not been dequeued yet.
+	late: usize,
+}
+impl VchiqBackend {
+	/// Opens the backend on [`VchiqCharDevice::DEFAULT_PATH`].
+	pub fn new() -> Result<Self, GencmdInitError> {
+		Self::with_device(VchiqCharDevice::open()?)
+	}
+}
+impl<D: VchiqDevice> VchiqBackend<D> {
+	/// Connects `device` and opens the gencmd service.
+	pub fn with_device(mut device: D) -> Result<Self, GencmdInitError> {
+		device.connect().map_err(|err| {
+			log::error!("vchiq connect failed: {}", err);
+			GencmdInitError::VchiConnect
+		})?;
+
+		let handle = device
+			.open_service(GENCMD_FOURCC, GENCMD_VERSION, GENCMD_VERSION)
+			.map_err(GencmdInitError::ServiceOpen)?;
+		log::debug!("opened gencmd service with handle {}", handle);
+
+		Ok(VchiqBackend {
+			device,
+			handle: Some(handle),
+			message: vec![0; STATUS_SIZE + ffi::GENCMDSERVICE_MSGFIFO_SIZE as usize],
+			late: 0,
+		})
+	}
+
+	pub fn device(&self) -> &D {
+		&self.device
+	}
+
+	pub fn is_closed(&self) -> bool {
+		self.handle.is_none()
+	}
+
+	/// Closes the gencmd service.
+	///
+	/// It's okay to call this multiple times. After closing, sending commands panics.
+	pub fn close(&mut self) -> Result<(), GencmdDeinitError> {
+		let handle = match self.handle.take() {
+			None => return Ok(()),
+			Some(handle) => handle,
+		};
+
+		self.device.close_service(handle).map_err(|err| {
+			log::error!("vchiq close service failed: {}", err);
+			GencmdDeinitError::VchiDisconnect
+		})
+	}
+
+	fn handle(&self) -> u32 {
+		self.handle.expect("The gencmd service has been closed")
+	}
+
+	/// Runs `f` between use and release of the service, as the C client does to keep the videocore awake.
+	fn with_service_used<R>(
+		&mut self,
+		error: GencmdCmdError,
+		f: impl FnOnce(&mut Self, u32) -> std::io::Result<R>,
+	) -> Result<R, GencmdCmdError> {
+		let handle = self.handle();
+
+		if let Err(err) = self.device.use_service(handle) {
+			log::error!("vchiq use service failed: {}", err);
+			return Err(error);
+		}
+		let result = f(self, handle);
+		if let Err(err) = self.device.release_service(handle) {
+			log::warn!("vchiq release service failed: {}", err);
+		}
+
+		result.map_err(|err| {
+			log::error!("vchiq message transfer failed: {}", err);
+			error
+		})
+	}
+
+	/// Dequeues the response to the last command into `self.message`, returning its size or `None` once `deadline` has passed.
+	fn dequeue_response(
+		&mut self,
+		deadline: Option<Instant>,
+	) -> Result<Option<usize>, GencmdCmdError> {
+		self.with_service_used(GencmdCmdError::Read, |this, handle| loop {
+			let size = match deadline {
+				Some(deadline) => {
+					match this.device.try_dequeue_message(handle, &mut this.message)? {
+						Some(size) => size,
+						None => {
+							let now = Instant::now();
+							if now >= deadline {
+								return Ok(None);
+							}
+
+							std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+							continue;
+						}
+					}
+				}
+				None => this.device.dequeue_message(handle, &mut this.message)?,
+			};
+
+			if this.late == 0 {
+				return Ok(Some(size));
+			}
+			log::debug!("skipping late vchiq response");
+			this.late -= 1;
+		})
+	}
+
+	fn retrieve_response_with(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Option<Duration>,
+	) -> Result<usize, GencmdCmdError> {
+		let size = match self.dequeue_response(timeout.map(|timeout| Instant::now() + timeout))? {
+			Some(size) => size,
+			None => {
+				self.late += 1;
+				return Err(GencmdCmdError::Timeout(timeout.unwrap_or_default()));
+			}
+		};
+
+		if size < STATUS_SIZE || size > self.message.len() {
+			log::error!("vchiq response has invalid size {}", size);
+			return Err(GencmdCmdError::Read);
+		}
+		let status = i32::from_le_bytes(self.message[..STATUS_SIZE].try_into().unwrap());
+		log::trace!("vchiq response status: {}", status);
+
+		let string = &self.message[STATUS_SIZE..size];
+		let len = string.iter().position(|&b| b == 0).unwrap_or(string.len());
+		if len + 1 > buffer.len() {
+			return Err(GencmdCmdError::Read);
+		}
+
+		buffer[..len].copy_from_slice(&string[..len]);
+		buffer[len] = 0;
+
+		Ok(len)
+	}
+}
+impl<D: VchiqDevice> GencmdBackend for VchiqBackend<D> {
+	/// ### Panic
+	/// Will panic if the service has been closed.
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		if command.to_bytes().len() + 1 > ffi::GENCMD_MAX_LENGTH as usize {
+			return Err(GencmdCmdError::CommandTooLong);
+		}
+
+		log::debug!("sending vchiq command: {:?}", command);
+		self.with_service_used(GencmdCmdError::Send, |this, handle| {
+			this.device
+				.queue_message(handle, command.to_bytes_with_nul())
+		})
+	}
+
+	/// ### Panic
+	/// Will panic if the service has been closed.
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		self.retrieve_response_with(buffer, None)
+	}
+
+	/// ### Panic
+	/// Will panic if the service has been closed.
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		self.retrieve_response_with(buffer, Some(timeout))
+	}
+
+	fn supports_timeout(&self) -> bool {
+		true
+	}
+}
+impl<D: VchiqDevice> Drop for VchiqBackend<D> {
+	fn drop(&mut self) {
+		if let Err(err) = self.close() {
+			log::error!("failed to close vchiq backend inside drop: {}", err);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{collections::VecDeque, time::Duration};
+
+	use super::{VchiqBackend, VchiqDevice, GENCMD_FOURCC};
+	use crate::{
+		error::GencmdCmdError,
+		gencmd::{commands::CmdMeasureTemp, unique::GencmdUnique},
+	};
+
+	/// A fake kernel which answers gencmd messages like the firmware would.
+	#[derive(Default)]
+	struct FakeKernel {
+		connected: bool,
+		open: Option<u32>,
+		use_count: i32,
+		queue: VecDeque<Vec<u8>>,
+		/// Response to `slow`, only queued along with the next message.
+		delayed: Option<Vec<u8>>,
+		received: Vec<String>,
+	}
+	impl FakeKernel {
+		fn check(&self, handle: u32) -> std::io::Result<()> {
+			if self.open != Some(handle) {
+				return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+			}
+
+			Ok(())
+		}
+	}
+	impl VchiqDevice for FakeKernel {
+		fn connect(&mut self) -> std::io::Result<()> {
+			self.connected = true;
+			Ok(())
+		}
+
+		fn open_service(
+			&mut self,
+			fourcc: u32,
+			version: i16,
+			version_min: i16,
+		) -> std::io::Result<u32> {
+			assert!(self.connected);
+			assert_eq!(fourcc, GENCMD_FOURCC);
+			assert_eq!((version, version_min), (1, 1));
+
+			self.open = Some(0x1000);
+			Ok(0x1000)
+		}
+
+		fn close_service(&mut self, handle: u32) -> std::io::Result<()> {
+			self.check(handle)?;
+			self.open = None;
+			Ok(())
+		}
+
+		fn use_service(&mut self, handle: u32) -> std::io::Result<()> {
+			self.check(handle)?;
+			self.use_count += 1;
+			Ok(())
+		}
+
+		fn release_service(&mut self, handle: u32) -> std::io::Result<()> {
+			self.check(handle)?;
+			self.use_count -= 1;
+			Ok(())
+		}
+
+		fn queue_message(&mut self, handle: u32, data: &[u8]) -> std::io::Result<()> {
+			self.check(handle)?;
+			assert_eq!(self.use_count, 1);
+
+			let command = std::str::from_utf8(data.strip_suffix(b"\0").unwrap()).unwrap();
+			let response: &[u8] = match command {
+				"measure_temp" => b"temp=38.9'C\0",
+				"slow" => b"slow=1\0",
+				_ => b"error=1 error_msg=\"command not registered\"\0",
+			};
+
+			let mut message = 0i32.to_le_bytes().to_vec();
+			message.extend_from_slice(response);
+			self.queue.extend(self.delayed.take());
+			match command {
+				"slow" => self.delayed = Some(message),
+				_ => self.queue.push_back(message),
+			}
+			self.received.push(command.to_string());
+
+			Ok(())
+		}
+
+		fn dequeue_message(&mut self, handle: u32, buffer: &mut [u8]) -> std::io::Result<usize> {
+			self.check(handle)?;
+
+			let message = self
+				.queue
+				.pop_front()
+				.ok_or_else(|| std::io::Error::from_raw_os_error(libc::EWOULDBLOCK))?;
+			buffer[..message.len()].copy_from_slice(&message);
+
+			Ok(message.len())
+		}
+
+		fn try_dequeue_message(
+			&mut self,
+			handle: u32,
+			buffer: &mut [u8],
+		) -> std::io::Result<Option<usize>> {
+			match self.dequeue_message(handle, buffer) {
+				Ok(size) => Ok(Some(size)),
+				Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+				Err(err) => Err(err),
+			}
+		}
+	}
+
+	#[test]
+	fn exchanges_messages_with_gencmd_service() {
+		let backend = VchiqBackend::with_device(FakeKernel::default()).unwrap();
+		let mut gencmd = GencmdUnique::from_backend(backend);
+
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 38.9);
+		assert!(matches!(
+			gencmd.send_cmd_raw("foo"),
+			Err(GencmdCmdError::ErrorResponse(_))
+		));
+
+		assert_eq!(gencmd.1.device().received, vec!["measure_temp", "foo"]);
+		assert_eq!(gencmd.1.device().use_count, 0);
+	}
+
+	#[test]
+	fn skips_response_which_timed_out() {
+		let backend = VchiqBackend::with_device(FakeKernel::default()).unwrap();
+		let mut gencmd = GencmdUnique::from_backend(backend);
+
+		assert!(matches!(
+			gencmd.send_cmd_raw_timeout("slow", Duration::from_millis(10)),
+			Err(GencmdCmdError::Timeout(_))
+		));
+		assert_eq!(
+			gencmd
+				.send_cmd_raw_timeout("measure_temp", Duration::from_millis(10))
+				.unwrap(),
+			"temp=38.9'C"
+		);
+		assert!(gencmd.1.device().queue.is_empty());
+		assert_eq!(gencmd.1.device().use_count, 0);
+	}
+
+	#[test]
+	fn read_fails_without_response() {
+		let mut backend = VchiqBackend::with_device(FakeKernel::default()).unwrap();
+
+		let mut buffer = [0u8; 32];
+		assert!(matches!(
+			crate::backend::GencmdBackend::retrieve_response(&mut backend, &mut buffer),
+			Err(GencmdCmdError::Read)
+		));
+
+		backend.close().unwrap();
+		assert!(backend.is_closed());
+		assert!(backend.device().open.is_none());
+	}
+}
@@ -0,0 +1,238 @@
+//! Backend using the firmware mailbox property interface exposed by `/dev/vcio`.
+//!
+//! This is how the newer `vcgencmd` from raspberrypi/utils talks to the firmware and it requires no C libraries.
+//! The command is sent in a single property call with the gencmd tag and the response is written back into the same buffer.
+
+use std::{ffi::CStr, fs::File, os::unix::io::AsRawFd, path::Path, time::Duration};
+
+use super::GencmdBackend;
+use crate::error::{GencmdCmdError, GencmdInitError};
+
+/// Property tag of the gencmd request.
+pub const TAG_GENCMD: u32 = 0x0003_0080;
+/// Maximum length of the command and response strings including the null terminator.
+pub const MAX_STRING: usize = 1024;
+
+const PROCESS_REQUEST: u32 = 0x0000_0000;
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+const TAG_END: u32 = 0x0000_0000;
+
+// header (size, code), tag header (tag, buffer size, request size), gencmd error, string, end tag
+const HEADER_WORDS: usize = 6;
+const BUFFER_WORDS: usize = HEADER_WORDS + MAX_STRING / 4 + 1;
+
+/// `_IOWR(100, 0, char *)`
+const IOCTL_MBOX_PROPERTY: u32 =
+	(3 << 30) | ((std::mem::size_of::<*mut u8>() as u32) << 16) | (100 << 8);
+
+/// The ioctl layer of the mailbox.
+///
+/// Abstracted so that the backend can be tested without the device.
+pub trait MailboxDevice {
+	/// Processes the property `buffer` in-place.
+	fn property(&mut self, buffer: &mut [u32]) -> std::io::Result<()>;
+}
+
+/// The `/dev/vcio` character device.
+pub struct VcioDevice {
+	file: File,
+}
+impl VcioDevice {
+	pub const DEFAULT_PATH: &'static str = "/dev/vcio";
+
+	pub fn open() -> Result<Self, GencmdInitError> {
+		Self::open_path(Self::DEFAULT_PATH)
+	}
+
+	pub fn open_path(path: impl AsRef<Path>) -> Result<Self, GencmdInitError> {
+		let file = File::open(path.as_ref()).map_err(GencmdInitError::DeviceOpen)?;
+		log::debug!("opened mailbox device {}", path.as_ref().display());
+
+		Ok(VcioDevice { file })
+	}
+}
+impl MailboxDevice for VcioDevice {
+	fn property(&mut self, buffer: &mut [u32]) -> std::io::Result<()> {
+		// SAFETY: the buffer is valid for the whole duration of the call and its size is written in its first word
+		let result = unsafe {
+			libc::ioctl(
+				self.file.as_raw_fd(),
+				IOCTL_MBOX_PROPERTY as _,
+				buffer.as_mut_ptr(),
+			)
+		};
+		if result < 0 {
+			return Err(std::io::Error::last_os_error());
+		}
+
+		Ok(())
+	}
+}
+
+/// Backend sending commands through the mailbox property interface.
+pub struct VcioBackend<D: MailboxDevice = VcioDevice> {
+	device: D,
+	buffer: Vec<u32>,
+}
+impl VcioBackend {
+	/// Opens the backend on [`VcioDevice::DEFAULT_PATH`].
+	pub fn new() -> Result<Self, GencmdInitError> {
+		Ok(Self::with_device(VcioDevice::open()?))
+	}
+}
+impl<D: MailboxDevice> VcioBackend<D> {
+	pub fn with_device(device: D) -> Self {
+		VcioBackend {
+			device,
+			buffer: vec![0; BUFFER_WORDS],
+		}
+	}
+
+	pub fn device(&self) -> &D {
+		&self.device
+	}
+
+	fn string_bytes_mut(&mut self) -> &mut [u8] {
+		let words = &mut self.buffer[HEADER_WORDS..HEADER_WORDS + MAX_STRING / 4];
+
+		// SAFETY: u8 has no alignment requirements and the slice covers exactly the words
+		unsafe { std::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, MAX_STRING) }
+	}
+}
+impl<D: MailboxDevice> GencmdBackend for VcioBackend<D> {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		let command = command.to_bytes_with_nul();
+		if command.len() > MAX_STRING {
+			return Err(GencmdCmdError::CommandTooLong);
+		}
+
+		self.buffer.fill(0);
+		self.buffer[0] = (BUFFER_WORDS * 4) as u32;
+		self.buffer[1] = PROCESS_REQUEST;
+		self.buffer[2] = TAG_GENCMD;
+		self.buffer[3] = MAX_STRING as u32;
+		self.buffer[4] = 0;
+		self.buffer[5] = 0;
+		self.string_bytes_mut()[..command.len()].copy_from_slice(command);
+		self.buffer[BUFFER_WORDS - 1] = TAG_END;
+
+		log::debug!("sending vcio property gencmd: {:?}", command);
+		if let Err(err) = self.device.property(&mut self.buffer) {
+			log::error!("mailbox property call failed: {}", err);
+			return Err(GencmdCmdError::Send);
+		}
+
+		if self.buffer[1] != RESPONSE_SUCCESS {
+			log::error!("mailbox returned code {:#X}", self.buffer[1]);
+			return Err(GencmdCmdError::Send);
+		}
+
+		Ok(())
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		if self.buffer[5] != 0 {
+			log::error!("gencmd property returned error {}", self.buffer[5]);
+			return Err(GencmdCmdError::Read);
+		}
+
+		let string = self.string_bytes_mut();
+		let len = string.iter().position(|&b| b == 0).unwrap_or(MAX_STRING);
+		if len + 1 > buffer.len() {
+			return Err(GencmdCmdError::Read);
+		}
+
+		buffer[..len].copy_from_slice(&string[..len]);
+		buffer[len] = 0;
+
+		Ok(len)
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		_timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		// the property call in `send_command` has already returned the response
+		self.retrieve_response(buffer)
+	}
+
+	fn supports_timeout(&self) -> bool {
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{MailboxDevice, VcioBackend, HEADER_WORDS, MAX_STRING, TAG_GENCMD};
+	use crate::gencmd::{
+		commands::{CmdGetThrottled, CmdMeasureTemp},
+		unique::GencmdUnique,
+	};
+
+	/// Answers with canned property buffers like the firmware would.
+	struct FakeDevice {
+		commands: Vec<String>,
+		fail_code: Option<u32>,
+	}
+	impl MailboxDevice for FakeDevice {
+		fn property(&mut self, buffer: &mut [u32]) -> std::io::Result<()> {
+			assert_eq!(buffer[0] as usize, buffer.len() * 4);
+			assert_eq!(buffer[2], TAG_GENCMD);
+
+			let string = unsafe {
+				std::slice::from_raw_parts_mut(
+					buffer[HEADER_WORDS..].as_mut_ptr() as *mut u8,
+					MAX_STRING,
+				)
+			};
+			let len = string.iter().position(|&b| b == 0).unwrap();
+			let command = std::str::from_utf8(&string[..len]).unwrap().to_string();
+
+			let response: &[u8] = match command.as_str() {
+				"measure_temp" => b"temp=51.5'C\0",
+				"get_throttled" => b"throttled=0x50000\0",
+				_ => b"error=1 error_msg=\"command not registered\"\0",
+			};
+			string[..response.len()].copy_from_slice(response);
+			self.commands.push(command);
+
+			buffer[1] = self.fail_code.unwrap_or(0x8000_0000);
+			buffer[4] = 0x8000_0000 | response.len() as u32;
+
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn sends_commands_through_property_buffer() {
+		let mut gencmd = GencmdUnique::from_backend(VcioBackend::with_device(FakeDevice {
+			commands: Vec::new(),
+			fail_code: None,
+		}));
+
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 51.5);
+		assert_eq!(
+			u32::from(gencmd.send_cmd::<CmdGetThrottled>().unwrap()),
+			0x50000
+		);
+		assert!(gencmd.send_cmd_raw("foo").is_err());
+		assert_eq!(
+			gencmd.1.device().commands,
+			vec!["measure_temp", "get_throttled", "foo"]
+		);
+	}
+
+	#[test]
+	fn fails_on_mailbox_error() {
+		let mut gencmd = GencmdUnique::from_backend(VcioBackend::with_device(FakeDevice {
+			commands: Vec::new(),
+			fail_code: Some(0x8000_0001),
+		}));
+
+		assert!(matches!(
+			gencmd.send_cmd::<CmdMeasureTemp>(),
+			Err(crate::error::GencmdCmdError::Send)
+		));
+	}
+}
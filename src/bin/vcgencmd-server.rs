@@ -0,0 +1,170 @@
+use std::{
+	net::TcpListener,
+	path::PathBuf,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use clap::{Parser, ValueEnum};
+
+use videocore_gencmd::{
+	backend::mock::MockBackend,
+	gencmd::capabilities::Capabilities,
+	prelude::*,
+	remote::{
+		protocol::{DEFAULT_PORT, DEFAULT_SOCKET_PATH},
+		server::{
+			bind_unix, GencmdServer, DEFAULT_AUTH_TIMEOUT, DEFAULT_IDLE_TIMEOUT,
+			DEFAULT_MAX_CONNECTIONS, DEFAULT_RESPONSE_TIMEOUT,
+		},
+	},
+};
+
+/// Environment variable read for the secret when `--secret-file` is not given.
+const SECRET_ENV: &str = "VCGENCMD_SERVER_SECRET";
+
+#[derive(Debug, Clone, ValueEnum)]
+enum Verbosity {
+	Off,
+	Error,
+	Warn,
+	Info,
+	Debug,
+	Trace,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum Backend {
+	/// VideoCore gencmd service (through FFI or native vchiq)
+	#[cfg(global_instance_available)]
+	Vchi,
+	/// Canned responses, does not communicate with the firmware
+	Mock,
+	/// Firmware mailbox through /dev/vcio
+	#[cfg(feature = "vcio_backend")]
+	Vcio,
+	/// Temperature, arm clock and throttling read from sysfs
+	Sysfs,
+}
+#[cfg(global_instance_available)]
+const DEFAULT_BACKEND: &str = "vchi";
+#[cfg(all(not(global_instance_available), feature = "vcio_backend"))]
+const DEFAULT_BACKEND: &str = "vcio";
+
+#[derive(Debug, Parser)]
+#[command(author, version, about = "Serves gencmd to remote clients over TCP or to local processes over a Unix socket", long_about = None)]
+struct Cli {
+	/// Address to listen on
+	#[arg(short, long, default_value_t = format!("127.0.0.1:{}", DEFAULT_PORT))]
+	pub listen: String,
+	/// Run as a local daemon listening on a Unix socket at this path instead of TCP
+	#[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_SOCKET_PATH)]
+	pub socket: Option<PathBuf>,
+	/// File containing the secret clients must authenticate with (defaults to the `VCGENCMD_SERVER_SECRET` environment variable)
+	#[arg(short, long)]
+	pub secret_file: Option<PathBuf>,
+	/// Only allow this command, may be repeated (all commands are allowed if not given)
+	#[arg(short, long)]
+	pub allow: Vec<String>,
+	/// Seconds after which a silent connection is closed, 0 keeps connections open forever
+	#[arg(long, default_value_t = DEFAULT_IDLE_TIMEOUT.as_secs())]
+	pub idle_timeout: u64,
+	/// Seconds a client has to authenticate after connecting
+	#[arg(long, default_value_t = DEFAULT_AUTH_TIMEOUT.as_secs())]
+	pub auth_timeout: u64,
+	/// Number of connections served at the same time
+	#[arg(long, default_value_t = DEFAULT_MAX_CONNECTIONS)]
+	pub max_connections: usize,
+	/// Seconds the backend has to respond to a command, 0 waits forever
+	#[arg(long, default_value_t = DEFAULT_RESPONSE_TIMEOUT.as_secs())]
+	pub response_timeout: u64,
+	#[arg(short, long, default_value = "info")]
+	pub verbosity: Verbosity,
+	/// Required if built without a backend talking to the firmware, so that clients are never served canned responses by accident
+	#[arg(short, long)]
+	#[cfg_attr(
+		any(global_instance_available, feature = "vcio_backend"),
+		arg(default_value = DEFAULT_BACKEND)
+	)]
+	pub backend: Backend,
+}
+
+fn setup_logger(level: log::Level) {
+	edwardium_logger::Logger::new(
+		edwardium_logger::targets::stderr::StderrTarget::new(level, Default::default()),
+		std::time::Instant::now(),
+	)
+	.init_boxed()
+	.expect("Could not initialize logger");
+}
+
+fn main() -> anyhow::Result<()> {
+	let cli = Cli::parse();
+
+	if let Some(level) = match cli.verbosity {
+		Verbosity::Off => None,
+		Verbosity::Error => Some(log::Level::Error),
+		Verbosity::Warn => Some(log::Level::Warn),
+		Verbosity::Info => Some(log::Level::Info),
+		Verbosity::Debug => Some(log::Level::Debug),
+		Verbosity::Trace => Some(log::Level::Trace),
+	} {
+		setup_logger(level);
+		log::debug!("{:?}", cli);
+	}
+
+	let secret = match cli.secret_file {
+		Some(ref path) => Some(std::fs::read_to_string(path)?.trim_end().to_string()),
+		None => std::env::var(SECRET_ENV).ok(),
+	};
+	if secret.is_none()
+		&& cli.socket.is_none()
+		&& !cli.listen.starts_with("127.")
+		&& !cli.listen.starts_with("localhost")
+	{
+		log::warn!("listening on {} without a secret", cli.listen);
+	}
+
+	let backend: Box<dyn GencmdBackend + Send> = match cli.backend {
+		#[cfg(global_instance_available)]
+		Backend::Vchi => Box::new(GlobalInstance::new()?),
+		Backend::Mock => Box::new(MockBackend::new()),
+		#[cfg(feature = "vcio_backend")]
+		Backend::Vcio => Box::new(videocore_gencmd::backend::vcio::VcioBackend::new()?),
+		Backend::Sysfs => Box::new(videocore_gencmd::backend::sysfs::SysfsBackend::new()),
+	};
+
+	let mut server = GencmdServer::new(Arc::new(Mutex::new(backend)))
+		.with_idle_timeout(match cli.idle_timeout {
+			0 => None,
+			secs => Some(Duration::from_secs(secs)),
+		})
+		.with_auth_timeout(Duration::from_secs(cli.auth_timeout))
+		.with_max_connections(cli.max_connections)
+		.with_response_timeout(match cli.response_timeout {
+			0 => None,
+			secs => Some(Duration::from_secs(secs)),
+		});
+	if let Some(secret) = secret {
+		server = server.with_secret(secret);
+	}
+	if !cli.allow.is_empty() {
+		server = server.with_allowlist(Capabilities::from_commands(
+			cli.allow.iter().map(String::as_str),
+		));
+	}
+
+	let server = Arc::new(server);
+	match cli.socket {
+		Some(path) => {
+			let listener = bind_unix(&path)?;
+			server.serve_unix(listener)?;
+		}
+		None => {
+			let listener = TcpListener::bind(&cli.listen)?;
+			server.serve_tcp(listener)?;
+		}
+	}
+
+	Ok(())
+}
@@ -1,6 +1,6 @@
 use clap::{Parser, ValueEnum};
 
-use videocore_gencmd::prelude::*;
+use videocore_gencmd::{backend::mock::MockBackend, prelude::*};
 
 #[derive(Debug, Clone, ValueEnum)]
 enum Verbosity {
@@ -12,6 +12,27 @@ enum Verbosity {
 	Trace,
 }
 
+#[derive(Debug, Clone, ValueEnum)]
+enum Backend {
+	/// VideoCore gencmd service (through FFI or native vchiq)
+	#[cfg(global_instance_available)]
+	Vchi,
+	/// Canned responses, does not communicate with the firmware
+	Mock,
+	/// Firmware mailbox through /dev/vcio
+	#[cfg(feature = "vcio_backend")]
+	Vcio,
+	/// Temperature, arm clock and throttling read from sysfs
+	Sysfs,
+	/// Local `vcgencmd-server` daemon listening on `--socket`
+	#[cfg(feature = "remote")]
+	Daemon,
+}
+#[cfg(global_instance_available)]
+const DEFAULT_BACKEND: &str = "vchi";
+#[cfg(all(not(global_instance_available), feature = "vcio_backend"))]
+const DEFAULT_BACKEND: &str = "vcio";
+
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
@@ -20,6 +41,17 @@ struct Cli {
 	pub raw: bool,
 	#[arg(short, long, default_value = "off")]
 	pub verbosity: Verbosity,
+	/// Required if built without a backend talking to the firmware, so that canned responses are never printed by accident
+	#[arg(short, long)]
+	#[cfg_attr(
+		any(global_instance_available, feature = "vcio_backend"),
+		arg(default_value = DEFAULT_BACKEND)
+	)]
+	pub backend: Backend,
+	/// Socket of the daemon used by the `daemon` backend
+	#[cfg(feature = "remote")]
+	#[arg(long, default_value = videocore_gencmd::remote::protocol::DEFAULT_SOCKET_PATH)]
+	pub socket: std::path::PathBuf,
 	#[arg(required = true)]
 	pub command: Vec<String>,
 }
@@ -49,7 +81,22 @@ fn main() -> anyhow::Result<()> {
 	}
 
 	let mut command = cli.command.into_iter();
-	let mut gencmd = GencmdUnique::new()?;
+	let backend: Box<dyn GencmdBackend> = match cli.backend {
+		#[cfg(global_instance_available)]
+		Backend::Vchi => Box::new(GlobalInstance::new()?),
+		Backend::Mock => Box::new(MockBackend::new()),
+		#[cfg(feature = "vcio_backend")]
+		Backend::Vcio => Box::new(videocore_gencmd::backend::vcio::VcioBackend::new()?),
+		Backend::Sysfs => Box::new(videocore_gencmd::backend::sysfs::SysfsBackend::new()),
+		#[cfg(feature = "remote")]
+		Backend::Daemon => Box::new(
+			videocore_gencmd::backend::remote::RemoteBackend::connect_unix(
+				&cli.socket,
+				std::env::var("VCGENCMD_SERVER_SECRET").ok().as_deref(),
+			)?,
+		),
+	};
+	let mut gencmd = GencmdUnique::from_backend(backend);
 	if cli.raw {
 		let first_command = command.next().unwrap();
 		let command = command.fold(first_command, |mut acc, curr| {
@@ -72,7 +119,7 @@ fn main() -> anyhow::Result<()> {
 		}
 		"get_throttled" => {
 			let throttled = gencmd.send_cmd::<CmdGetThrottled>()?;
-			println!("0x{:X}", u32::from(throttled));
+			println!("0x{:X} ({})", u32::from(throttled), throttled);
 		}
 		"measure_clock" => match command.next().as_deref() {
 			Some("arm") => {
@@ -81,6 +128,23 @@ fn main() -> anyhow::Result<()> {
 			}
 			_ => anyhow::bail!("unrecognized arguments to `measure_clock`, try again with `--raw` or add implementation"),
 		}
+		"vcos" => match (command.next().as_deref(), command.next().as_deref()) {
+			(Some("version"), None) => {
+				let version = gencmd.send_cmd::<CmdVcosVersion>()?;
+				println!("{}", version.build_date);
+				println!("{}", version.copyright);
+				println!("version {}", version.version);
+				if let Some(host) = version.host {
+					println!("host {}", host);
+				}
+			}
+			(Some("log"), Some("status")) => gencmd.send_cmd::<CmdVcosLogStatus>()?.into_iter().for_each(|category| println!("{} - {}", category.name, category.level)),
+			(Some("heap"), None) => {
+				let heap = gencmd.send_cmd::<CmdVcosHeap>()?;
+				println!("total={} used={} free={} largest_free={}", heap.total, heap.used, heap.free, heap.largest_free);
+			}
+			_ => anyhow::bail!("unrecognized arguments to `vcos`, try again with `--raw` or add implementation"),
+		}
 		_ => anyhow::bail!("unrecognized command, try again with `--raw` or add implementation")
 	}
 
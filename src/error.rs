@@ -27,6 +27,13 @@ pub enum VcosError {
 	Interrupt = ffi::VCOS_STATUS_T::VCOS_EINTR.0 as u8,
 }
 
+impl VcosError {
+	/// Returns true if the failure is temporary and the operation may succeed when retried.
+	pub const fn is_transient(&self) -> bool {
+		matches!(self, VcosError::Again | VcosError::Interrupt)
+	}
+}
+
 impl ffi::VCOS_STATUS_T {
 	pub fn to_result(self) -> Result<(), VcosError> {
 		let error = match self {
@@ -58,6 +65,26 @@ pub enum GencmdInitError {
 	VchiConnect,
 	#[error("Another instance is already initialized")]
 	AlreadyInitialized,
+	#[error("Failed to open device: {0}")]
+	DeviceOpen(std::io::Error),
+	#[error("Failed to open gencmd service: {0}")]
+	ServiceOpen(std::io::Error),
+	#[error("Failed to load videocore libraries: {0}")]
+	LibraryLoad(Box<dyn std::error::Error + Send + Sync>),
+	#[error("Failed to connect to the server: {0}")]
+	Connect(std::io::Error),
+	#[error("The server rejected the secret")]
+	Unauthorized,
+}
+
+impl GencmdInitError {
+	/// Returns true if the failure is temporary and initialization may succeed when retried.
+	pub const fn is_transient(&self) -> bool {
+		match self {
+			GencmdInitError::VcosInit(err) => err.is_transient(),
+			_ => false,
+		}
+	}
 }
 
 #[derive(Error, Debug)]
@@ -73,10 +100,28 @@ pub enum GencmdCmdError {
 		ffi::GENCMD_MAX_LENGTH
 	)]
 	CommandTooLong,
+	#[error("Command `{0}` is not supported on this firmware")]
+	Unsupported(String),
+	#[error("Command `{0}` was not expected by the replayed transcript")]
+	UnexpectedCommand(String),
+	#[error("Remote server returned an error: {0}")]
+	Remote(String),
+	#[error("Timed out after {0:?} waiting for the lock held by another process")]
+	LockTimeout(std::time::Duration),
+	#[error("Failed to take the lock: {0}")]
+	Lock(std::io::Error),
+	#[error("The worker thread has stopped")]
+	WorkerStopped,
 	#[error("Failed to send command")]
 	Send,
 	#[error("Failed to read response")]
 	Read,
+	#[error("Response not received within {0:?}")]
+	Timeout(std::time::Duration),
+	#[error("The backend cannot time out waiting for responses")]
+	TimeoutUnsupported,
+	#[error("Failed to reconnect: {0}")]
+	Reconnect(GencmdInitError),
 	#[error("The received response is not valid utf8: {0}")]
 	Utf8(#[from] std::str::Utf8Error),
 	#[error(transparent)]
@@ -85,6 +130,20 @@ pub enum GencmdCmdError {
 	InvalidResponseFormat(Box<dyn std::error::Error + Send + Sync>),
 }
 impl GencmdCmdError {
+	/// Returns true if the failure is temporary and the command may succeed when retried.
+	///
+	/// Failures to send or read, timeouts and lock contention are transient. Error responses, invalid responses
+	/// and commands which are too long or unsupported fail the same way every time.
+	pub const fn is_transient(&self) -> bool {
+		matches!(
+			self,
+			GencmdCmdError::Send
+				| GencmdCmdError::Read
+				| GencmdCmdError::Timeout(_)
+				| GencmdCmdError::LockTimeout(_)
+		) || matches!(self, GencmdCmdError::Reconnect(err) if err.is_transient())
+	}
+
 	pub fn from_invalid_format(error: impl std::error::Error + Send + Sync + 'static) -> Self {
 		GencmdCmdError::InvalidResponseFormat(Box::new(error))
 	}
@@ -0,0 +1,253 @@
+//! Runtime loading of the VideoCore libraries.
+//!
+//! Instead of linking `vchiq_arm`, `vcos` and `bcm_host` at build time, the libraries are opened with `dlopen`
+//! and the functions used by [`GlobalInstance`](crate::global::GlobalInstance) are resolved at runtime.
+//! The functions in this module have the same names as the bindings and replace them in the [`ffi`](super) module.
+//!
+//! The libraries are loaded on the first initialization of the global instance from [`DEFAULT_SEARCH_PATHS`], or
+//! explicitly using [`load_libraries_from`].
+//!
+//! The safety requirements of the wrapper functions are the same as those of the bindings they replace.
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+	ffi::{CStr, CString},
+	os::raw::{c_char, c_int, c_void},
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+
+use thiserror::Error;
+
+use super::{VCHI_CONNECTION_T, VCHI_INSTANCE_T, VCOS_STATUS_T};
+
+/// Libraries to load, in dependency order.
+pub const LIBRARIES: &[&str] = &["libvcos.so", "libvchiq_arm.so", "libbcm_host.so"];
+
+/// Directories searched by [`load_libraries`]. The empty path means the system library search path.
+pub const DEFAULT_SEARCH_PATHS: &[&str] = &["/opt/vc/lib", ""];
+
+#[derive(Error, Debug)]
+pub enum DlopenError {
+	#[error("Could not load library {library}: {message}")]
+	Library { library: String, message: String },
+	#[error("Could not resolve symbol {symbol}: {message}")]
+	Symbol {
+		symbol: &'static str,
+		message: String,
+	},
+}
+
+#[derive(Clone, Copy)]
+struct Functions {
+	vcos_init: unsafe extern "C" fn() -> VCOS_STATUS_T,
+	vcos_deinit: unsafe extern "C" fn(),
+	vchi_initialise: unsafe extern "C" fn(*mut VCHI_INSTANCE_T) -> i32,
+	vchi_connect: unsafe extern "C" fn(*mut *mut VCHI_CONNECTION_T, u32, VCHI_INSTANCE_T) -> i32,
+	vchi_disconnect: unsafe extern "C" fn(VCHI_INSTANCE_T) -> i32,
+	vc_vchi_gencmd_init: unsafe extern "C" fn(VCHI_INSTANCE_T, *mut *mut VCHI_CONNECTION_T, u32),
+	vc_gencmd_stop: unsafe extern "C" fn(),
+	vc_gencmd_send: unsafe extern "C" fn(*const c_char, ...) -> c_int,
+	vc_gencmd_read_response: unsafe extern "C" fn(*mut c_char, c_int) -> c_int,
+}
+// SAFETY: these are plain function pointers into libraries which are never unloaded
+unsafe impl Send for Functions {}
+
+static FUNCTIONS: Mutex<Option<Functions>> = Mutex::new(None);
+
+fn dlerror() -> String {
+	// SAFETY: dlerror returns either null or a valid C string
+	let error = unsafe { libc::dlerror() };
+	if error.is_null() {
+		return "unknown error".to_string();
+	}
+
+	unsafe { CStr::from_ptr(error) }
+		.to_string_lossy()
+		.into_owned()
+}
+
+fn open_library(name: &str, search_paths: &[PathBuf]) -> Result<*mut c_void, DlopenError> {
+	let mut message = String::new();
+
+	for directory in search_paths {
+		let path = directory.join(name);
+		let path = CString::new(path.to_string_lossy().into_owned()).map_err(|err| {
+			DlopenError::Library {
+				library: name.to_string(),
+				message: err.to_string(),
+			}
+		})?;
+
+		// SAFETY: path is a valid C string
+		let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_GLOBAL) };
+		if !handle.is_null() {
+			log::debug!("loaded {:?}", path);
+			return Ok(handle);
+		}
+
+		message = dlerror();
+		log::trace!("could not load {:?}: {}", path, message);
+	}
+
+	Err(DlopenError::Library {
+		library: name.to_string(),
+		message,
+	})
+}
+
+/// ### Safety
+/// `T` must be a function pointer type matching the symbol.
+unsafe fn resolve<T: Copy>(
+	handles: &[*mut c_void],
+	symbol: &'static str,
+) -> Result<T, DlopenError> {
+	debug_assert_eq!(std::mem::size_of::<T>(), std::mem::size_of::<*mut c_void>());
+
+	let name = CString::new(symbol).unwrap();
+	for &handle in handles.iter().rev() {
+		let address = libc::dlsym(handle, name.as_ptr());
+		if !address.is_null() {
+			return Ok(std::mem::transmute_copy(&address));
+		}
+	}
+
+	Err(DlopenError::Symbol {
+		symbol,
+		message: dlerror(),
+	})
+}
+
+/// Loads the libraries from [`DEFAULT_SEARCH_PATHS`] unless they are already loaded.
+pub fn load_libraries() -> Result<(), DlopenError> {
+	load_libraries_from(DEFAULT_SEARCH_PATHS)
+}
+
+/// Loads the libraries from the first directory in `search_paths` which contains them, unless they are already loaded.
+pub fn load_libraries_from(search_paths: &[impl AsRef<Path>]) -> Result<(), DlopenError> {
+	let mut lock = FUNCTIONS.lock().expect("mutex poisoned");
+	if lock.is_some() {
+		return Ok(());
+	}
+
+	let search_paths: Vec<PathBuf> = search_paths
+		.iter()
+		.map(|p| p.as_ref().to_path_buf())
+		.collect();
+	let handles = LIBRARIES
+		.iter()
+		.map(|library| open_library(library, &search_paths))
+		.collect::<Result<Vec<_>, _>>()?;
+
+	// SAFETY: the types match the bindings
+	let functions = unsafe {
+		Functions {
+			vcos_init: resolve(&handles, "vcos_init")?,
+			vcos_deinit: resolve(&handles, "vcos_deinit")?,
+			vchi_initialise: resolve(&handles, "vchi_initialise")?,
+			vchi_connect: resolve(&handles, "vchi_connect")?,
+			vchi_disconnect: resolve(&handles, "vchi_disconnect")?,
+			vc_vchi_gencmd_init: resolve(&handles, "vc_vchi_gencmd_init")?,
+			vc_gencmd_stop: resolve(&handles, "vc_gencmd_stop")?,
+			vc_gencmd_send: resolve(&handles, "vc_gencmd_send")?,
+			vc_gencmd_read_response: resolve(&handles, "vc_gencmd_read_response")?,
+		}
+	};
+	*lock = Some(functions);
+
+	log::info!("Loaded videocore libraries");
+
+	Ok(())
+}
+
+/// Returns true if the libraries have been loaded.
+pub fn is_loaded() -> bool {
+	FUNCTIONS.lock().expect("mutex poisoned").is_some()
+}
+
+fn functions() -> Functions {
+	FUNCTIONS
+		.lock()
+		.expect("mutex poisoned")
+		.expect("videocore libraries have not been loaded")
+}
+
+/// ### Panic
+/// Panics if the libraries have not been loaded. The same applies for all functions below.
+pub unsafe fn vcos_init() -> VCOS_STATUS_T {
+	(functions().vcos_init)()
+}
+
+pub unsafe fn vcos_deinit() {
+	(functions().vcos_deinit)()
+}
+
+pub unsafe fn vchi_initialise(instance_handle: *mut VCHI_INSTANCE_T) -> i32 {
+	(functions().vchi_initialise)(instance_handle)
+}
+
+pub unsafe fn vchi_connect(
+	connections: *mut *mut VCHI_CONNECTION_T,
+	num_connections: u32,
+	instance_handle: VCHI_INSTANCE_T,
+) -> i32 {
+	(functions().vchi_connect)(connections, num_connections, instance_handle)
+}
+
+pub unsafe fn vchi_disconnect(instance_handle: VCHI_INSTANCE_T) -> i32 {
+	(functions().vchi_disconnect)(instance_handle)
+}
+
+pub unsafe fn vc_vchi_gencmd_init(
+	initialise_instance: VCHI_INSTANCE_T,
+	connections: *mut *mut VCHI_CONNECTION_T,
+	num_connections: u32,
+) {
+	(functions().vc_vchi_gencmd_init)(initialise_instance, connections, num_connections)
+}
+
+pub unsafe fn vc_gencmd_stop() {
+	(functions().vc_gencmd_stop)()
+}
+
+/// Only the `"%s"` format with a single string argument is supported, as variadic functions cannot be defined in Rust.
+pub unsafe fn vc_gencmd_send(format: *const c_char, arg1: *const c_char) -> c_int {
+	(functions().vc_gencmd_send)(format, arg1)
+}
+
+pub unsafe fn vc_gencmd_read_response(response: *mut c_char, maxlen: c_int) -> c_int {
+	(functions().vc_gencmd_read_response)(response, maxlen)
+}
+
+#[cfg(test)]
+mod test {
+	use super::{load_libraries_from, DlopenError};
+
+	#[test]
+	fn missing_libraries_are_reported() {
+		if super::is_loaded() {
+			return;
+		}
+
+		match load_libraries_from(&["/nonexistent/videocore/lib"]) {
+			Err(DlopenError::Library { library, .. }) => assert_eq!(library, "libvcos.so"),
+			other => panic!("expected library error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	#[cfg(not(feature = "native_vchiq"))]
+	fn global_instance_reports_missing_libraries() {
+		use crate::{error::GencmdInitError, global::GlobalInstance};
+
+		// the libraries are installed on this system
+		if super::is_loaded() || super::load_libraries().is_ok() {
+			return;
+		}
+
+		match GlobalInstance::new() {
+			Err(GencmdInitError::LibraryLoad(_)) => (),
+			other => panic!("expected library load error, got {:?}", other.err()),
+		}
+	}
+}
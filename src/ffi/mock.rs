@@ -1,12 +1,146 @@
-use std::{ffi::CStr, sync::Mutex};
+use std::{
+	ffi::CStr,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Mutex,
+	},
+	time::Duration,
+};
 
 use super::{VCHI_CONNECTION_T, VCHI_INSTANCE_T, VCOS_STATUS_T};
+use crate::backend::mock::RESPONSE_ERROR_1;
+pub use crate::backend::mock::{MockGencmd, MockPattern};
+
+/// Responses of the mocked functions.
+///
+/// The mock is shared by the whole process, so tests configuring it should use commands not used by other tests.
+pub static MOCK_GENCMD: MockGencmd = MockGencmd::new();
+
+/// A failure injected into the mocked functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockFault {
+	/// `vcos_init` returns the status.
+	VcosInit(VCOS_STATUS_T),
+	/// `vchi_initialise` fails without initializing the instance.
+	VchiInitialise,
+	/// `vchi_connect` fails.
+	VchiConnect,
+	/// `vchi_disconnect` fails.
+	VchiDisconnect,
+	/// `vc_gencmd_send` returns the code.
+	Send(i32),
+	/// `vc_gencmd_read_response` returns the code.
+	Read(i32),
+	/// `vc_gencmd_read_response` ignores `maxlen` and truncates longer responses without a null terminator.
+	ReadOverflow,
+	/// `vc_gencmd_read_response` writes the response without a null terminator and fills the rest of the buffer with garbage.
+	MissingNul,
+	/// `vc_gencmd_read_response` writes a response which is not valid utf8.
+	InvalidUtf8,
+	/// `vc_gencmd_send` sleeps before answering.
+	Latency(Duration),
+	/// `vc_gencmd_read_response` sleeps before answering.
+	ReadLatency(Duration),
+}
+impl MockFault {
+	const fn is_command_fault(&self) -> bool {
+		matches!(
+			self,
+			MockFault::Send(_)
+				| MockFault::Read(_)
+				| MockFault::ReadOverflow
+				| MockFault::MissingNul
+				| MockFault::InvalidUtf8
+				| MockFault::Latency(_)
+				| MockFault::ReadLatency(_)
+		)
+	}
+}
+
+/// Failures injected into the mocked functions.
+///
+/// Faults stay active until cleared. Command faults can be limited to commands matching a pattern, the rest apply to the whole process,
+/// so tests using them must not run concurrently with other tests using the global instance.
+pub struct MockFaults {
+	faults: Mutex<Vec<(Option<MockPattern>, MockFault)>>,
+}
+impl MockFaults {
+	pub const fn new() -> Self {
+		MockFaults {
+			faults: Mutex::new(Vec::new()),
+		}
+	}
+
+	/// Injects `fault`. Command faults apply to every command.
+	pub fn inject(&self, fault: MockFault) {
+		self.faults
+			.lock()
+			.expect("mutex poisoned")
+			.push((None, fault));
+	}
+
+	/// Injects a command fault for commands matching `pattern`.
+	///
+	/// ### Panic
+	/// Panics if `fault` does not concern commands.
+	pub fn inject_for(&self, pattern: impl Into<MockPattern>, fault: MockFault) {
+		assert!(
+			fault.is_command_fault(),
+			"{:?} is not a command fault",
+			fault
+		);
+
+		self.faults
+			.lock()
+			.expect("mutex poisoned")
+			.push((Some(pattern.into()), fault));
+	}
+
+	/// Removes all injected faults.
+	pub fn clear(&self) {
+		self.faults.lock().expect("mutex poisoned").clear();
+	}
+
+	fn find(
+		&self,
+		command: Option<&str>,
+		predicate: impl Fn(&MockFault) -> bool,
+	) -> Option<MockFault> {
+		let faults = self.faults.lock().expect("mutex poisoned");
+
+		faults
+			.iter()
+			.rev()
+			.filter(|(_, fault)| predicate(fault))
+			.find(|(pattern, _)| match (pattern, command) {
+				(None, _) => true,
+				(Some(pattern), Some(command)) => pattern.matches(command),
+				(Some(_), None) => false,
+			})
+			.map(|(_, fault)| *fault)
+	}
+
+	fn is_injected(&self, fault: MockFault) -> bool {
+		self.find(None, |f| *f == fault).is_some()
+	}
+}
+impl Default for MockFaults {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Faults of the mocked functions.
+pub static MOCK_FAULTS: MockFaults = MockFaults::new();
 
 #[no_mangle]
 pub extern "C" fn vcos_init() -> VCOS_STATUS_T {
 	log::trace!("vcos_init");
 
-	VCOS_STATUS_T::VCOS_SUCCESS
+	match MOCK_FAULTS.find(None, |fault| matches!(fault, MockFault::VcosInit(_))) {
+		Some(MockFault::VcosInit(status)) => status,
+		_ => VCOS_STATUS_T::VCOS_SUCCESS,
+	}
 }
 
 #[no_mangle]
@@ -18,6 +152,10 @@ pub extern "C" fn vcos_deinit() {
 pub extern "C" fn vchi_initialise(instance_handle: *mut VCHI_INSTANCE_T) -> i32 {
 	log::trace!("vchi_initialise");
 
+	if MOCK_FAULTS.is_injected(MockFault::VchiInitialise) {
+		return -1;
+	}
+
 	unsafe {
 		*instance_handle = std::ptr::NonNull::dangling().as_ptr();
 	}
@@ -33,6 +171,10 @@ pub extern "C" fn vchi_connect(
 ) -> i32 {
 	log::trace!("vchi_connect");
 
+	if MOCK_FAULTS.is_injected(MockFault::VchiConnect) {
+		return -1;
+	}
+
 	0
 }
 
@@ -40,6 +182,10 @@ pub extern "C" fn vchi_connect(
 pub extern "C" fn vchi_disconnect(_instance_handle: VCHI_INSTANCE_T) -> i32 {
 	log::trace!("vchi_disconnect");
 
+	if MOCK_FAULTS.is_injected(MockFault::VchiDisconnect) {
+		return -1;
+	}
+
 	0
 }
 
@@ -50,20 +196,30 @@ pub extern "C" fn vc_vchi_gencmd_init(
 	_num_connections: u32,
 ) {
 	log::trace!("vc_vchi_gencmd_init");
+
+	SERVICE_STOPPED.store(false, Ordering::Release);
 }
 
+/// Set by `vc_gencmd_stop`, sends fail until the service is initialized again.
+static SERVICE_STOPPED: AtomicBool = AtomicBool::new(false);
+
 #[no_mangle]
 pub extern "C" fn vc_gencmd_stop() {
 	log::trace!("vc_gencmd_stop");
+
+	SERVICE_STOPPED.store(true, Ordering::Release);
 }
 
-const RESPONSE_ERROR_1: &'static [u8] = b"error=1 error_msg=\"command not registered\"\0";
-const RESPONSE_ERROR_2: &'static [u8] = b"error=2 error_msg=\"invalid arguments\"\0";
-const RESPONSE_COMMANDS: &'static [u8] = b"commands=\"vcos, ap_output_control, ap_output_post_processing, vchi_test_init, vchi_test_exit, pm_set_policy, pm_get_status, pm_show_stats, pm_start_logging, pm_stop_logging, version, commands, set_vll_dir, set_backlight, set_logging, get_lcd_info, arbiter, cache_flush, otp_dump, test_result, codec_enabled, get_camera, get_mem, measure_clock, measure_volts, enable_clock, scaling_kernel, scaling_sharpness, get_hvs_asserts, get_throttled, measure_temp, get_config, hdmi_ntsc_freqs, hdmi_adjust_clock, hdmi_status_show, hvs_update_fields, pwm_speedup, force_audio, hdmi_stream_channels, hdmi_channel_map, display_power, read_ring_osc, memtest, dispmanx_list, get_rsts, schmoo, render_bar, disk_notify, inuse_notify, sus_suspend, sus_status, sus_is_enabled, sus_stop_test_thread, egl_platform_switch, mem_validate, mem_oom, mem_reloc_stats, hdmi_cvt, hdmi_timings, readmr, pmicrd, pmicwr, bootloader_version, bootloader_config, file, vctest_memmap, vctest_start, vctest_stop, vctest_set, vctest_get\"\0";
-const RESPONSE_GET_THROTTLED: &'static [u8] = b"throttled=0x0\0";
-const RESPONSE_MEASURE_CLOCK_ARM: &'static [u8] = b"frequency(48)=6000000\0";
-const RESPONSE_MEASURE_TEMP: &'static [u8] = b"temp=45.6'C\0";
-static RESPONSE_LAST_SEND: Mutex<&'static [u8]> = Mutex::new(RESPONSE_ERROR_1);
+struct LastSend {
+	response: Vec<u8>,
+	fault: Option<MockFault>,
+	read_latency: Option<Duration>,
+}
+static LAST_SEND: Mutex<LastSend> = Mutex::new(LastSend {
+	response: Vec::new(),
+	fault: None,
+	read_latency: None,
+});
 
 #[no_mangle]
 pub extern "C" fn vc_gencmd_send(
@@ -73,14 +229,22 @@ pub extern "C" fn vc_gencmd_send(
 ) -> ::std::os::raw::c_int {
 	log::trace!("vc_gencmd_send({:p}, {:p})", format, arg1);
 
-	let mut lock = RESPONSE_LAST_SEND.lock().expect("mutex poisoned");
+	let mut lock = LAST_SEND.lock().expect("mutex poisoned");
+	lock.fault = None;
+	lock.read_latency = None;
+
+	if SERVICE_STOPPED.load(Ordering::Acquire) {
+		log::warn!("vc_gencmd_send called on a stopped service");
+		lock.response = RESPONSE_ERROR_1.to_vec();
+		return -1;
+	}
 
 	let format = unsafe { CStr::from_ptr(format) };
 
 	// not going to reimplement printf
 	if format.to_bytes() != b"%s" {
 		log::warn!("Unsupported printf format for mock ffi");
-		*lock = RESPONSE_ERROR_1;
+		lock.response = RESPONSE_ERROR_1.to_vec();
 		return 0;
 	}
 
@@ -88,20 +252,42 @@ pub extern "C" fn vc_gencmd_send(
 		Ok(command) => command,
 		Err(err) => {
 			log::warn!("Unsupported command bytes for mock ffi: {}", err);
-			*lock = RESPONSE_ERROR_1;
+			lock.response = RESPONSE_ERROR_1.to_vec();
 			return 0;
 		}
 	};
 
 	log::trace!("vc_gencmd_send command: {}", command);
-	*lock = match command {
-		"commands" => RESPONSE_COMMANDS,
-		"get_throttled" => RESPONSE_GET_THROTTLED,
-		"measure_clock" => RESPONSE_ERROR_2,
-		"measure_clock arm" => RESPONSE_MEASURE_CLOCK_ARM,
-		"measure_temp" => RESPONSE_MEASURE_TEMP,
-		_ => RESPONSE_ERROR_1,
-	};
+	if let Some(MockFault::Latency(latency)) = MOCK_FAULTS.find(Some(command), |fault| {
+		matches!(fault, MockFault::Latency(_))
+	}) {
+		std::thread::sleep(latency);
+	}
+
+	if let Some(MockFault::Send(code)) =
+		MOCK_FAULTS.find(Some(command), |fault| matches!(fault, MockFault::Send(_)))
+	{
+		lock.response = RESPONSE_ERROR_1.to_vec();
+		return code;
+	}
+
+	let mut response = MOCK_GENCMD.respond(command).into_bytes();
+	response.push(0);
+	lock.response = response;
+	lock.fault = MOCK_FAULTS.find(Some(command), |fault| {
+		matches!(
+			fault,
+			MockFault::Read(_)
+				| MockFault::ReadOverflow
+				| MockFault::MissingNul
+				| MockFault::InvalidUtf8
+		)
+	});
+	if let Some(MockFault::ReadLatency(latency)) = MOCK_FAULTS.find(Some(command), |fault| {
+		matches!(fault, MockFault::ReadLatency(_))
+	}) {
+		lock.read_latency = Some(latency);
+	}
 
 	0
 }
@@ -113,19 +299,46 @@ pub extern "C" fn vc_gencmd_read_response(
 ) -> ::std::os::raw::c_int {
 	log::trace!("vc_gencmd_read_response");
 
-	let mock_response = *RESPONSE_LAST_SEND.lock().expect("mutex poisoned");
+	let read_latency = LAST_SEND.lock().expect("mutex poisoned").read_latency;
+	if let Some(latency) = read_latency {
+		std::thread::sleep(latency);
+	}
+
+	let lock = LAST_SEND.lock().expect("mutex poisoned");
+	let mut mock_response: &[u8] = if lock.response.is_empty() {
+		RESPONSE_ERROR_1
+	} else {
+		&lock.response
+	};
+	let maxlen = maxlen.max(0) as usize;
+	// SAFETY: the caller guarantees the buffer is valid for maxlen bytes
+	let buffer = unsafe { std::slice::from_raw_parts_mut(response as *mut u8, maxlen) };
+
+	match lock.fault {
+		Some(MockFault::Read(code)) => return code,
+		Some(MockFault::ReadOverflow) => {
+			let len = mock_response.len().min(maxlen);
+			buffer[..len].copy_from_slice(&mock_response[..len]);
+			return 0;
+		}
+		Some(MockFault::MissingNul) => {
+			let len = (mock_response.len() - 1).min(maxlen);
+			buffer[..len].copy_from_slice(&mock_response[..len]);
+			buffer[len..].fill(b'?');
+			return 0;
+		}
+		Some(MockFault::InvalidUtf8) => mock_response = b"temp=\xff\xfe'C\0",
+		_ => (),
+	}
 
-	if maxlen < mock_response.len() as i32 {
-		unsafe {
-			*response = 0;
+	if maxlen < mock_response.len() {
+		if let Some(first) = buffer.first_mut() {
+			*first = 0;
 		}
 		return -1;
 	}
 
-	unsafe {
-		std::slice::from_raw_parts_mut(response as *mut u8, mock_response.len())
-			.copy_from_slice(mock_response);
-	}
+	buffer[..mock_response.len()].copy_from_slice(mock_response);
 
 	0
 }
@@ -3,12 +3,23 @@
 #[cfg(feature = "mock_vc_ffi")]
 pub mod mock;
 
+#[cfg(all(feature = "dlopen_vc_ffi", not(feature = "mock_vc_ffi")))]
+pub mod dynamic;
+
 #[cfg(feature = "run_bindgen")]
+#[cfg_attr(
+	all(feature = "dlopen_vc_ffi", not(feature = "mock_vc_ffi")),
+	allow(dead_code)
+)]
 mod inner {
 	include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
 
 #[cfg(not(feature = "run_bindgen"))]
+#[cfg_attr(
+	all(feature = "dlopen_vc_ffi", not(feature = "mock_vc_ffi")),
+	allow(dead_code)
+)]
 mod inner {
 	/* automatically generated by rust-bindgen 0.66.1 */
 
@@ -123,3 +134,10 @@ mod inner {
 }
 
 pub use inner::*;
+
+// shadow the linked functions from the glob import
+#[cfg(all(feature = "dlopen_vc_ffi", not(feature = "mock_vc_ffi")))]
+pub use dynamic::{
+	vc_gencmd_read_response, vc_gencmd_send, vc_gencmd_stop, vc_vchi_gencmd_init, vchi_connect,
+	vchi_disconnect, vchi_initialise, vcos_deinit, vcos_init,
+};
@@ -0,0 +1,200 @@
+//! Async interface which keeps the blocking calls off the executor.
+//!
+//! [`AsyncGencmd`] owns the backend on a dedicated worker thread and hands out futures which complete once the worker
+//! has sent the command and read the response. The futures do not depend on any particular runtime.
+
+use std::{
+	future::Future,
+	pin::Pin,
+	sync::{Arc, Mutex, PoisonError},
+	task::{Context, Poll, Waker},
+};
+
+#[cfg(global_instance_available)]
+use crate::global::GlobalInstance;
+use crate::{
+	backend::GencmdBackend,
+	error::GencmdCmdError,
+	gencmd::{capabilities::Capabilities, worker::Worker, Gencmd, OwnedCommand},
+};
+
+/// Sends commands from async code through a worker thread.
+///
+/// Cloning the handle is cheap and all clones share the worker. Commands are processed in the order they were sent.
+/// The worker stops, dropping the backend, once all handles are dropped and the queued commands are processed.
+/// Dropping the last handle blocks until then.
+#[derive(Clone)]
+pub struct AsyncGencmd {
+	worker: Arc<Worker>,
+}
+impl AsyncGencmd {
+	/// Creates a [`GlobalInstance`] and moves it to the worker.
+	#[cfg(global_instance_available)]
+	pub fn new() -> Result<Self, crate::error::GencmdInitError> {
+		Ok(Self::from_backend(GlobalInstance::new()?))
+	}
+
+	pub fn from_backend(backend: impl GencmdBackend + Send + 'static) -> Self {
+		Self::from_parts(Gencmd::new(), backend)
+	}
+
+	/// Uses `gencmd` (e.g. with loaded capabilities) together with `backend` on the worker.
+	pub fn from_parts(gencmd: Gencmd, backend: impl GencmdBackend + Send + 'static) -> Self {
+		AsyncGencmd {
+			worker: Arc::new(Worker::spawn(gencmd, backend)),
+		}
+	}
+
+	/// See [`Gencmd::send_cmd_raw`].
+	pub async fn send_cmd_raw(&self, command: impl Into<String>) -> Result<String, GencmdCmdError> {
+		let command = command.into();
+
+		self.run(move |gencmd, backend| gencmd.send_cmd_raw(backend, &command).map(str::to_string))
+			.await
+	}
+
+	/// See [`Gencmd::send_cmd`], e.g. `gencmd.send_cmd::<CmdMeasureTemp>().await`.
+	///
+	/// Only [commands with owned responses](OwnedCommand) can be sent this way. Responses borrowing from the buffer (such as [`CmdCommands`](super::commands::CmdCommands))
+	/// can be parsed from the result of [`send_cmd_raw`](Self::send_cmd_raw) using [`Command::parse_response`](super::Command::parse_response).
+	pub async fn send_cmd<C: OwnedCommand>(&self) -> Result<C::Output, GencmdCmdError>
+	where
+		C::Output: Send + 'static,
+	{
+		self.run(|gencmd, backend| gencmd.send_cmd::<C>(backend))
+			.await
+	}
+
+	/// See [`Gencmd::load_capabilities`].
+	pub async fn load_capabilities(&self) -> Result<Capabilities, GencmdCmdError> {
+		self.run(|gencmd, backend| gencmd.load_capabilities(backend).cloned())
+			.await
+	}
+
+	async fn run<T: Send + 'static>(
+		&self,
+		job: impl FnOnce(&mut Gencmd, &mut dyn GencmdBackend) -> Result<T, GencmdCmdError>
+			+ Send
+			+ 'static,
+	) -> Result<T, GencmdCmdError> {
+		let (responder, response) = oneshot();
+
+		self.worker.submit(Box::new(move |gencmd, backend| {
+			responder.send(job(gencmd, backend))
+		}))?;
+
+		response.await?
+	}
+}
+
+struct Shared<T> {
+	value: Option<T>,
+	waker: Option<Waker>,
+	closed: bool,
+}
+
+fn oneshot<T>() -> (Responder<T>, ResponseFuture<T>) {
+	let shared = Arc::new(Mutex::new(Shared {
+		value: None,
+		waker: None,
+		closed: false,
+	}));
+
+	(Responder(shared.clone()), ResponseFuture(shared))
+}
+
+/// Completes the paired future, or fails it if dropped without sending (e.g. if the job panicked).
+struct Responder<T>(Arc<Mutex<Shared<T>>>);
+impl<T> Responder<T> {
+	fn send(self, value: T) {
+		self.0.lock().unwrap_or_else(PoisonError::into_inner).value = Some(value);
+	}
+}
+impl<T> Drop for Responder<T> {
+	fn drop(&mut self) {
+		let mut shared = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+		shared.closed = true;
+
+		if let Some(waker) = shared.waker.take() {
+			waker.wake();
+		}
+	}
+}
+
+struct ResponseFuture<T>(Arc<Mutex<Shared<T>>>);
+impl<T> Future for ResponseFuture<T> {
+	type Output = Result<T, GencmdCmdError>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let mut shared = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+
+		if let Some(value) = shared.value.take() {
+			return Poll::Ready(Ok(value));
+		}
+		if shared.closed {
+			return Poll::Ready(Err(GencmdCmdError::WorkerStopped));
+		}
+
+		shared.waker = Some(cx.waker().clone());
+		Poll::Pending
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		future::Future,
+		sync::Arc,
+		task::{Context, Poll, Wake},
+		thread::Thread,
+	};
+
+	use super::AsyncGencmd;
+	use crate::{
+		backend::mock::MockBackend,
+		error::GencmdCmdError,
+		gencmd::{
+			commands::{CmdCommands, CmdMeasureTemp},
+			Command,
+		},
+	};
+
+	struct ThreadWaker(Thread);
+	impl Wake for ThreadWaker {
+		fn wake(self: Arc<Self>) {
+			self.0.unpark();
+		}
+	}
+
+	fn block_on<F: Future>(future: F) -> F::Output {
+		let waker = Arc::new(ThreadWaker(std::thread::current())).into();
+		let mut context = Context::from_waker(&waker);
+		let mut future = std::pin::pin!(future);
+
+		loop {
+			match future.as_mut().poll(&mut context) {
+				Poll::Ready(output) => return output,
+				Poll::Pending => std::thread::park(),
+			}
+		}
+	}
+
+	#[test]
+	fn sends_commands_from_async() {
+		let gencmd = AsyncGencmd::from_backend(MockBackend::new());
+
+		block_on(async {
+			assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().await.unwrap(), 45.6);
+
+			let commands = gencmd.send_cmd_raw("commands").await.unwrap();
+			assert!(CmdCommands::parse_response(&commands)
+				.unwrap()
+				.contains(&"measure_temp"));
+
+			assert!(matches!(
+				gencmd.send_cmd_raw("not_a_command").await,
+				Err(GencmdCmdError::ErrorResponse(_))
+			));
+		});
+	}
+}
@@ -0,0 +1,71 @@
+//! Sending of multiple typed commands in one call.
+//!
+//! A [`Batch`] is a tuple of [`Command`]s with owned responses. Sending it returns a tuple with the result of each command,
+//! so one failing command does not prevent the others from being sent. The wrappers hold their lock for the whole batch.
+//!
+//! ```
+//! # use videocore_gencmd::{backend::mock::MockBackend, prelude::*};
+//! let mut gencmd = GencmdUnique::from_backend(MockBackend::new());
+//!
+//! let (temperature, frequency, throttled) =
+//!     gencmd.send_batch::<(CmdMeasureTemp, CmdMeasureClockArm, CmdGetThrottled)>();
+//! assert_eq!(temperature.unwrap(), 45.6);
+//! assert!(frequency.is_ok() && throttled.is_ok());
+//! ```
+
+use crate::{backend::GencmdBackend, error::GencmdCmdError};
+
+use super::{Command, Gencmd};
+
+/// A tuple of commands sent together.
+pub trait Batch {
+	/// Tuple of the results of the commands.
+	type Output;
+
+	fn send(gencmd: &mut Gencmd, instance: &mut (impl GencmdBackend + ?Sized)) -> Self::Output;
+}
+
+macro_rules! impl_batch {
+	($($command: ident => $response: ident),+) => {
+		impl<$($command, $response),+> Batch for ($($command,)+)
+		where
+			$($command: for<'a> Command<'a, Response = $response>,)+
+		{
+			type Output = ($(Result<$response, GencmdCmdError>,)+);
+
+			fn send(gencmd: &mut Gencmd, instance: &mut (impl GencmdBackend + ?Sized)) -> Self::Output {
+				($(gencmd.send_cmd::<$command>(instance),)+)
+			}
+		}
+	};
+}
+impl_batch!(C1 => R1);
+impl_batch!(C1 => R1, C2 => R2);
+impl_batch!(C1 => R1, C2 => R2, C3 => R3);
+impl_batch!(C1 => R1, C2 => R2, C3 => R3, C4 => R4);
+impl_batch!(C1 => R1, C2 => R2, C3 => R3, C4 => R4, C5 => R5);
+impl_batch!(C1 => R1, C2 => R2, C3 => R3, C4 => R4, C5 => R5, C6 => R6);
+impl_batch!(C1 => R1, C2 => R2, C3 => R3, C4 => R4, C5 => R5, C6 => R6, C7 => R7);
+impl_batch!(C1 => R1, C2 => R2, C3 => R3, C4 => R4, C5 => R5, C6 => R6, C7 => R7, C8 => R8);
+
+impl Gencmd {
+	/// Sends all commands of the batch `B` in order, returning their results.
+	pub fn send_batch<B: Batch>(
+		&mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+	) -> B::Output {
+		B::send(self, instance)
+	}
+
+	/// Sends raw `commands` in order, returning their owned responses.
+	pub fn send_batch_raw<'c>(
+		&mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+		commands: impl IntoIterator<Item = &'c str>,
+	) -> Vec<Result<String, GencmdCmdError>> {
+		commands
+			.into_iter()
+			.map(|command| self.send_cmd_raw(instance, command).map(str::to_string))
+			.collect()
+	}
+}
@@ -0,0 +1,280 @@
+//! Caching of responses which do not change or change slowly.
+//!
+//! Each [`Command`](super::Command) declares a [`CachePolicy`] and raw commands get one based on their name (see [`CachePolicy::for_command`]), both of which can be overridden.
+//! A [`ResponseCache`] can be shared between multiple [`CachedGencmd`]s so that static data is fetched once and sensor readings
+//! are deduplicated across callers within the TTL.
+
+use std::{
+	borrow::BorrowMut,
+	collections::HashMap,
+	ops::DerefMut,
+	sync::{Arc, Mutex, PoisonError},
+	time::{Duration, Instant},
+};
+
+#[cfg(global_instance_available)]
+use crate::global::GlobalInstance;
+use crate::{backend::GencmdBackend, error::GencmdCmdError};
+
+use super::{capabilities::Capabilities, Gencmd, OwnedCommand};
+
+/// TTL of sensor readings such as temperature and clocks.
+pub const SENSOR_TTL: Duration = Duration::from_millis(500);
+
+/// How long a response can be reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+	/// The response never changes while the system runs (e.g. the firmware version).
+	Static,
+	/// The response is reused for the duration.
+	Ttl(Duration),
+	/// The response is never reused.
+	Live,
+}
+impl CachePolicy {
+	/// Returns the built-in policy for a raw `command`.
+	pub fn for_command(command: &str) -> Self {
+		if command == "vcos version" {
+			return CachePolicy::Static;
+		}
+
+		match Capabilities::command_name(command) {
+			"commands" | "version" | "otp_dump" | "get_config" | "codec_enabled" | "get_mem" => {
+				CachePolicy::Static
+			}
+			"measure_temp" | "measure_clock" | "measure_volts" => CachePolicy::Ttl(SENSOR_TTL),
+			_ => CachePolicy::Live,
+		}
+	}
+
+	fn is_fresh(&self, fetched: Instant) -> bool {
+		match self {
+			CachePolicy::Static => true,
+			CachePolicy::Ttl(ttl) => fetched.elapsed() < *ttl,
+			CachePolicy::Live => false,
+		}
+	}
+}
+
+/// Responses shared by [`CachedGencmd`]s.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+	entries: Mutex<HashMap<String, (Arc<str>, Instant)>>,
+	overrides: HashMap<String, CachePolicy>,
+}
+impl ResponseCache {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Overrides the policy of `command`, which is either a full command (e.g. `measure_clock arm`) or just its name.
+	///
+	/// Full commands take precedence over names.
+	pub fn with_override(mut self, command: impl Into<String>, policy: CachePolicy) -> Self {
+		self.overrides.insert(command.into(), policy);
+		self
+	}
+
+	/// Returns the policy of `command`, using `default` if it has no override.
+	pub fn policy(&self, command: &str, default: CachePolicy) -> CachePolicy {
+		self.overrides
+			.get(command)
+			.or_else(|| self.overrides.get(Capabilities::command_name(command)))
+			.copied()
+			.unwrap_or(default)
+	}
+
+	/// Returns the cached response to `command` if it is still fresh according to `policy`.
+	pub fn get(&self, command: &str, policy: CachePolicy) -> Option<Arc<str>> {
+		let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+		entries
+			.get(command)
+			.filter(|(_, fetched)| policy.is_fresh(*fetched))
+			.map(|(response, _)| response.clone())
+	}
+
+	fn insert(&self, command: &str, response: Arc<str>) {
+		self.entries
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(command.to_string(), (response, Instant::now()));
+	}
+
+	/// Removes the cached response to `command`.
+	pub fn invalidate(&self, command: &str) {
+		self.entries
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.remove(command);
+	}
+
+	/// Removes all cached responses.
+	pub fn clear(&self) {
+		self.entries
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clear();
+	}
+}
+
+/// A [`Gencmd`] over a shared backend which answers from a [`ResponseCache`] when possible.
+///
+/// Error responses are never cached.
+#[cfg(global_instance_available)]
+pub struct CachedGencmd<G: BorrowMut<Gencmd> = Gencmd, B: GencmdBackend = GlobalInstance> {
+	gencmd: G,
+	backend: Arc<Mutex<B>>,
+	cache: Arc<ResponseCache>,
+}
+/// A [`Gencmd`] over a shared backend which answers from a [`ResponseCache`] when possible.
+///
+/// Error responses are never cached.
+#[cfg(not(global_instance_available))]
+pub struct CachedGencmd<G: BorrowMut<Gencmd>, B: GencmdBackend> {
+	gencmd: G,
+	backend: Arc<Mutex<B>>,
+	cache: Arc<ResponseCache>,
+}
+impl<B: GencmdBackend> CachedGencmd<Gencmd, B> {
+	/// Caches responses of `backend` in a new cache.
+	pub fn new(backend: Arc<Mutex<B>>) -> Self {
+		Self::with_cache(backend, Arc::new(ResponseCache::new()))
+	}
+
+	/// Uses `cache`, which can be shared with other callers of the same backend.
+	pub fn with_cache(backend: Arc<Mutex<B>>, cache: Arc<ResponseCache>) -> Self {
+		CachedGencmd {
+			gencmd: Gencmd::new(),
+			backend,
+			cache,
+		}
+	}
+}
+impl<G: BorrowMut<Gencmd>, B: GencmdBackend> CachedGencmd<G, B> {
+	pub fn cache(&self) -> &Arc<ResponseCache> {
+		&self.cache
+	}
+
+	/// Sends `command` unless a fresh response is cached, using its [built-in policy](CachePolicy::for_command) unless overridden.
+	pub fn send_cmd_raw(&mut self, command: &str) -> Result<Arc<str>, GencmdCmdError> {
+		self.send_with_policy(command, CachePolicy::for_command(command))
+	}
+
+	/// Sends the command unless a fresh response is cached, using [`Command::CACHE_POLICY`](super::Command::CACHE_POLICY) unless overridden.
+	///
+	/// Only [commands with owned responses](OwnedCommand) can be sent this way, e.g. `gencmd.send_cmd::<CmdMeasureTemp>()`.
+	/// Responses borrowing from the buffer can be parsed from the result of [`send_cmd_raw`](Self::send_cmd_raw) using [`Command::parse_response`](super::Command::parse_response).
+	pub fn send_cmd<C: OwnedCommand>(&mut self) -> Result<C::Output, GencmdCmdError> {
+		let response = self.send_with_policy(C::COMMAND_STR, C::CACHE_POLICY)?;
+
+		C::parse_response(&response)
+	}
+
+	fn send_with_policy(
+		&mut self,
+		command: &str,
+		default: CachePolicy,
+	) -> Result<Arc<str>, GencmdCmdError> {
+		let policy = self.cache.policy(command, default);
+		if let Some(response) = self.cache.get(command, policy) {
+			log::trace!("cached response to {:?}", command);
+			return Ok(response);
+		}
+
+		let mut lock = self.backend.lock().expect("mutex poisoned");
+
+		// another caller might have fetched it while we were waiting for the lock
+		if let Some(response) = self.cache.get(command, policy) {
+			return Ok(response);
+		}
+
+		let response: Arc<str> = self
+			.gencmd
+			.borrow_mut()
+			.send_cmd_raw(lock.deref_mut(), command)?
+			.into();
+		if policy != CachePolicy::Live {
+			self.cache.insert(command, response.clone());
+		}
+
+		Ok(response)
+	}
+}
+impl<B: GencmdBackend> Clone for CachedGencmd<Gencmd, B> {
+	/// Returns a wrapper with its own buffer, sharing the backend and the cache.
+	fn clone(&self) -> Self {
+		Self::with_cache(self.backend.clone(), self.cache.clone())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		ffi::CStr,
+		sync::{Arc, Mutex},
+		time::Duration,
+	};
+
+	use super::{CachePolicy, CachedGencmd, ResponseCache};
+	use crate::{
+		backend::{mock::MockBackend, GencmdBackend},
+		error::GencmdCmdError,
+		gencmd::commands::{CmdGetThrottled, CmdMeasureTemp},
+	};
+
+	/// Counts the commands reaching the backend.
+	struct CountingBackend {
+		sent: Vec<String>,
+		inner: MockBackend,
+	}
+	impl GencmdBackend for CountingBackend {
+		unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+			self.sent.push(command.to_str()?.to_string());
+			self.inner.send_command(command)
+		}
+
+		fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+			self.inner.retrieve_response(buffer)
+		}
+	}
+
+	#[test]
+	fn caches_according_to_policy() {
+		let backend = Arc::new(Mutex::new(CountingBackend {
+			sent: Vec::new(),
+			inner: MockBackend::new(),
+		}));
+		let cache = Arc::new(
+			ResponseCache::new()
+				.with_override("measure_temp", CachePolicy::Ttl(Duration::from_millis(50))),
+		);
+		let mut first = CachedGencmd::with_cache(backend.clone(), cache.clone());
+		let mut second = first.clone();
+
+		for gencmd in [&mut first, &mut second] {
+			assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 45.6);
+			assert!(gencmd.send_cmd::<CmdGetThrottled>().is_ok());
+			assert!(gencmd.send_cmd_raw("vcos version").is_ok());
+		}
+		assert!(first.send_cmd_raw("not_a_command").is_err());
+		assert!(first.send_cmd_raw("not_a_command").is_err());
+
+		std::thread::sleep(Duration::from_millis(60));
+		assert_eq!(second.send_cmd::<CmdMeasureTemp>().unwrap(), 45.6);
+		assert!(first.send_cmd_raw("vcos version").is_ok());
+
+		assert_eq!(
+			backend.lock().unwrap().sent,
+			[
+				"measure_temp",
+				"get_throttled",
+				"vcos version",
+				"get_throttled",
+				"not_a_command",
+				"not_a_command",
+				"measure_temp"
+			]
+		);
+	}
+}
@@ -0,0 +1,158 @@
+//! Discovery of commands supported by the running firmware.
+//!
+//! Different boards and firmware versions register different commands. A [`Capabilities`] object records
+//! the response of [`CmdCommands`](super::commands::CmdCommands) so that unsupported commands can be rejected without a round trip.
+
+use std::{collections::HashSet, sync::Arc};
+
+use super::Command;
+
+/// Set of commands supported by the firmware.
+///
+/// Cloning is cheap as the set is shared.
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+	commands: Arc<HashSet<String>>,
+}
+impl Capabilities {
+	pub fn from_commands<'a>(commands: impl IntoIterator<Item = &'a str>) -> Self {
+		Capabilities {
+			commands: Arc::new(commands.into_iter().map(str::to_string).collect()),
+		}
+	}
+
+	/// Returns the command name, that is the first word of `command`.
+	pub fn command_name(command: &str) -> &str {
+		command.split_whitespace().next().unwrap_or("")
+	}
+
+	/// Returns true if the firmware supports `command`.
+	///
+	/// Only the command name is checked, arguments are ignored.
+	pub fn supports_command(&self, command: &str) -> bool {
+		self.commands.contains(Self::command_name(command))
+	}
+
+	/// Returns true if the firmware supports the typed command `C`.
+	pub fn supports<'a, C: Command<'a>>(&self) -> bool {
+		self.supports_command(C::COMMAND_STR)
+	}
+
+	pub fn commands(&self) -> impl Iterator<Item = &str> {
+		self.commands.iter().map(String::as_str)
+	}
+
+	pub fn len(&self) -> usize {
+		self.commands.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.commands.is_empty()
+	}
+}
+impl<'a> From<Vec<&'a str>> for Capabilities {
+	/// Converts the response of [`CmdCommands`](super::commands::CmdCommands).
+	fn from(value: Vec<&'a str>) -> Self {
+		Capabilities::from_commands(value)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		ffi::CStr,
+		sync::{
+			atomic::{AtomicU32, Ordering},
+			Arc, OnceLock,
+		},
+	};
+
+	use super::Capabilities;
+	use crate::{
+		backend::{mock::MockBackend, GencmdBackend},
+		error::GencmdCmdError,
+		gencmd::{
+			commands::{CmdGetThrottled, CmdMeasureClockArm, CmdMeasureTemp, CmdVcosVersion},
+			Gencmd,
+		},
+	};
+
+	/// Mock backend keeping shared capabilities, counting how often they are fetched.
+	struct SharingBackend {
+		backend: MockBackend,
+		capabilities: OnceLock<Capabilities>,
+		fetches: Arc<AtomicU32>,
+	}
+	impl GencmdBackend for SharingBackend {
+		unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+			if command.to_bytes() == b"commands" {
+				self.fetches.fetch_add(1, Ordering::Relaxed);
+			}
+
+			self.backend.send_command(command)
+		}
+
+		fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+			self.backend.retrieve_response(buffer)
+		}
+
+		fn shared_capabilities(&self) -> Option<&OnceLock<Capabilities>> {
+			Some(&self.capabilities)
+		}
+	}
+
+	#[test]
+	fn supports_typed_commands_by_name() {
+		let capabilities = Capabilities::from_commands(["vcos", "measure_clock"]);
+
+		assert!(capabilities.supports::<CmdMeasureClockArm>());
+		assert!(capabilities.supports::<CmdVcosVersion>());
+		assert!(!capabilities.supports::<CmdGetThrottled>());
+	}
+
+	#[test]
+	fn ignores_arguments() {
+		let capabilities = Capabilities::from_commands(["get_config"]);
+
+		assert!(capabilities.supports_command("  get_config int"));
+		assert!(!capabilities.supports_command("get_configs"));
+		assert!(!capabilities.supports_command(""));
+	}
+
+	#[test]
+	fn shares_capabilities_of_backend() {
+		let fetches = Arc::new(AtomicU32::new(0));
+		let mut backend = SharingBackend {
+			backend: MockBackend::with_responder(|command| match command {
+				"commands" => "commands=\"commands, measure_temp\"".to_string(),
+				"measure_temp" => "temp=45.6'C".to_string(),
+				_ => "error=1 error_msg=\"command not registered\"".to_string(),
+			}),
+			capabilities: OnceLock::new(),
+			fetches: fetches.clone(),
+		};
+
+		// typed commands are checked without loading the capabilities explicitly
+		let mut first = Gencmd::new();
+		assert_eq!(
+			first.send_cmd::<CmdMeasureTemp>(&mut backend).unwrap(),
+			45.6
+		);
+		assert!(matches!(
+			first.send_cmd::<CmdGetThrottled>(&mut backend),
+			Err(GencmdCmdError::Unsupported(_))
+		));
+
+		let mut second = Gencmd::new();
+		assert!(second
+			.load_capabilities(&mut backend)
+			.unwrap()
+			.supports::<CmdMeasureTemp>());
+		assert!(matches!(
+			second.send_cmd_raw(&mut backend, "get_throttled"),
+			Err(GencmdCmdError::Unsupported(_))
+		));
+
+		assert_eq!(fetches.load(Ordering::Relaxed), 1);
+	}
+}
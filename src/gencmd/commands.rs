@@ -2,8 +2,12 @@
 //!
 //! More commands should be implemented on demand.
 
+use thiserror::Error;
+
 use super::{
+	cache::{CachePolicy, SENSOR_TTL},
 	response::{self, IntRadix},
+	throttle::ThrottleCondition,
 	Command, GencmdCmdError,
 };
 
@@ -15,6 +19,7 @@ impl<'a> Command<'a> for CmdCommands {
 	type Response = Vec<&'a str>;
 
 	const COMMAND_STR: &'static str = "commands";
+	const CACHE_POLICY: CachePolicy = CachePolicy::Static;
 
 	fn parse_response(response: &'a str) -> Result<Self::Response, GencmdCmdError> {
 		let (_, commands) = response::parse_field_simple::<&str>(response, "commands")
@@ -29,6 +34,7 @@ impl<'a> Command<'a> for CmdMeasureTemp {
 	type Response = f32;
 
 	const COMMAND_STR: &'static str = "measure_temp";
+	const CACHE_POLICY: CachePolicy = CachePolicy::Ttl(SENSOR_TTL);
 
 	fn parse_response(response: &'a str) -> Result<Self::Response, GencmdCmdError> {
 		let (_, temperature) = response::parse_field::<f32>(response, "temp", None, Some("'C"))
@@ -43,6 +49,7 @@ impl<'a> Command<'a> for CmdMeasureClockArm {
 	type Response = u64;
 
 	const COMMAND_STR: &'static str = "measure_clock arm";
+	const CACHE_POLICY: CachePolicy = CachePolicy::Ttl(SENSOR_TTL);
 
 	fn parse_response(response: &'a str) -> Result<Self::Response, GencmdCmdError> {
 		let (_, frequency) = response::parse_field_simple::<u64>(response, "frequency(48)")
@@ -52,7 +59,7 @@ impl<'a> Command<'a> for CmdMeasureClockArm {
 	}
 }
 
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
 pub struct ThrottleStatus {
 	pub under_voltage: bool,
@@ -66,7 +73,11 @@ impl ThrottleStatus {
 	pub const BIT_FREQUENCT_CAPPED: u32 = 2;
 	pub const BIT_THROTTLED: u32 = 4;
 	pub const BIT_SOFT_TEMPERATURE_LIMIT: u32 = 8;
-	pub const SHIFT_OCCURED: usize = 16;
+	pub const SHIFT_OCCURRED: usize = 16;
+	#[deprecated(note = "renamed to `SHIFT_OCCURRED`")]
+	pub const SHIFT_OCCURED: usize = Self::SHIFT_OCCURRED;
+	/// All bits of the current status known to this crate.
+	pub const MASK: u32 = Self::BIT_UNDER_VOLTAGE | Self::BIT_FREQUENCT_CAPPED | Self::BIT_THROTTLED | Self::BIT_SOFT_TEMPERATURE_LIMIT;
 }
 impl ThrottleStatus {
 	pub fn from_current(value: u32) -> Self {
@@ -78,8 +89,13 @@ impl ThrottleStatus {
 		}
 	}
 
+	pub fn from_occurred(value: u32) -> Self {
+		Self::from_current(value >> Self::SHIFT_OCCURRED)
+	}
+
+	#[deprecated(note = "renamed to `from_occurred`")]
 	pub fn from_occured(value: u32) -> Self {
-		Self::from_current(value >> Self::SHIFT_OCCURED)
+		Self::from_occurred(value)
 	}
 
 	pub fn to_current(self) -> u32 {
@@ -89,28 +105,279 @@ impl ThrottleStatus {
 			| (self.soft_temperature_limit as u32) * Self::BIT_SOFT_TEMPERATURE_LIMIT
 	}
 
+	pub fn to_occurred(self) -> u32 {
+		self.to_current() << Self::SHIFT_OCCURRED
+	}
+
+	#[deprecated(note = "renamed to `to_occurred`")]
 	pub fn to_occured(self) -> u32 {
-		self.to_current() << Self::SHIFT_OCCURED
+		self.to_occurred()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.to_current() == 0
 	}
 }
+impl std::fmt::Display for ThrottleStatus {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if self.is_empty() {
+			return f.write_str("none");
+		}
 
-#[derive(Debug, Default)]
-#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+		let mut first = true;
+		for flag in ThrottleFlag::CURRENT.iter() {
+			if self.to_current() & flag.bit() != 0 {
+				if !first {
+					f.write_str(", ")?;
+				}
+				f.write_str(flag.condition_name())?;
+				first = false;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// A single bit of the [`CmdGetThrottled`] response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThrottleFlag {
+	UnderVoltage,
+	FrequencyCapped,
+	Throttled,
+	SoftTemperatureLimit,
+	UnderVoltageOccurred,
+	FrequencyCappedOccurred,
+	ThrottledOccurred,
+	SoftTemperatureLimitOccurred,
+}
+impl ThrottleFlag {
+	pub const ALL: [ThrottleFlag; 8] = [
+		ThrottleFlag::UnderVoltage,
+		ThrottleFlag::FrequencyCapped,
+		ThrottleFlag::Throttled,
+		ThrottleFlag::SoftTemperatureLimit,
+		ThrottleFlag::UnderVoltageOccurred,
+		ThrottleFlag::FrequencyCappedOccurred,
+		ThrottleFlag::ThrottledOccurred,
+		ThrottleFlag::SoftTemperatureLimitOccurred,
+	];
+	/// The flags reporting current conditions, in the order of [`ThrottleFlag::ALL`].
+	pub const CURRENT: [ThrottleFlag; 4] = [
+		ThrottleFlag::UnderVoltage,
+		ThrottleFlag::FrequencyCapped,
+		ThrottleFlag::Throttled,
+		ThrottleFlag::SoftTemperatureLimit,
+	];
+
+	pub const fn bit(&self) -> u32 {
+		match self {
+			ThrottleFlag::UnderVoltage => ThrottleStatus::BIT_UNDER_VOLTAGE,
+			ThrottleFlag::FrequencyCapped => ThrottleStatus::BIT_FREQUENCT_CAPPED,
+			ThrottleFlag::Throttled => ThrottleStatus::BIT_THROTTLED,
+			ThrottleFlag::SoftTemperatureLimit => ThrottleStatus::BIT_SOFT_TEMPERATURE_LIMIT,
+			ThrottleFlag::UnderVoltageOccurred => {
+				ThrottleStatus::BIT_UNDER_VOLTAGE << ThrottleStatus::SHIFT_OCCURRED
+			}
+			ThrottleFlag::FrequencyCappedOccurred => {
+				ThrottleStatus::BIT_FREQUENCT_CAPPED << ThrottleStatus::SHIFT_OCCURRED
+			}
+			ThrottleFlag::ThrottledOccurred => {
+				ThrottleStatus::BIT_THROTTLED << ThrottleStatus::SHIFT_OCCURRED
+			}
+			ThrottleFlag::SoftTemperatureLimitOccurred => {
+				ThrottleStatus::BIT_SOFT_TEMPERATURE_LIMIT << ThrottleStatus::SHIFT_OCCURRED
+			}
+		}
+	}
+
+	/// Returns true if this flag reports a condition that has occurred since boot rather than a current one.
+	pub const fn is_occurred(&self) -> bool {
+		self.bit() > ThrottleStatus::MASK
+	}
+
+	pub const fn condition(&self) -> ThrottleCondition {
+		match self {
+			ThrottleFlag::UnderVoltage | ThrottleFlag::UnderVoltageOccurred => {
+				ThrottleCondition::UnderVoltage
+			}
+			ThrottleFlag::FrequencyCapped | ThrottleFlag::FrequencyCappedOccurred => {
+				ThrottleCondition::FrequencyCapped
+			}
+			ThrottleFlag::Throttled | ThrottleFlag::ThrottledOccurred => {
+				ThrottleCondition::Throttled
+			}
+			ThrottleFlag::SoftTemperatureLimit | ThrottleFlag::SoftTemperatureLimitOccurred => {
+				ThrottleCondition::SoftTemperatureLimit
+			}
+		}
+	}
+
+	/// Machine-friendly name of the flag, also used by serde.
+	pub const fn as_str(&self) -> &'static str {
+		match self {
+			ThrottleFlag::UnderVoltage => "under_voltage",
+			ThrottleFlag::FrequencyCapped => "frequency_capped",
+			ThrottleFlag::Throttled => "throttled",
+			ThrottleFlag::SoftTemperatureLimit => "soft_temperature_limit",
+			ThrottleFlag::UnderVoltageOccurred => "under_voltage_occurred",
+			ThrottleFlag::FrequencyCappedOccurred => "frequency_capped_occurred",
+			ThrottleFlag::ThrottledOccurred => "throttled_occurred",
+			ThrottleFlag::SoftTemperatureLimitOccurred => "soft_temperature_limit_occurred",
+		}
+	}
+
+	/// Human-readable description of the flag.
+	pub const fn description(&self) -> &'static str {
+		match self {
+			ThrottleFlag::UnderVoltage => "under-voltage detected",
+			ThrottleFlag::FrequencyCapped => "arm frequency capped",
+			ThrottleFlag::Throttled => "currently throttled",
+			ThrottleFlag::SoftTemperatureLimit => "soft temperature limit active",
+			ThrottleFlag::UnderVoltageOccurred => "under-voltage has occurred",
+			ThrottleFlag::FrequencyCappedOccurred => "arm frequency capping has occurred",
+			ThrottleFlag::ThrottledOccurred => "throttling has occurred",
+			ThrottleFlag::SoftTemperatureLimitOccurred => "soft temperature limit has occurred",
+		}
+	}
+
+	const fn condition_name(&self) -> &'static str {
+		match self.condition() {
+			ThrottleCondition::UnderVoltage => "under-voltage",
+			ThrottleCondition::FrequencyCapped => "frequency capped",
+			ThrottleCondition::Throttled => "throttled",
+			ThrottleCondition::SoftTemperatureLimit => "soft temperature limit",
+		}
+	}
+}
+impl std::str::FromStr for ThrottleFlag {
+	type Err = ThrottleParseError;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		ThrottleFlag::ALL
+			.into_iter()
+			.find(|flag| flag.as_str() == value)
+			.ok_or_else(|| ThrottleParseError::UnknownFlag(value.to_string()))
+	}
+}
+impl std::fmt::Display for ThrottleFlag {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.description())
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum ThrottleParseError {
+	#[error("Unknown throttle flag: {0}")]
+	UnknownFlag(String),
+}
+
+/// Response of [`CmdGetThrottled`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct CpuThrottled {
 	pub current: ThrottleStatus,
 	pub occured: ThrottleStatus,
+	/// Bits not known to this crate (reserved or added by newer firmware), kept in their original positions.
+	pub unknown: u32,
+}
+impl CpuThrottled {
+	pub const KNOWN_MASK: u32 =
+		ThrottleStatus::MASK | ThrottleStatus::MASK << ThrottleStatus::SHIFT_OCCURRED;
+
+	pub fn is_set(&self, flag: ThrottleFlag) -> bool {
+		u32::from(*self) & flag.bit() != 0
+	}
+
+	/// Iterates over the known flags that are set.
+	pub fn flags(&self) -> impl Iterator<Item = ThrottleFlag> {
+		let value = u32::from(*self);
+
+		ThrottleFlag::ALL
+			.into_iter()
+			.filter(move |flag| value & flag.bit() != 0)
+	}
+
+	/// Iterates over positions of unknown bits that are set.
+	pub fn unknown_bits(&self) -> impl Iterator<Item = u32> {
+		let unknown = self.unknown;
+
+		(0..u32::BITS).filter(move |bit| unknown & (1 << bit) != 0)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		u32::from(*self) == 0
+	}
 }
 impl From<u32> for CpuThrottled {
 	fn from(value: u32) -> Self {
 		CpuThrottled {
 			current: ThrottleStatus::from_current(value),
-			occured: ThrottleStatus::from_occured(value),
+			occured: ThrottleStatus::from_occurred(value),
+			unknown: value & !Self::KNOWN_MASK,
 		}
 	}
 }
 impl From<CpuThrottled> for u32 {
 	fn from(value: CpuThrottled) -> Self {
-		value.current.to_current() | value.occured.to_occured()
+		value.current.to_current() | value.occured.to_occurred() | value.unknown
+	}
+}
+impl std::fmt::Display for CpuThrottled {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if self.is_empty() {
+			return f.write_str("no throttling");
+		}
+
+		write!(f, "current: {}; occurred: {}", self.current, self.occured)?;
+
+		if self.unknown != 0 {
+			write!(f, "; unknown bits: 0x{:X}", self.unknown)?;
+		}
+
+		Ok(())
+	}
+}
+#[cfg(feature = "serde_models")]
+impl Serialize for CpuThrottled {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		use serde::ser::SerializeSeq;
+
+		let mut seq = serializer.serialize_seq(None)?;
+		for flag in self.flags() {
+			seq.serialize_element(flag.as_str())?;
+		}
+		for bit in self.unknown_bits() {
+			seq.serialize_element(&format!("bit_{}", bit))?;
+		}
+
+		seq.end()
+	}
+}
+#[cfg(feature = "serde_models")]
+impl<'de> Deserialize<'de> for CpuThrottled {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		use serde::de::Error;
+
+		let names = Vec::<std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+
+		let mut value = 0u32;
+		for name in names {
+			let bit = match name.strip_prefix("bit_") {
+				Some(bit) => bit
+					.parse::<u32>()
+					.ok()
+					.filter(|bit| *bit < u32::BITS)
+					.map(|bit| 1 << bit)
+					.ok_or_else(|| D::Error::custom(format!("invalid bit: {}", name)))?,
+				None => name
+					.parse::<ThrottleFlag>()
+					.map_err(D::Error::custom)?
+					.bit(),
+			};
+			value |= bit;
+		}
+
+		Ok(CpuThrottled::from(value))
 	}
 }
 
@@ -128,3 +395,250 @@ impl<'a> Command<'a> for CmdGetThrottled {
 		Ok(CpuThrottled::from(throttled.0))
 	}
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub struct VcosVersion<'a> {
+	/// Firmware build date and time, e.g. `Mar 17 2023 10:50:39`.
+	pub build_date: &'a str,
+	pub copyright: &'a str,
+	/// Version hash with optional build flags, e.g. `82f3750a65fadae9a38077e3c2e217ad158c8d54 (clean)`.
+	pub version: &'a str,
+	#[cfg_attr(feature = "serde_models", serde(borrow))]
+	pub host: Option<&'a str>,
+}
+
+#[derive(Error, Debug)]
+pub enum VcosParseError {
+	#[error("Response is missing the `{0}` line")]
+	MissingLine(&'static str),
+	#[error("Invalid log line: {0}")]
+	InvalidLogLine(String),
+	#[error("Invalid log level: {0}")]
+	InvalidLogLevel(String),
+}
+
+pub struct CmdVcosVersion;
+impl<'a> Command<'a> for CmdVcosVersion {
+	type Response = VcosVersion<'a>;
+
+	const COMMAND_STR: &'static str = "vcos version";
+	const CACHE_POLICY: CachePolicy = CachePolicy::Static;
+
+	fn parse_response(response: &'a str) -> Result<Self::Response, GencmdCmdError> {
+		// Mar 17 2023 10:50:39
+		// Copyright (c) 2011 Broadcom
+		// version 82f3750a65fadae9a38077e3c2e217ad158c8d54 (clean)
+		// host buildbot
+		let mut lines = response
+			.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty());
+
+		let build_date = lines
+			.next()
+			.ok_or(VcosParseError::MissingLine("build date"))
+			.map_err(GencmdCmdError::from_invalid_format)?;
+
+		let mut copyright = None;
+		let mut version = None;
+		let mut host = None;
+		for line in lines {
+			if line.starts_with("Copyright") {
+				copyright = Some(line);
+			} else if let Some(value) = line.strip_prefix("version ") {
+				version = Some(value.trim_start());
+			} else if let Some(value) = line.strip_prefix("host ") {
+				host = Some(value.trim_start());
+			}
+		}
+
+		Ok(VcosVersion {
+			build_date,
+			copyright: copyright
+				.ok_or(VcosParseError::MissingLine("copyright"))
+				.map_err(GencmdCmdError::from_invalid_format)?,
+			version: version
+				.ok_or(VcosParseError::MissingLine("version"))
+				.map_err(GencmdCmdError::from_invalid_format)?,
+			host,
+		})
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub enum VcosLogLevel {
+	Uninitialized,
+	Never,
+	Error,
+	Warn,
+	Info,
+	Trace,
+}
+impl VcosLogLevel {
+	/// Returns the level name as printed by vcos.
+	pub const fn as_str(&self) -> &'static str {
+		match self {
+			VcosLogLevel::Uninitialized => "uninit",
+			VcosLogLevel::Never => "never",
+			VcosLogLevel::Error => "error",
+			VcosLogLevel::Warn => "warn",
+			VcosLogLevel::Info => "info",
+			VcosLogLevel::Trace => "trace",
+		}
+	}
+}
+impl std::str::FromStr for VcosLogLevel {
+	type Err = VcosParseError;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let level = match value {
+			"uninit" => VcosLogLevel::Uninitialized,
+			"never" => VcosLogLevel::Never,
+			"error" => VcosLogLevel::Error,
+			"warn" => VcosLogLevel::Warn,
+			"info" => VcosLogLevel::Info,
+			"trace" => VcosLogLevel::Trace,
+			_ => return Err(VcosParseError::InvalidLogLevel(value.to_string())),
+		};
+
+		Ok(level)
+	}
+}
+impl std::fmt::Display for VcosLogLevel {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub struct VcosLogCategory<'a> {
+	pub name: &'a str,
+	pub level: VcosLogLevel,
+}
+
+pub struct CmdVcosLogStatus;
+impl<'a> Command<'a> for CmdVcosLogStatus {
+	type Response = Vec<VcosLogCategory<'a>>;
+
+	const COMMAND_STR: &'static str = "vcos log status";
+
+	fn parse_response(response: &'a str) -> Result<Self::Response, GencmdCmdError> {
+		// each line is "%-*s - %s" with the category name padded to the longest one
+		response
+			.lines()
+			.filter(|line| !line.trim().is_empty())
+			.map(|line| {
+				let (name, level) = line
+					.rsplit_once(" - ")
+					.ok_or_else(|| VcosParseError::InvalidLogLine(line.to_string()))?;
+
+				Ok(VcosLogCategory {
+					name: name.trim(),
+					level: level.trim().parse()?,
+				})
+			})
+			.collect::<Result<_, VcosParseError>>()
+			.map_err(GencmdCmdError::from_invalid_format)
+	}
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub struct VcosHeapUsage {
+	/// Total heap size in bytes.
+	pub total: u64,
+	/// Bytes currently allocated.
+	pub used: u64,
+	/// Bytes currently free.
+	pub free: u64,
+	/// Size of the largest contiguous free block in bytes.
+	pub largest_free: u64,
+}
+
+pub struct CmdVcosHeap;
+impl<'a> Command<'a> for CmdVcosHeap {
+	type Response = VcosHeapUsage;
+
+	const COMMAND_STR: &'static str = "vcos heap";
+
+	fn parse_response(response: &'a str) -> Result<Self::Response, GencmdCmdError> {
+		let (response, total) = response::parse_field_simple::<u64>(response, "total")
+			.map_err(GencmdCmdError::from_invalid_format)?;
+		let (response, used) = response::parse_field_simple::<u64>(response, "used")
+			.map_err(GencmdCmdError::from_invalid_format)?;
+		let (response, free) = response::parse_field_simple::<u64>(response, "free")
+			.map_err(GencmdCmdError::from_invalid_format)?;
+		let (_, largest_free) = response::parse_field_simple::<u64>(response, "largest_free")
+			.map_err(GencmdCmdError::from_invalid_format)?;
+
+		Ok(VcosHeapUsage {
+			total,
+			used,
+			free,
+			largest_free,
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{CpuThrottled, ThrottleFlag};
+
+	#[test]
+	fn cpu_throttled_round_trips_unknown_bits() {
+		let value = 0xE0_0F_80_05;
+
+		let throttled = CpuThrottled::from(value);
+
+		assert_eq!(throttled.unknown, 0xE0_00_80_00);
+		assert_eq!(u32::from(throttled), value);
+	}
+
+	#[test]
+	fn cpu_throttled_lists_set_flags() {
+		let throttled = CpuThrottled::from(0x50005);
+
+		assert_eq!(
+			throttled.flags().collect::<Vec<_>>(),
+			vec![
+				ThrottleFlag::UnderVoltage,
+				ThrottleFlag::Throttled,
+				ThrottleFlag::UnderVoltageOccurred,
+				ThrottleFlag::ThrottledOccurred
+			]
+		);
+		assert!(throttled.is_set(ThrottleFlag::ThrottledOccurred));
+		assert!(!throttled.is_set(ThrottleFlag::FrequencyCapped));
+	}
+
+	#[test]
+	fn cpu_throttled_displays_conditions() {
+		assert_eq!(CpuThrottled::from(0x0).to_string(), "no throttling");
+		assert_eq!(
+			CpuThrottled::from(0x80050005).to_string(),
+			"current: under-voltage, throttled; occurred: under-voltage, throttled; unknown bits: 0x80000000"
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "serde_models")]
+	fn cpu_throttled_serializes_flag_names() {
+		use serde_test::{assert_tokens, Token};
+
+		assert_tokens(
+			&CpuThrottled::from(0x80050005),
+			&[
+				Token::Seq { len: None },
+				Token::Str("under_voltage"),
+				Token::Str("throttled"),
+				Token::Str("under_voltage_occurred"),
+				Token::Str("throttled_occurred"),
+				Token::Str("bit_31"),
+				Token::SeqEnd,
+			],
+		);
+	}
+}
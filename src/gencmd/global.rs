@@ -2,20 +2,33 @@ use std::{
 	borrow::BorrowMut,
 	ops::DerefMut,
 	sync::{Arc, Mutex},
+	time::Duration,
 };
 
+#[cfg(global_instance_available)]
+use crate::global::GlobalInstance;
 use crate::{
-	error::{GencmdCmdError, GencmdInitError},
-	gencmd::{Command, Gencmd},
-	global::GlobalInstance,
+	backend::GencmdBackend,
+	error::GencmdCmdError,
+	gencmd::{
+		batch::Batch,
+		capabilities::Capabilities,
+		retry::{Retried, RetryPolicy},
+		Command, Gencmd,
+	},
 };
 
-pub struct GencmdGlobal<
-	G: BorrowMut<Gencmd> = Gencmd,
-	I: BorrowMut<GlobalInstance> = GlobalInstance,
->(pub G, pub Arc<Mutex<I>>);
+#[cfg(global_instance_available)]
+pub struct GencmdGlobal<G: BorrowMut<Gencmd> = Gencmd, B: GencmdBackend = GlobalInstance>(
+	pub G,
+	pub Arc<Mutex<B>>,
+);
+/// Without the global instance there is no default backend, see [`from_backend`](Self::from_backend).
+#[cfg(not(global_instance_available))]
+pub struct GencmdGlobal<G: BorrowMut<Gencmd>, B: GencmdBackend>(pub G, pub Arc<Mutex<B>>);
+#[cfg(global_instance_available)]
 impl GencmdGlobal {
-	pub fn new() -> Result<Self, GencmdInitError> {
+	pub fn new() -> Result<Self, crate::error::GencmdInitError> {
 		let instance = GlobalInstance::instance::<128>()?;
 
 		let gencmd = Gencmd::new();
@@ -23,30 +36,132 @@ impl GencmdGlobal {
 		Ok(GencmdGlobal(gencmd, instance))
 	}
 }
-impl<G: BorrowMut<Gencmd>, I: BorrowMut<GlobalInstance>> GencmdGlobal<G, I> {
+impl<B: GencmdBackend> GencmdGlobal<Gencmd, B> {
+	pub fn from_backend(backend: Arc<Mutex<B>>) -> Self {
+		GencmdGlobal(Gencmd::new(), backend)
+	}
+}
+impl<G: BorrowMut<Gencmd>, B: GencmdBackend> GencmdGlobal<G, B> {
 	pub fn send_cmd_raw(&mut self, command: &str) -> Result<&str, GencmdCmdError> {
 		let mut lock = self.1.lock().expect("mutex poisoned");
 
+		self.0.borrow_mut().send_cmd_raw(lock.deref_mut(), command)
+	}
+
+	pub fn send_cmd<'a, C: Command<'a>>(&'a mut self) -> Result<C::Response, GencmdCmdError> {
+		let mut lock = self.1.lock().expect("mutex poisoned");
+
+		self.0.borrow_mut().send_cmd::<C>(lock.deref_mut())
+	}
+
+	/// See [`Gencmd::send_cmd_raw_timeout`].
+	pub fn send_cmd_raw_timeout(
+		&mut self,
+		command: &str,
+		timeout: Duration,
+	) -> Result<&str, GencmdCmdError> {
+		let mut lock = self.1.lock().expect("mutex poisoned");
+
 		self.0
 			.borrow_mut()
-			.send_cmd_raw(lock.deref_mut().borrow_mut(), command)
+			.send_cmd_raw_timeout(lock.deref_mut(), command, timeout)
 	}
 
-	pub fn send_cmd<'a, C: Command<'a>>(&'a mut self) -> Result<C::Response, GencmdCmdError> {
+	/// See [`Gencmd::send_cmd_timeout`].
+	pub fn send_cmd_timeout<'a, C: Command<'a>>(
+		&'a mut self,
+		timeout: Duration,
+	) -> Result<C::Response, GencmdCmdError> {
+		let mut lock = self.1.lock().expect("mutex poisoned");
+
+		self.0
+			.borrow_mut()
+			.send_cmd_timeout::<C>(lock.deref_mut(), timeout)
+	}
+
+	/// See [`Gencmd::send_cmd_raw_retry`].
+	///
+	/// The instance is only locked during the attempts, not while waiting between them.
+	pub fn send_cmd_raw_retry(
+		&mut self,
+		command: &str,
+		policy: &RetryPolicy,
+	) -> Result<Retried<&str>, GencmdCmdError> {
+		let gencmd = self.0.borrow_mut();
+		let Retried {
+			value: len,
+			attempts,
+		} = policy.run(|| {
+			let mut lock = self.1.lock().expect("mutex poisoned");
+
+			gencmd.exchange(lock.deref_mut(), command, None)
+		})?;
+
+		Ok(Retried {
+			value: gencmd.response(len)?,
+			attempts,
+		})
+	}
+
+	/// See [`Gencmd::send_cmd_retry`].
+	pub fn send_cmd_retry<'a, C: Command<'a>>(
+		&'a mut self,
+		policy: &RetryPolicy,
+	) -> Result<Retried<C::Response>, GencmdCmdError> {
+		{
+			let mut lock = self.1.lock().expect("mutex poisoned");
+			self.0.borrow_mut().check_supported::<C>(lock.deref_mut())?;
+		}
+		let Retried { value, attempts } = self.send_cmd_raw_retry(C::COMMAND_STR, policy)?;
+
+		Ok(Retried {
+			value: C::parse_response(value)?,
+			attempts,
+		})
+	}
+
+	/// See [`Gencmd::send_batch`].
+	///
+	/// The instance is locked once for the whole batch.
+	pub fn send_batch<C: Batch>(&mut self) -> C::Output {
+		let mut lock = self.1.lock().expect("mutex poisoned");
+
+		self.0.borrow_mut().send_batch::<C>(lock.deref_mut())
+	}
+
+	/// See [`Gencmd::send_batch_raw`].
+	///
+	/// The instance is locked once for the whole batch.
+	pub fn send_batch_raw<'c>(
+		&mut self,
+		commands: impl IntoIterator<Item = &'c str>,
+	) -> Vec<Result<String, GencmdCmdError>> {
 		let mut lock = self.1.lock().expect("mutex poisoned");
 
 		self.0
 			.borrow_mut()
-			.send_cmd::<C>(lock.deref_mut().borrow_mut())
+			.send_batch_raw(lock.deref_mut(), commands)
+	}
+
+	/// See [`Gencmd::load_capabilities`].
+	pub fn load_capabilities(&mut self) -> Result<&Capabilities, GencmdCmdError> {
+		let mut lock = self.1.lock().expect("mutex poisoned");
+
+		self.0.borrow_mut().load_capabilities(lock.deref_mut())
 	}
 }
 
-#[cfg(test)]
+#[cfg(all(test, global_instance_available))]
 mod test {
-	use crate::gencmd::global::GencmdGlobal;
+	use crate::{
+		backend::GencmdBackend,
+		error::GencmdCmdError,
+		gencmd::{capabilities::Capabilities, global::GencmdGlobal},
+	};
 
 	use crate::gencmd::commands::{
-		CmdCommands, CmdGetThrottled, CmdMeasureClockArm, CmdMeasureTemp,
+		CmdCommands, CmdGetThrottled, CmdMeasureClockArm, CmdMeasureTemp, CmdVcosHeap,
+		CmdVcosLogStatus, CmdVcosVersion, VcosLogLevel,
 	};
 
 	#[test]
@@ -94,6 +209,283 @@ mod test {
 		dbg!(gencmd.send_cmd::<CmdGetThrottled>()).unwrap();
 	}
 
+	#[test]
+	fn test_cmd_vcos_version() {
+		crate::test::setup_global();
+
+		let mut gencmd = GencmdGlobal::new().unwrap();
+
+		let version = dbg!(gencmd.send_cmd::<CmdVcosVersion>()).unwrap();
+
+		assert!(!version.build_date.is_empty());
+		assert!(!version.version.is_empty());
+	}
+
+	#[test]
+	fn test_cmd_vcos_log_status() {
+		crate::test::setup_global();
+
+		let mut gencmd = GencmdGlobal::new().unwrap();
+
+		let categories = dbg!(gencmd.send_cmd::<CmdVcosLogStatus>()).unwrap();
+
+		assert!(categories
+			.iter()
+			.any(|category| category.name == "mmal" && category.level == VcosLogLevel::Error));
+	}
+
+	#[test]
+	fn test_cmd_vcos_heap() {
+		crate::test::setup_global();
+
+		let mut gencmd = GencmdGlobal::new().unwrap();
+
+		let heap = dbg!(gencmd.send_cmd::<CmdVcosHeap>()).unwrap();
+
+		assert_eq!(heap.used + heap.free, heap.total);
+		assert!(heap.largest_free <= heap.free);
+	}
+
+	#[test]
+	fn test_capabilities_reject_unsupported() {
+		crate::test::setup_global();
+
+		let mut gencmd = GencmdGlobal::new().unwrap();
+
+		let capabilities = gencmd.load_capabilities().unwrap();
+		assert!(capabilities.supports::<CmdMeasureTemp>());
+
+		// loaded once for the instance, not per wrapper
+		let other = GencmdGlobal::new().unwrap();
+		assert!(other
+			.1
+			.lock()
+			.unwrap()
+			.shared_capabilities()
+			.and_then(std::sync::OnceLock::get)
+			.is_some());
+
+		gencmd
+			.0
+			.set_capabilities(Some(Capabilities::from_commands(["commands"])));
+		match gencmd.send_cmd::<CmdMeasureTemp>() {
+			Err(GencmdCmdError::Unsupported(command)) => assert_eq!(command, "measure_temp"),
+			other => panic!("expected unsupported error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "mock_vc_ffi")]
+	fn test_mock_gencmd_responses() {
+		use crate::ffi::mock::{MockPattern, MOCK_GENCMD};
+
+		crate::test::setup_global();
+
+		MOCK_GENCMD
+			.set_pattern_response(MockPattern::Prefix("downstream_".to_string()), "value=42");
+
+		let mut gencmd = GencmdGlobal::new().unwrap();
+		assert_eq!(
+			gencmd.send_cmd_raw("downstream_command").unwrap(),
+			"value=42"
+		);
+		assert!(MOCK_GENCMD
+			.commands()
+			.contains(&"downstream_command".to_string()));
+	}
+
+	#[test]
+	#[cfg(feature = "mock_vc_ffi")]
+	fn test_mock_faults() {
+		use std::time::{Duration, Instant};
+
+		use crate::ffi::mock::{MockFault, MockPattern, MOCK_FAULTS, MOCK_GENCMD};
+
+		crate::test::setup_global();
+
+		MOCK_GENCMD.set_pattern_response(MockPattern::Prefix("fault_".to_string()), "value=1");
+		MOCK_GENCMD.set_response("fault_overflow", "x".repeat(8192));
+		MOCK_FAULTS.inject_for("fault_send", MockFault::Send(-1));
+		MOCK_FAULTS.inject_for("fault_read", MockFault::Read(-1));
+		MOCK_FAULTS.inject_for("fault_overflow", MockFault::ReadOverflow);
+		MOCK_FAULTS.inject_for("fault_nul", MockFault::MissingNul);
+		MOCK_FAULTS.inject_for("fault_utf8", MockFault::InvalidUtf8);
+		MOCK_FAULTS.inject_for(
+			"fault_latency",
+			MockFault::Latency(Duration::from_millis(50)),
+		);
+
+		let mut gencmd = GencmdGlobal::new().unwrap();
+		assert!(matches!(
+			gencmd.send_cmd_raw("fault_send"),
+			Err(GencmdCmdError::Send)
+		));
+		assert!(matches!(
+			gencmd.send_cmd_raw("fault_read"),
+			Err(GencmdCmdError::Read)
+		));
+		assert!(matches!(
+			gencmd.send_cmd_raw("fault_overflow"),
+			Err(GencmdCmdError::Read)
+		));
+		assert!(matches!(
+			gencmd.send_cmd_raw("fault_nul"),
+			Err(GencmdCmdError::Read)
+		));
+		assert!(matches!(
+			gencmd.send_cmd_raw("fault_utf8"),
+			Err(GencmdCmdError::Utf8(_))
+		));
+		assert!(matches!(
+			gencmd.send_cmd_raw(&"a".repeat(1024)),
+			Err(GencmdCmdError::CommandTooLong)
+		));
+
+		let start = Instant::now();
+		assert_eq!(gencmd.send_cmd_raw("fault_latency").unwrap(), "value=1");
+		assert!(start.elapsed() >= Duration::from_millis(50));
+
+		// other commands are not affected
+		assert_eq!(gencmd.send_cmd_raw("fault_none").unwrap(), "value=1");
+	}
+
+	#[test]
+	#[cfg(feature = "mock_vc_ffi")]
+	fn test_reconnects_after_service_stop() {
+		use std::sync::{
+			atomic::{AtomicU32, Ordering},
+			Arc,
+		};
+
+		use crate::global::reconnect::{ReconnectEvent, ReconnectPolicy};
+
+		crate::test::setup_global();
+
+		let reconnects = Arc::new(AtomicU32::new(0));
+		let mut gencmd = GencmdGlobal::new().unwrap();
+		gencmd
+			.1
+			.lock()
+			.unwrap()
+			.set_reconnect_policy(Some(ReconnectPolicy::new().on_reconnect({
+				let reconnects = reconnects.clone();
+				move |event| {
+					if let ReconnectEvent::Reconnected { .. } = event {
+						reconnects.fetch_add(1, Ordering::Relaxed);
+					}
+				}
+			})));
+
+		// the service going away makes every send fail until it is initialized again
+		crate::ffi::mock::vc_gencmd_stop();
+
+		assert!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap() > 0.0);
+		assert!(gencmd.1.lock().unwrap().is_healthy());
+		assert!(reconnects.load(Ordering::Relaxed) >= 1);
+	}
+
+	#[test]
+	#[cfg(feature = "mock_vc_ffi")]
+	fn test_read_timeout_recovers() {
+		use std::time::Duration;
+
+		use crate::ffi::mock::{MockFault, MOCK_FAULTS, MOCK_GENCMD};
+
+		crate::test::setup_global();
+
+		MOCK_GENCMD.set_response("timeout_slow", "value=slow");
+		MOCK_GENCMD.set_response("timeout_fast", "value=fast");
+		MOCK_FAULTS.inject_for(
+			"timeout_slow",
+			MockFault::ReadLatency(Duration::from_millis(200)),
+		);
+
+		let mut gencmd = GencmdGlobal::new().unwrap();
+		assert!(matches!(
+			gencmd.send_cmd_raw_timeout("timeout_slow", Duration::from_millis(20)),
+			Err(GencmdCmdError::Timeout(_))
+		));
+
+		// the late response to the slow command must not be returned for the next one
+		assert_eq!(gencmd.send_cmd_raw("timeout_fast").unwrap(), "value=fast");
+		assert_eq!(
+			gencmd
+				.send_cmd_raw_timeout("timeout_slow", Duration::from_secs(1))
+				.unwrap(),
+			"value=slow"
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "mock_vc_ffi")]
+	fn test_single_read_failure_keeps_connection() {
+		use crate::{
+			ffi::mock::{MockFault, MOCK_FAULTS, MOCK_GENCMD},
+			global::{reconnect::ReconnectPolicy, GlobalInstance},
+		};
+
+		crate::test::setup_global();
+
+		MOCK_GENCMD.set_response("suspect_fine", "value=1");
+		MOCK_GENCMD.set_response("suspect_nul", "value=1");
+		MOCK_FAULTS.inject_for("suspect_nul", MockFault::MissingNul);
+
+		let gencmd = GencmdGlobal::new().unwrap();
+		// holding the lock keeps other tests from interleaving their reads
+		let mut instance = gencmd.1.lock().unwrap();
+		instance.set_reconnect_policy(Some(ReconnectPolicy::new().with_read_failures(2)));
+
+		fn exchange(instance: &mut GlobalInstance, command: &str) -> Result<usize, GencmdCmdError> {
+			let command = std::ffi::CString::new(command).unwrap();
+			let mut buffer = [0u8; 64];
+			unsafe { instance.send_command(&command) }?;
+			instance.retrieve_response(&mut buffer)
+		}
+
+		assert!(exchange(&mut instance, "suspect_fine").is_ok());
+		assert!(matches!(
+			exchange(&mut instance, "suspect_nul"),
+			Err(GencmdCmdError::Read)
+		));
+		assert!(exchange(&mut instance, "suspect_fine").is_ok());
+		assert!(instance.is_healthy());
+
+		// failing reads in a row do suggest a lost connection
+		assert!(matches!(
+			exchange(&mut instance, "suspect_nul"),
+			Err(GencmdCmdError::Read)
+		));
+		assert!(matches!(
+			exchange(&mut instance, "suspect_nul"),
+			Err(GencmdCmdError::Read)
+		));
+		assert!(!instance.is_healthy());
+		assert!(exchange(&mut instance, "suspect_fine").is_ok());
+		assert!(instance.is_healthy());
+
+		instance.set_reconnect_policy(Some(ReconnectPolicy::new()));
+	}
+
+	#[test]
+	fn test_send_batch() {
+		crate::test::setup_global();
+
+		let mut gencmd = GencmdGlobal::new().unwrap();
+
+		let (temp, freq, throttled) =
+			gencmd.send_batch::<(CmdMeasureTemp, CmdMeasureClockArm, CmdGetThrottled)>();
+		assert!(temp.unwrap() > 0.0);
+		assert!(freq.unwrap() > 0);
+		assert!(throttled.is_ok());
+
+		let responses = gencmd.send_batch_raw(["measure_temp", "not_a_command"]);
+		assert!(responses[0].as_ref().unwrap().starts_with("temp="));
+		assert!(matches!(
+			responses[1],
+			Err(GencmdCmdError::ErrorResponse(_))
+		));
+	}
+
 	#[test]
 	fn test_cmds_threads_racing() {
 		crate::test::setup_global();
@@ -0,0 +1,161 @@
+//! Cloneable handle sending commands through a single worker thread.
+
+use std::sync::{mpsc, Arc};
+
+#[cfg(global_instance_available)]
+use crate::global::GlobalInstance;
+use crate::{
+	backend::GencmdBackend,
+	error::GencmdCmdError,
+	gencmd::{batch::Batch, capabilities::Capabilities, worker::Worker, Gencmd, OwnedCommand},
+};
+
+/// A `Clone + Send + Sync` handle to a worker thread which owns the backend and the only [`Gencmd`] buffer.
+///
+/// Unlike [`GencmdGlobal`](super::global::GencmdGlobal) no mutex is shared between the callers, so a panicking caller cannot poison it
+/// and threads do not need their own buffer. Requests are queued and processed in order.
+///
+/// The worker stops once all handles are dropped, or on [`shutdown`](Self::shutdown). Dropping the last handle waits until
+/// the worker has processed the queued requests and dropped the backend.
+#[derive(Clone)]
+pub struct GencmdHandle {
+	worker: Arc<Worker>,
+}
+impl GencmdHandle {
+	/// Creates a [`GlobalInstance`] and moves it to the worker.
+	#[cfg(global_instance_available)]
+	pub fn new() -> Result<Self, crate::error::GencmdInitError> {
+		Ok(Self::from_backend(GlobalInstance::new()?))
+	}
+
+	pub fn from_backend(backend: impl GencmdBackend + Send + 'static) -> Self {
+		Self::from_parts(Gencmd::new(), backend)
+	}
+
+	/// Uses `gencmd` (e.g. with loaded capabilities) together with `backend` on the worker.
+	pub fn from_parts(gencmd: Gencmd, backend: impl GencmdBackend + Send + 'static) -> Self {
+		GencmdHandle {
+			worker: Arc::new(Worker::spawn(gencmd, backend)),
+		}
+	}
+
+	/// See [`Gencmd::send_cmd_raw`].
+	pub fn send_cmd_raw(&self, command: &str) -> Result<String, GencmdCmdError> {
+		let command = command.to_string();
+
+		self.run(move |gencmd, backend| gencmd.send_cmd_raw(backend, &command).map(str::to_string))
+	}
+
+	/// See [`Gencmd::send_cmd`], e.g. `handle.send_cmd::<CmdMeasureTemp>()`.
+	///
+	/// Only [commands with owned responses](OwnedCommand) can be sent this way. Responses borrowing from the buffer (such as [`CmdCommands`](super::commands::CmdCommands))
+	/// can be parsed from the result of [`send_cmd_raw`](Self::send_cmd_raw) using [`Command::parse_response`](super::Command::parse_response).
+	pub fn send_cmd<C: OwnedCommand>(&self) -> Result<C::Output, GencmdCmdError>
+	where
+		C::Output: Send + 'static,
+	{
+		self.run(|gencmd, backend| gencmd.send_cmd::<C>(backend))
+	}
+
+	/// See [`Gencmd::send_batch`]. The batch is processed by the worker as one request.
+	pub fn send_batch<C: Batch>(&self) -> Result<C::Output, GencmdCmdError>
+	where
+		C::Output: Send + 'static,
+	{
+		self.run(|gencmd, backend| Ok(gencmd.send_batch::<C>(backend)))
+	}
+
+	/// See [`Gencmd::load_capabilities`].
+	pub fn load_capabilities(&self) -> Result<Capabilities, GencmdCmdError> {
+		self.run(|gencmd, backend| gencmd.load_capabilities(backend).cloned())
+	}
+
+	/// Stops accepting requests on all clones of this handle and waits until the queued ones are processed.
+	///
+	/// Further requests fail with [`GencmdCmdError::WorkerStopped`].
+	pub fn shutdown(&self) {
+		self.worker.shutdown();
+	}
+
+	fn run<T: Send + 'static>(
+		&self,
+		job: impl FnOnce(&mut Gencmd, &mut dyn GencmdBackend) -> Result<T, GencmdCmdError>
+			+ Send
+			+ 'static,
+	) -> Result<T, GencmdCmdError> {
+		let (sender, receiver) = mpsc::sync_channel(1);
+
+		self.worker.submit(Box::new(move |gencmd, backend| {
+			// the caller only goes away together with the receiver
+			let _ = sender.send(job(gencmd, backend));
+		}))?;
+
+		receiver.recv().map_err(|_| GencmdCmdError::WorkerStopped)?
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	};
+
+	use super::GencmdHandle;
+	use crate::{
+		backend::mock::MockBackend,
+		error::GencmdCmdError,
+		gencmd::commands::{CmdGetThrottled, CmdMeasureTemp},
+	};
+
+	#[test]
+	fn shares_worker_between_threads() {
+		let handle = GencmdHandle::from_backend(MockBackend::new());
+
+		let threads: Vec<_> = (0..8)
+			.map(|_| {
+				let handle = handle.clone();
+				std::thread::spawn(move || {
+					for _ in 0..16 {
+						assert_eq!(handle.send_cmd::<CmdMeasureTemp>().unwrap(), 45.6);
+						assert!(handle.send_cmd::<CmdGetThrottled>().is_ok());
+					}
+				})
+			})
+			.collect();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+
+		handle.shutdown();
+		assert!(matches!(
+			handle.send_cmd_raw("measure_temp"),
+			Err(GencmdCmdError::WorkerStopped)
+		));
+	}
+
+	#[test]
+	fn dropping_last_handle_drops_backend() {
+		struct SetOnDrop(Arc<AtomicBool>);
+		impl Drop for SetOnDrop {
+			fn drop(&mut self) {
+				self.0.store(true, Ordering::SeqCst);
+			}
+		}
+
+		let dropped = Arc::new(AtomicBool::new(false));
+		let guard = SetOnDrop(dropped.clone());
+		let handle = GencmdHandle::from_backend(MockBackend::with_responder(move |_| {
+			let _ = &guard;
+			"temp=42.0'C".to_string()
+		}));
+
+		let clone = handle.clone();
+		assert_eq!(clone.send_cmd::<CmdMeasureTemp>().unwrap(), 42.0);
+		drop(clone);
+		assert!(!dropped.load(Ordering::SeqCst));
+
+		drop(handle);
+		assert!(dropped.load(Ordering::SeqCst));
+	}
+}
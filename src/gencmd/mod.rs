@@ -1,35 +1,166 @@
-use std::ffi::CStr;
+use std::{ffi::CStr, sync::OnceLock, time::Duration};
 
-use crate::{error::*, ffi, global::GlobalInstance};
+use crate::{backend::GencmdBackend, error::*, ffi};
 
+use self::{capabilities::Capabilities, commands::CmdCommands};
+
+#[cfg(feature = "async_api")]
+pub mod asynchronous;
+pub mod batch;
+pub mod cache;
+pub mod capabilities;
 pub mod commands;
+pub mod handle;
 pub mod response;
+pub mod retry;
+pub mod throttle;
 
 #[cfg(feature = "global_singleton")]
 pub mod global;
 
 pub mod unique;
+mod worker;
 
 pub trait Command<'a> {
 	type Response;
 
 	const COMMAND_STR: &'static str;
 
+	/// How long the response can be reused by a [`CachedGencmd`](cache::CachedGencmd).
+	const CACHE_POLICY: cache::CachePolicy = cache::CachePolicy::Live;
+
 	fn parse_response(response: &'a str) -> Result<Self::Response, GencmdCmdError>;
 }
 
+/// A [`Command`] whose response does not borrow from the buffer, so that it can be named without a lifetime.
+///
+/// Implemented for all such commands, lets the wrappers which cannot lend out their buffer name the response of `C` as `C::Output`.
+pub trait OwnedCommand: for<'a> Command<'a, Response = <Self as OwnedCommand>::Output> {
+	type Output;
+}
+impl<C, R> OwnedCommand for C
+where
+	C: for<'a> Command<'a, Response = R>,
+{
+	type Output = R;
+}
+
 /// A wrapper around the gencmd interface.
 ///
 /// This holds an internal buffer for communication and an Arc to the instance.
+///
+/// Optionally it also holds the [`Capabilities`] of the firmware, in which case commands not supported by the firmware
+/// are rejected with [`GencmdCmdError::Unsupported`] without being sent. Otherwise typed commands are checked against
+/// the capabilities [shared by the backend](GencmdBackend::shared_capabilities), if it keeps any.
+///
+/// With the `process_lock` feature it can also hold a [`ProcessLock`](crate::lock::ProcessLock) which is taken around
+/// each send/read pair so that other processes using the same lock never interleave with it.
 #[derive(Clone)]
 pub struct Gencmd {
 	buffer: [u8; ffi::GENCMDSERVICE_MSGFIFO_SIZE as usize],
+	capabilities: Option<Capabilities>,
+	#[cfg(feature = "process_lock")]
+	process_lock: Option<std::sync::Arc<crate::lock::ProcessLock>>,
 }
 impl Gencmd {
 	pub fn new() -> Self {
 		Gencmd {
 			buffer: [0u8; ffi::GENCMDSERVICE_MSGFIFO_SIZE as usize],
+			capabilities: None,
+			#[cfg(feature = "process_lock")]
+			process_lock: None,
+		}
+	}
+
+	pub fn with_capabilities(capabilities: Capabilities) -> Self {
+		let mut gencmd = Self::new();
+		gencmd.set_capabilities(Some(capabilities));
+
+		gencmd
+	}
+
+	pub fn capabilities(&self) -> Option<&Capabilities> {
+		self.capabilities.as_ref()
+	}
+
+	/// Sets or clears capabilities used to reject unsupported commands.
+	pub fn set_capabilities(&mut self, capabilities: Option<Capabilities>) {
+		self.capabilities = capabilities;
+	}
+
+	#[cfg(feature = "process_lock")]
+	pub fn process_lock(&self) -> Option<&crate::lock::ProcessLock> {
+		self.process_lock.as_deref()
+	}
+
+	/// Sets or clears the lock taken around each send/read pair.
+	///
+	/// Clones of this `Gencmd` share the lock.
+	#[cfg(feature = "process_lock")]
+	pub fn set_process_lock(&mut self, lock: Option<crate::lock::ProcessLock>) {
+		self.process_lock = lock.map(std::sync::Arc::new);
+	}
+
+	/// Fetches the capabilities from the firmware, unless they were already loaded, and checks all commands against them.
+	///
+	/// Backends which [share capabilities](GencmdBackend::shared_capabilities) fetch them only once for all `Gencmd`s.
+	pub fn load_capabilities(
+		&mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+	) -> Result<&Capabilities, GencmdCmdError> {
+		if self.capabilities.is_none() {
+			self.capabilities = Some(self.shared_capabilities(instance)?);
 		}
+
+		Ok(self.capabilities.as_ref().unwrap())
+	}
+
+	/// Returns the capabilities shared by `instance`, fetching them if they are not loaded yet or not shared at all.
+	fn shared_capabilities(
+		&mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+	) -> Result<Capabilities, GencmdCmdError> {
+		if let Some(capabilities) = instance.shared_capabilities().and_then(OnceLock::get) {
+			return Ok(capabilities.clone());
+		}
+
+		// not `send_cmd`, which would check the capabilities being loaded
+		let response = self.send_cmd_raw(instance, CmdCommands::COMMAND_STR)?;
+		let capabilities = Capabilities::from(CmdCommands::parse_response(response)?);
+		log::debug!("loaded {} supported commands", capabilities.len());
+
+		match instance.shared_capabilities() {
+			Some(shared) => Ok(shared.get_or_init(|| capabilities).clone()),
+			None => Ok(capabilities),
+		}
+	}
+
+	/// Rejects the typed command `C` if the capabilities shared by `instance` do not list it.
+	///
+	/// Capabilities of this `Gencmd` take precedence and are checked for every command in [`exchange`](Self::exchange).
+	/// If the shared capabilities cannot be loaded the command is sent anyway.
+	pub(crate) fn check_supported<'a, C: Command<'a>>(
+		&mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+	) -> Result<(), GencmdCmdError> {
+		if self.capabilities.is_some() || instance.shared_capabilities().is_none() {
+			return Ok(());
+		}
+
+		let capabilities = match self.shared_capabilities(instance) {
+			Ok(capabilities) => capabilities,
+			Err(err) => {
+				log::warn!("failed to load the capabilities of the firmware: {}", err);
+				return Ok(());
+			}
+		};
+		if !capabilities.supports::<C>() {
+			return Err(GencmdCmdError::Unsupported(
+				Capabilities::command_name(C::COMMAND_STR).to_string(),
+			));
+		}
+
+		Ok(())
 	}
 
 	/// Send a string command and receive a string response.
@@ -37,12 +168,57 @@ impl Gencmd {
 	/// This function does not parse the response unless it is the error.
 	pub fn send_cmd_raw(
 		&mut self,
-		instance: &mut GlobalInstance,
+		instance: &mut (impl GencmdBackend + ?Sized),
 		command: &str,
 	) -> Result<&str, GencmdCmdError> {
+		self.send_cmd_raw_with(instance, command, None)
+	}
+
+	/// Like [`send_cmd_raw`](Self::send_cmd_raw), but fails with [`GencmdCmdError::Timeout`] if the response does not arrive within `timeout`.
+	///
+	/// See [`GencmdBackend::retrieve_response_timeout`].
+	pub fn send_cmd_raw_timeout(
+		&mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+		command: &str,
+		timeout: Duration,
+	) -> Result<&str, GencmdCmdError> {
+		self.send_cmd_raw_with(instance, command, Some(timeout))
+	}
+
+	fn send_cmd_raw_with(
+		&mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+		command: &str,
+		timeout: Option<Duration>,
+	) -> Result<&str, GencmdCmdError> {
+		let len = self.exchange(instance, command, timeout)?;
+
+		self.response(len)
+	}
+
+	/// Sends `command` and retrieves the response into the buffer, returning its length.
+	///
+	/// Error responses are returned as errors.
+	pub(crate) fn exchange(
+		&mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+		command: &str,
+		timeout: Option<Duration>,
+	) -> Result<usize, GencmdCmdError> {
 		if command.len() >= ffi::GENCMD_MAX_LENGTH as usize {
 			return Err(GencmdCmdError::CommandTooLong);
 		}
+		if let Some(ref capabilities) = self.capabilities {
+			if !capabilities.supports_command(command) {
+				return Err(GencmdCmdError::Unsupported(
+					Capabilities::command_name(command).to_string(),
+				));
+			}
+		}
+		if timeout.is_some() && !instance.supports_timeout() {
+			return Err(GencmdCmdError::TimeoutUnsupported);
+		}
 		// this is true now but one never knows with C APIs
 		debug_assert!(ffi::GENCMD_MAX_LENGTH <= ffi::GENCMDSERVICE_MSGFIFO_SIZE);
 
@@ -50,13 +226,25 @@ impl Gencmd {
 		self.buffer[..command.len()].copy_from_slice(command.as_bytes());
 		self.buffer[command.len()] = 0;
 
+		#[cfg(feature = "process_lock")]
+		let guard = match self.process_lock {
+			Some(ref lock) => Some(lock.acquire()?),
+			None => None,
+		};
+
 		// SAFETY: We call the retrieve right under and have unique access
 		unsafe {
 			instance
 				.send_command(CStr::from_bytes_with_nul(&self.buffer[..=command.len()]).unwrap())?;
 		}
 
-		let len = instance.retrieve_response(&mut self.buffer)?;
+		let len = match timeout {
+			Some(timeout) => instance.retrieve_response_timeout(&mut self.buffer, timeout)?,
+			None => instance.retrieve_response(&mut self.buffer)?,
+		};
+
+		#[cfg(feature = "process_lock")]
+		drop(guard);
 
 		let response = std::str::from_utf8(&self.buffer[..len])?;
 
@@ -65,18 +253,36 @@ impl Gencmd {
 			return Err(error.into());
 		}
 
-		Ok(response)
+		Ok(len)
+	}
+
+	/// Returns the response of length `len` retrieved by [`exchange`](Self::exchange).
+	pub(crate) fn response(&self, len: usize) -> Result<&str, GencmdCmdError> {
+		Ok(std::str::from_utf8(&self.buffer[..len])?)
 	}
 
 	pub fn send_cmd<'a, C: Command<'a>>(
 		&'a mut self,
-		instance: &mut GlobalInstance,
+		instance: &mut (impl GencmdBackend + ?Sized),
 	) -> Result<C::Response, GencmdCmdError> {
+		self.check_supported::<C>(instance)?;
 		let response = self.send_cmd_raw(instance, C::COMMAND_STR)?;
 
 		C::parse_response(response)
 	}
 
+	/// Like [`send_cmd`](Self::send_cmd), but fails with [`GencmdCmdError::Timeout`] if the response does not arrive within `timeout`.
+	pub fn send_cmd_timeout<'a, C: Command<'a>>(
+		&'a mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+		timeout: Duration,
+	) -> Result<C::Response, GencmdCmdError> {
+		self.check_supported::<C>(instance)?;
+		let response = self.send_cmd_raw_timeout(instance, C::COMMAND_STR, timeout)?;
+
+		C::parse_response(response)
+	}
+
 	fn parse_error(response: &str) -> Result<GencmdErrorResponse, GencmdCmdError> {
 		let (response, code) = response::parse_field_simple::<i32>(response, "error")
 			.map_err(GencmdCmdError::from_invalid_format)?;
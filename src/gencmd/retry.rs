@@ -0,0 +1,292 @@
+//! Retrying of commands which failed for transient reasons.
+//!
+//! Under heavy load the firmware occasionally fails to accept a command or to deliver its response even though an immediate retry succeeds.
+//! A [`RetryPolicy`] retries the errors classified as [transient](GencmdCmdError::is_transient) with exponential backoff and jitter,
+//! while errors such as error responses are returned right away.
+
+use std::{
+	collections::hash_map::RandomState,
+	hash::{BuildHasher, Hasher},
+	time::Duration,
+};
+
+use crate::{backend::GencmdBackend, error::GencmdCmdError};
+
+use super::{Command, Gencmd};
+
+/// A value obtained after `attempts` attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retried<T> {
+	pub value: T,
+	/// Number of attempts made, including the successful one.
+	pub attempts: u32,
+}
+
+/// How many times and how often failed commands are retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+	max_attempts: u32,
+	initial_backoff: Duration,
+	max_backoff: Duration,
+	multiplier: f64,
+	jitter: f64,
+}
+impl RetryPolicy {
+	pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+	pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+	pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+	pub const fn new() -> Self {
+		RetryPolicy {
+			max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+			initial_backoff: Self::DEFAULT_INITIAL_BACKOFF,
+			max_backoff: Self::DEFAULT_MAX_BACKOFF,
+			multiplier: 2.0,
+			jitter: 0.2,
+		}
+	}
+
+	/// Never retries.
+	pub const fn none() -> Self {
+		Self::new().with_max_attempts(1)
+	}
+
+	/// Sets the total number of attempts, at least one attempt is always made.
+	pub const fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+		self.max_attempts = max_attempts;
+		self
+	}
+
+	/// Sets the delay before the first retry and the limit the delays grow up to.
+	pub const fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+		self.initial_backoff = initial;
+		self.max_backoff = max;
+		self
+	}
+
+	/// Sets the factor by which the delay grows after each retry.
+	///
+	/// ### Panic
+	/// Panics if `multiplier` is NaN or infinite.
+	pub const fn with_multiplier(mut self, multiplier: f64) -> Self {
+		assert!(multiplier.is_finite(), "backoff multiplier must be finite");
+		self.multiplier = multiplier;
+		self
+	}
+
+	/// Sets the fraction (`0.0..=1.0`) by which each delay is randomly lengthened or shortened.
+	///
+	/// ### Panic
+	/// Panics if `jitter` is NaN.
+	pub const fn with_jitter(mut self, jitter: f64) -> Self {
+		assert!(!jitter.is_nan(), "backoff jitter must not be NaN");
+		self.jitter = jitter;
+		self
+	}
+
+	pub const fn max_attempts(&self) -> u32 {
+		self.max_attempts
+	}
+
+	/// Returns the delay before retry number `retry` (starting at 1), without jitter.
+	pub fn backoff(&self, retry: u32) -> Duration {
+		let exponent = i32::try_from(retry.saturating_sub(1)).unwrap_or(i32::MAX);
+		let factor = self.multiplier.powi(exponent);
+		// the factor grows to infinity after enough retries, which `Duration` cannot represent
+		let secs = (self.initial_backoff.as_secs_f64() * factor).max(0.0);
+
+		Duration::try_from_secs_f64(secs)
+			.unwrap_or(self.max_backoff)
+			.min(self.max_backoff)
+	}
+
+	/// Calls `attempt` until it succeeds, fails with a permanent error or the attempts run out.
+	pub fn run<T>(
+		&self,
+		mut attempt: impl FnMut() -> Result<T, GencmdCmdError>,
+	) -> Result<Retried<T>, GencmdCmdError> {
+		let mut attempts = 0;
+
+		loop {
+			attempts += 1;
+
+			match attempt() {
+				Ok(value) => return Ok(Retried { value, attempts }),
+				Err(err) if err.is_transient() && attempts < self.max_attempts => {
+					let delay = self.jittered(self.backoff(attempts));
+					log::warn!(
+						"attempt {}/{} failed: {}, retrying in {:?}",
+						attempts,
+						self.max_attempts,
+						err,
+						delay
+					);
+
+					std::thread::sleep(delay);
+				}
+				Err(err) => {
+					if attempts > 1 {
+						log::error!("giving up after {} attempts: {}", attempts, err);
+					}
+
+					return Err(err);
+				}
+			}
+		}
+	}
+
+	fn jittered(&self, delay: Duration) -> Duration {
+		let jitter = self.jitter.clamp(0.0, 1.0);
+		if jitter == 0.0 {
+			return delay;
+		}
+
+		// random enough for spreading retries without pulling in a dependency
+		let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+
+		Duration::try_from_secs_f64(delay.as_secs_f64() * (1.0 + jitter * (random * 2.0 - 1.0)))
+			.unwrap_or(delay)
+	}
+}
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Gencmd {
+	/// Like [`send_cmd_raw`](Self::send_cmd_raw), but retries transient failures according to `policy`.
+	pub fn send_cmd_raw_retry(
+		&mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+		command: &str,
+		policy: &RetryPolicy,
+	) -> Result<Retried<&str>, GencmdCmdError> {
+		let Retried {
+			value: len,
+			attempts,
+		} = policy.run(|| self.exchange(instance, command, None))?;
+
+		Ok(Retried {
+			value: self.response(len)?,
+			attempts,
+		})
+	}
+
+	/// Like [`send_cmd`](Self::send_cmd), but retries transient failures according to `policy`.
+	pub fn send_cmd_retry<'a, C: Command<'a>>(
+		&'a mut self,
+		instance: &mut (impl GencmdBackend + ?Sized),
+		policy: &RetryPolicy,
+	) -> Result<Retried<C::Response>, GencmdCmdError> {
+		self.check_supported::<C>(instance)?;
+		let Retried { value, attempts } =
+			self.send_cmd_raw_retry(instance, C::COMMAND_STR, policy)?;
+
+		Ok(Retried {
+			value: C::parse_response(value)?,
+			attempts,
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{ffi::CStr, time::Duration};
+
+	use super::{Retried, RetryPolicy};
+	use crate::{
+		backend::{mock::MockBackend, GencmdBackend},
+		error::GencmdCmdError,
+		gencmd::{commands::CmdMeasureTemp, Gencmd},
+	};
+
+	/// Fails the first `failures` sends.
+	struct FlakyBackend {
+		failures: u32,
+		inner: MockBackend,
+	}
+	impl GencmdBackend for FlakyBackend {
+		unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+			if self.failures > 0 {
+				self.failures -= 1;
+				return Err(GencmdCmdError::Send);
+			}
+
+			self.inner.send_command(command)
+		}
+
+		fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+			self.inner.retrieve_response(buffer)
+		}
+	}
+
+	fn flaky(failures: u32) -> FlakyBackend {
+		FlakyBackend {
+			failures,
+			inner: MockBackend::new(),
+		}
+	}
+
+	#[test]
+	fn retries_transient_errors() {
+		let policy = RetryPolicy::new()
+			.with_max_attempts(3)
+			.with_backoff(Duration::ZERO, Duration::ZERO);
+		let mut gencmd = Gencmd::new();
+
+		assert_eq!(
+			gencmd
+				.send_cmd_retry::<CmdMeasureTemp>(&mut flaky(2), &policy)
+				.unwrap(),
+			Retried {
+				value: 45.6,
+				attempts: 3
+			}
+		);
+		assert!(matches!(
+			gencmd.send_cmd_retry::<CmdMeasureTemp>(&mut flaky(3), &policy),
+			Err(GencmdCmdError::Send)
+		));
+
+		// permanent errors are not retried
+		let mut attempts = 0;
+		assert!(matches!(
+			policy.run(|| -> Result<(), _> {
+				attempts += 1;
+				Err(GencmdCmdError::CommandTooLong)
+			}),
+			Err(GencmdCmdError::CommandTooLong)
+		));
+		assert_eq!(attempts, 1);
+	}
+
+	#[test]
+	fn backoff_grows_up_to_limit() {
+		let policy = RetryPolicy::new()
+			.with_backoff(Duration::from_millis(10), Duration::from_millis(50))
+			.with_multiplier(2.0);
+
+		assert_eq!(policy.backoff(1), Duration::from_millis(10));
+		assert_eq!(policy.backoff(2), Duration::from_millis(20));
+		assert_eq!(policy.backoff(3), Duration::from_millis(40));
+		assert_eq!(policy.backoff(4), Duration::from_millis(50));
+	}
+
+	#[test]
+	fn backoff_saturates_after_many_retries() {
+		let policy = RetryPolicy::new()
+			.with_backoff(Duration::from_millis(10), Duration::from_secs(1))
+			.with_multiplier(2.0);
+
+		assert_eq!(policy.backoff(100), Duration::from_secs(1));
+		assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(1));
+		assert!(policy.jittered(policy.backoff(u32::MAX)) <= Duration::from_millis(1200));
+	}
+
+	#[test]
+	#[should_panic]
+	fn rejects_infinite_multiplier() {
+		let _ = RetryPolicy::new().with_multiplier(f64::INFINITY);
+	}
+}
@@ -0,0 +1,272 @@
+//! Tracking of throttling conditions over time.
+//!
+//! A single [`CpuThrottled`](super::commands::CpuThrottled) reading is only a snapshot of the firmware state. The [`ThrottleTracker`] consumes
+//! successive readings (such as those returned by [`CmdGetThrottled`](super::commands::CmdGetThrottled)), reports what changed between them
+//! and accumulates how long and how many times each condition was active.
+
+use std::time::{Duration, Instant};
+
+use super::commands::{CpuThrottled, ThrottleStatus};
+
+#[cfg(feature = "serde_models")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub enum ThrottleCondition {
+	UnderVoltage,
+	FrequencyCapped,
+	Throttled,
+	SoftTemperatureLimit,
+}
+impl ThrottleCondition {
+	pub const ALL: [ThrottleCondition; 4] = [
+		ThrottleCondition::UnderVoltage,
+		ThrottleCondition::FrequencyCapped,
+		ThrottleCondition::Throttled,
+		ThrottleCondition::SoftTemperatureLimit,
+	];
+
+	pub fn is_set_in(self, status: &ThrottleStatus) -> bool {
+		match self {
+			ThrottleCondition::UnderVoltage => status.under_voltage,
+			ThrottleCondition::FrequencyCapped => status.frequency_capped,
+			ThrottleCondition::Throttled => status.throttled,
+			ThrottleCondition::SoftTemperatureLimit => status.soft_temperature_limit,
+		}
+	}
+
+	const fn index(self) -> usize {
+		self as usize
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub enum ThrottleTransition {
+	/// The condition is now active but was not in the previous reading.
+	Started(ThrottleCondition),
+	/// The condition was active in the previous reading but is not anymore.
+	Ended(ThrottleCondition),
+	/// The "occurred since boot" bit of the condition was newly set.
+	OccurredSinceBoot(ThrottleCondition),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub struct ThrottleConditionStats {
+	/// Cumulative time the condition was active, up to the last reading.
+	pub active_duration: Duration,
+	/// Number of times the condition started.
+	pub count: u32,
+	/// Whether the condition is active in the last reading.
+	pub active: bool,
+	/// Whether the firmware reported the condition as occurred since boot in the last reading.
+	pub occurred_since_boot: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_models", derive(Serialize, Deserialize))]
+pub struct ThrottleSummary {
+	/// Time between the first and the last reading.
+	pub observed: Duration,
+	/// Number of readings consumed.
+	pub readings: u64,
+	pub under_voltage: ThrottleConditionStats,
+	pub frequency_capped: ThrottleConditionStats,
+	pub throttled: ThrottleConditionStats,
+	pub soft_temperature_limit: ThrottleConditionStats,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Reading {
+	at: Instant,
+	current: ThrottleStatus,
+	occurred: ThrottleStatus,
+}
+
+/// Consumes successive throttling readings and tracks transitions between them.
+///
+/// A condition is considered active from the reading in which it was first seen until the reading in which it is no longer set,
+/// so the accuracy of the durations depends on the polling interval.
+#[derive(Debug, Default, Clone)]
+pub struct ThrottleTracker {
+	first: Option<Instant>,
+	last: Option<Reading>,
+	readings: u64,
+	stats: [ThrottleConditionStats; 4],
+}
+impl ThrottleTracker {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Consumes a reading taken now.
+	///
+	/// See [`update_at`](Self::update_at).
+	pub fn update(&mut self, reading: &CpuThrottled) -> Vec<ThrottleTransition> {
+		self.update_at(reading, Instant::now())
+	}
+
+	/// Consumes a reading taken at `at` and returns the transitions since the previous reading.
+	///
+	/// Conditions active in the first reading are reported as started and occurred flags set in the first reading
+	/// are reported as newly set, since nothing is known about the state before the tracker was created.
+	///
+	/// Readings older than the previous one are treated as if they were taken at the same time as the previous one.
+	pub fn update_at(&mut self, reading: &CpuThrottled, at: Instant) -> Vec<ThrottleTransition> {
+		let previous = self.last;
+		let at = previous.map(|p| p.at.max(at)).unwrap_or(at);
+		let elapsed = previous.map(|p| at - p.at).unwrap_or_default();
+
+		let mut transitions = Vec::new();
+		for condition in ThrottleCondition::ALL {
+			let stats = &mut self.stats[condition.index()];
+
+			let was_active = previous
+				.map(|p| condition.is_set_in(&p.current))
+				.unwrap_or(false);
+			let had_occurred = previous
+				.map(|p| condition.is_set_in(&p.occurred))
+				.unwrap_or(false);
+			let active = condition.is_set_in(&reading.current);
+			let occurred = condition.is_set_in(&reading.occured);
+
+			if was_active {
+				stats.active_duration += elapsed;
+			}
+
+			match (was_active, active) {
+				(false, true) => {
+					stats.count += 1;
+					transitions.push(ThrottleTransition::Started(condition));
+				}
+				(true, false) => transitions.push(ThrottleTransition::Ended(condition)),
+				_ => (),
+			}
+
+			if occurred && !had_occurred {
+				transitions.push(ThrottleTransition::OccurredSinceBoot(condition));
+			}
+
+			stats.active = active;
+			stats.occurred_since_boot = occurred;
+		}
+
+		for transition in transitions.iter() {
+			log::debug!("throttle transition: {:?}", transition);
+		}
+
+		self.first.get_or_insert(at);
+		self.last = Some(Reading {
+			at,
+			current: reading.current,
+			occurred: reading.occured,
+		});
+		self.readings += 1;
+
+		transitions
+	}
+
+	pub fn stats(&self, condition: ThrottleCondition) -> ThrottleConditionStats {
+		self.stats[condition.index()]
+	}
+
+	pub fn summary(&self) -> ThrottleSummary {
+		let observed = match (self.first, self.last) {
+			(Some(first), Some(last)) => last.at - first,
+			_ => Duration::ZERO,
+		};
+
+		ThrottleSummary {
+			observed,
+			readings: self.readings,
+			under_voltage: self.stats(ThrottleCondition::UnderVoltage),
+			frequency_capped: self.stats(ThrottleCondition::FrequencyCapped),
+			throttled: self.stats(ThrottleCondition::Throttled),
+			soft_temperature_limit: self.stats(ThrottleCondition::SoftTemperatureLimit),
+		}
+	}
+
+	/// Forgets all readings and statistics.
+	pub fn reset(&mut self) {
+		*self = Self::default();
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::{Duration, Instant};
+
+	use super::{ThrottleCondition, ThrottleTracker, ThrottleTransition};
+	use crate::gencmd::commands::CpuThrottled;
+
+	#[test]
+	fn reports_started_and_ended_transitions() {
+		let start = Instant::now();
+		let mut tracker = ThrottleTracker::new();
+
+		assert_eq!(tracker.update_at(&CpuThrottled::from(0x0), start), vec![]);
+		assert_eq!(
+			tracker.update_at(&CpuThrottled::from(0x50005), start + Duration::from_secs(1)),
+			vec![
+				ThrottleTransition::Started(ThrottleCondition::UnderVoltage),
+				ThrottleTransition::OccurredSinceBoot(ThrottleCondition::UnderVoltage),
+				ThrottleTransition::Started(ThrottleCondition::Throttled),
+				ThrottleTransition::OccurredSinceBoot(ThrottleCondition::Throttled),
+			]
+		);
+		assert_eq!(
+			tracker.update_at(&CpuThrottled::from(0x50004), start + Duration::from_secs(2)),
+			vec![ThrottleTransition::Ended(ThrottleCondition::UnderVoltage)]
+		);
+	}
+
+	#[test]
+	fn accumulates_durations_and_counts() {
+		let start = Instant::now();
+		let mut tracker = ThrottleTracker::new();
+
+		for (second, value) in [
+			(0, 0x0),
+			(10, 0x10001),
+			(25, 0x10000),
+			(40, 0x10001),
+			(45, 0x10001),
+		] {
+			tracker.update_at(
+				&CpuThrottled::from(value),
+				start + Duration::from_secs(second),
+			);
+		}
+
+		let summary = tracker.summary();
+		assert_eq!(summary.observed, Duration::from_secs(45));
+		assert_eq!(summary.readings, 5);
+		assert_eq!(summary.under_voltage.count, 2);
+		assert_eq!(
+			summary.under_voltage.active_duration,
+			Duration::from_secs(20)
+		);
+		assert!(summary.under_voltage.active);
+		assert!(summary.under_voltage.occurred_since_boot);
+		assert_eq!(summary.throttled.count, 0);
+		assert_eq!(summary.throttled.active_duration, Duration::ZERO);
+	}
+
+	#[test]
+	fn ignores_readings_out_of_order() {
+		let start = Instant::now() + Duration::from_secs(10);
+		let mut tracker = ThrottleTracker::new();
+
+		tracker.update_at(&CpuThrottled::from(0x2), start);
+		tracker.update_at(&CpuThrottled::from(0x2), start - Duration::from_secs(5));
+
+		assert_eq!(
+			tracker
+				.stats(ThrottleCondition::FrequencyCapped)
+				.active_duration,
+			Duration::ZERO
+		);
+	}
+}
@@ -1,17 +1,29 @@
-use std::borrow::BorrowMut;
+use std::{borrow::BorrowMut, time::Duration};
 
+#[cfg(global_instance_available)]
+use crate::global::GlobalInstance;
 use crate::{
-	error::{GencmdCmdError, GencmdInitError},
-	gencmd::{Command, Gencmd},
-	global::GlobalInstance,
+	backend::GencmdBackend,
+	error::GencmdCmdError,
+	gencmd::{
+		batch::Batch,
+		capabilities::Capabilities,
+		retry::{Retried, RetryPolicy},
+		Command, Gencmd,
+	},
 };
 
-pub struct GencmdUnique<
-	G: BorrowMut<Gencmd> = Gencmd,
-	I: BorrowMut<GlobalInstance> = GlobalInstance,
->(pub G, pub I);
+#[cfg(global_instance_available)]
+pub struct GencmdUnique<G: BorrowMut<Gencmd> = Gencmd, B: GencmdBackend = GlobalInstance>(
+	pub G,
+	pub B,
+);
+/// Without the global instance there is no default backend, see [`from_backend`](Self::from_backend).
+#[cfg(not(global_instance_available))]
+pub struct GencmdUnique<G: BorrowMut<Gencmd>, B: GencmdBackend>(pub G, pub B);
+#[cfg(global_instance_available)]
 impl GencmdUnique {
-	pub fn new() -> Result<Self, GencmdInitError> {
+	pub fn new() -> Result<Self, crate::error::GencmdInitError> {
 		let instance = GlobalInstance::new()?;
 
 		let gencmd = Gencmd::new();
@@ -19,14 +31,75 @@ impl GencmdUnique {
 		Ok(GencmdUnique(gencmd, instance))
 	}
 }
-impl<G: BorrowMut<Gencmd>, I: BorrowMut<GlobalInstance>> GencmdUnique<G, I> {
+impl<B: GencmdBackend> GencmdUnique<Gencmd, B> {
+	pub fn from_backend(backend: B) -> Self {
+		GencmdUnique(Gencmd::new(), backend)
+	}
+}
+impl<G: BorrowMut<Gencmd>, B: GencmdBackend> GencmdUnique<G, B> {
 	pub fn send_cmd_raw(&mut self, command: &str) -> Result<&str, GencmdCmdError> {
+		self.0.borrow_mut().send_cmd_raw(&mut self.1, command)
+	}
+
+	pub fn send_cmd<'a, C: Command<'a>>(&'a mut self) -> Result<C::Response, GencmdCmdError> {
+		self.0.borrow_mut().send_cmd::<C>(&mut self.1)
+	}
+
+	/// See [`Gencmd::send_cmd_raw_timeout`].
+	pub fn send_cmd_raw_timeout(
+		&mut self,
+		command: &str,
+		timeout: Duration,
+	) -> Result<&str, GencmdCmdError> {
 		self.0
 			.borrow_mut()
-			.send_cmd_raw(self.1.borrow_mut(), command)
+			.send_cmd_raw_timeout(&mut self.1, command, timeout)
 	}
 
-	pub fn send_cmd<'a, C: Command<'a>>(&'a mut self) -> Result<C::Response, GencmdCmdError> {
-		self.0.borrow_mut().send_cmd::<C>(self.1.borrow_mut())
+	/// See [`Gencmd::send_cmd_timeout`].
+	pub fn send_cmd_timeout<'a, C: Command<'a>>(
+		&'a mut self,
+		timeout: Duration,
+	) -> Result<C::Response, GencmdCmdError> {
+		self.0
+			.borrow_mut()
+			.send_cmd_timeout::<C>(&mut self.1, timeout)
+	}
+
+	/// See [`Gencmd::send_cmd_raw_retry`].
+	pub fn send_cmd_raw_retry(
+		&mut self,
+		command: &str,
+		policy: &RetryPolicy,
+	) -> Result<Retried<&str>, GencmdCmdError> {
+		self.0
+			.borrow_mut()
+			.send_cmd_raw_retry(&mut self.1, command, policy)
+	}
+
+	/// See [`Gencmd::send_cmd_retry`].
+	pub fn send_cmd_retry<'a, C: Command<'a>>(
+		&'a mut self,
+		policy: &RetryPolicy,
+	) -> Result<Retried<C::Response>, GencmdCmdError> {
+		self.0.borrow_mut().send_cmd_retry::<C>(&mut self.1, policy)
+	}
+
+	/// See [`Gencmd::send_batch`].
+	pub fn send_batch<C: Batch>(&mut self) -> C::Output {
+		self.0.borrow_mut().send_batch::<C>(&mut self.1)
+	}
+
+	/// See [`Gencmd::send_batch_raw`].
+	pub fn send_batch_raw<'c>(
+		&mut self,
+		commands: impl IntoIterator<Item = &'c str>,
+	) -> Vec<Result<String, GencmdCmdError>> {
+		self.0.borrow_mut().send_batch_raw(&mut self.1, commands)
+	}
+
+	/// See [`Gencmd::load_capabilities`].
+	pub fn load_capabilities(&mut self) -> Result<&Capabilities, GencmdCmdError> {
+		self.0.borrow_mut().load_capabilities(&mut self.1)
 	}
 }
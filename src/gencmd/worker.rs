@@ -0,0 +1,89 @@
+//! Worker thread owning a backend, shared by the handle types.
+
+use std::{
+	panic::AssertUnwindSafe,
+	sync::{mpsc, Mutex, PoisonError},
+	thread::JoinHandle,
+};
+
+use crate::{backend::GencmdBackend, error::GencmdCmdError, gencmd::Gencmd};
+
+pub(crate) type Job = Box<dyn FnOnce(&mut Gencmd, &mut dyn GencmdBackend) + Send>;
+
+/// Runs jobs in order on a dedicated thread which owns the [`Gencmd`] buffer and the backend.
+///
+/// Dropping the worker [shuts it down](Self::shutdown).
+pub(crate) struct Worker {
+	sender: Mutex<Option<mpsc::Sender<Job>>>,
+	thread: Mutex<Option<JoinHandle<()>>>,
+}
+impl Worker {
+	pub fn spawn(mut gencmd: Gencmd, mut backend: impl GencmdBackend + Send + 'static) -> Self {
+		let (sender, receiver) = mpsc::channel::<Job>();
+
+		let thread = std::thread::Builder::new()
+			.name("gencmd-worker".to_string())
+			.spawn(move || {
+				for job in receiver {
+					// the waiting caller sees the dropped response channel, the worker keeps serving others
+					if std::panic::catch_unwind(AssertUnwindSafe(|| job(&mut gencmd, &mut backend)))
+						.is_err()
+					{
+						log::error!("gencmd worker job panicked");
+					}
+				}
+				log::debug!("gencmd worker stopped");
+			})
+			.expect("failed to spawn gencmd worker thread");
+
+		Worker {
+			sender: Mutex::new(Some(sender)),
+			thread: Mutex::new(Some(thread)),
+		}
+	}
+
+	/// Queues `job`, failing with [`GencmdCmdError::WorkerStopped`] after [`shutdown`](Self::shutdown).
+	pub fn submit(&self, job: Job) -> Result<(), GencmdCmdError> {
+		// none of the locks are held across user code, so they cannot be poisoned in a meaningful way
+		let sender = self.sender.lock().unwrap_or_else(PoisonError::into_inner);
+
+		sender
+			.as_ref()
+			.ok_or(GencmdCmdError::WorkerStopped)?
+			.send(job)
+			.map_err(|_| GencmdCmdError::WorkerStopped)
+	}
+
+	/// Stops accepting jobs and waits until the queued ones are processed.
+	///
+	/// Does not wait when called from a job, as the worker would wait for itself.
+	pub fn shutdown(&self) {
+		self.sender
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.take();
+
+		let thread = self
+			.thread
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.take();
+		let thread = match thread {
+			Some(thread) => thread,
+			None => return,
+		};
+		if thread.thread().id() == std::thread::current().id() {
+			log::debug!("gencmd worker shut down by one of its jobs");
+			return;
+		}
+
+		if thread.join().is_err() {
+			log::error!("gencmd worker thread panicked");
+		}
+	}
+}
+impl Drop for Worker {
+	fn drop(&mut self) {
+		self.shutdown();
+	}
+}
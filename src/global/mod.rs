@@ -1,19 +1,58 @@
 use std::{
 	ffi::CStr,
-	sync::atomic::{AtomicBool, Ordering as AtomicOrdering},
+	sync::{
+		atomic::{AtomicBool, Ordering as AtomicOrdering},
+		OnceLock,
+	},
+	time::Duration,
 };
 
-use crate::{error::*, ffi};
+#[cfg(native_vchiq_instance)]
+use crate::backend::vchiq::VchiqBackend;
+use crate::{backend::GencmdBackend, error::*, ffi, gencmd::capabilities::Capabilities};
 
+use self::reconnect::{Health, ReconnectPolicy};
+#[cfg(not(native_vchiq_instance))]
+use self::watchdog::ReadWatchdog;
+
+pub mod reconnect;
 #[cfg(feature = "global_singleton")]
 pub mod singleton;
+#[cfg(not(native_vchiq_instance))]
+mod watchdog;
 
 static ONE_INSTANCE: AtomicBool = AtomicBool::new(false);
 
+/// Default time to wait for a response before giving up.
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The process-wide connection to the gencmd service.
+///
+/// With the `native_vchiq` feature this uses the [`VchiqBackend`](crate::backend::vchiq::VchiqBackend)
+/// instead of the VideoCore FFI.
+///
+/// Reads of responses through the FFI give up after the [read timeout](Self::set_read_timeout), see [`retrieve_response_timeout`](Self::retrieve_response_timeout).
+///
+/// If the connection is lost (e.g. when the VideoCore service restarts), it is reestablished according to the
+/// [reconnect policy](Self::set_reconnect_policy). This also applies to the instance shared by the singleton.
+///
+/// The [`Capabilities`] of the firmware are fetched once per instance and shared by every [`Gencmd`](crate::gencmd::Gencmd) using it.
 pub struct GlobalInstance {
+	#[cfg(not(native_vchiq_instance))]
 	instance: ffi::VCHI_INSTANCE_T,
+	#[cfg(not(native_vchiq_instance))]
 	#[allow(dead_code)] // :shrug:
 	connection: *mut ffi::VCHI_CONNECTION_T,
+	#[cfg(not(native_vchiq_instance))]
+	watchdog: Option<ReadWatchdog>,
+	#[cfg(native_vchiq_instance)]
+	backend: Option<VchiqBackend>,
+	read_timeout: Option<Duration>,
+	reconnect: Option<ReconnectPolicy>,
+	health: Health,
+	/// Consecutive failed reads.
+	read_failures: u32,
+	capabilities: OnceLock<Capabilities>,
 }
 impl GlobalInstance {
 	/// Initializes a new instance of videocore connection.
@@ -31,6 +70,60 @@ impl GlobalInstance {
 
 		log::info!("Initializing videocore gencmd instance");
 
+		Self::init()
+	}
+
+	#[cfg(native_vchiq_instance)]
+	fn init() -> Result<Self, GencmdInitError> {
+		match VchiqBackend::new() {
+			Ok(backend) => Ok(GlobalInstance {
+				backend: Some(backend),
+				read_timeout: Some(DEFAULT_READ_TIMEOUT),
+				reconnect: Some(ReconnectPolicy::new()),
+				health: Health::Healthy,
+				read_failures: 0,
+				capabilities: OnceLock::new(),
+			}),
+			Err(err) => {
+				ONE_INSTANCE.store(false, AtomicOrdering::Release);
+				Err(err)
+			}
+		}
+	}
+
+	#[cfg(not(native_vchiq_instance))]
+	fn init() -> Result<Self, GencmdInitError> {
+		let result = Self::init_ffi();
+		if result.is_err() {
+			ONE_INSTANCE.store(false, AtomicOrdering::Release);
+		}
+
+		result
+	}
+
+	#[cfg(not(native_vchiq_instance))]
+	fn init_ffi() -> Result<Self, GencmdInitError> {
+		let (instance, connection) = Self::connect_ffi()?;
+
+		Ok(GlobalInstance {
+			instance,
+			connection,
+			watchdog: None,
+			read_timeout: Some(DEFAULT_READ_TIMEOUT),
+			reconnect: Some(ReconnectPolicy::new()),
+			health: Health::Healthy,
+			read_failures: 0,
+			capabilities: OnceLock::new(),
+		})
+	}
+
+	#[cfg(not(native_vchiq_instance))]
+	fn connect_ffi() -> Result<(ffi::VCHI_INSTANCE_T, *mut ffi::VCHI_CONNECTION_T), GencmdInitError>
+	{
+		#[cfg(all(feature = "dlopen_vc_ffi", not(feature = "mock_vc_ffi")))]
+		ffi::dynamic::load_libraries()
+			.map_err(|err| GencmdInitError::LibraryLoad(Box::new(err)))?;
+
 		unsafe {
 			ffi::vcos_init()
 				.to_result()
@@ -40,11 +133,13 @@ impl GlobalInstance {
 		let mut instance: ffi::VCHI_INSTANCE_T = std::ptr::null_mut();
 		let result = unsafe { ffi::vchi_initialise(&mut instance) };
 		if result != 0 || instance == std::ptr::null_mut() {
+			unsafe { ffi::vcos_deinit() };
 			return Err(GencmdInitError::VchiInit);
 		}
 
 		let result = unsafe { ffi::vchi_connect(std::ptr::null_mut(), 0, instance) };
 		if result != 0 {
+			unsafe { ffi::vcos_deinit() };
 			return Err(GencmdInitError::VchiConnect);
 		}
 
@@ -54,10 +149,129 @@ impl GlobalInstance {
 		log::debug!("instance: {:p}", instance);
 		log::debug!("connection: {:p}", connection);
 
-		Ok(GlobalInstance {
-			instance,
-			connection,
-		})
+		Ok((instance, connection))
+	}
+
+	pub fn reconnect_policy(&self) -> Option<&ReconnectPolicy> {
+		self.reconnect.as_ref()
+	}
+
+	/// Sets or clears (disabling automatic reconnection) the policy used to reestablish a lost connection.
+	pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+		self.reconnect = policy;
+	}
+
+	/// Returns false if a command failed or the connection could not be reestablished.
+	pub fn is_healthy(&self) -> bool {
+		self.health == Health::Healthy
+	}
+
+	/// Tears down the connection and establishes it again, according to the reconnect policy (or the default one if disabled).
+	///
+	/// Unlike [`deinit`](Self::deinit), tearing down does not stop at a failed disconnect: the error is logged and vcos is
+	/// deinitialized anyway, so that initializing it again does not stack on top of the old state.
+	///
+	/// If all attempts fail the instance stays owned but disconnected, commands fail until a reconnection succeeds.
+	///
+	/// ### Panic
+	/// Will panic if this instance has been deinitialized.
+	pub fn reconnect(&mut self) -> Result<(), GencmdInitError> {
+		if self.is_deinitialized() {
+			panic!("This instance has been deinitialized");
+		}
+
+		log::warn!("Reconnecting videocore gencmd instance");
+
+		if self.health != Health::Lost {
+			self.teardown();
+			self.health = Health::Lost;
+		}
+
+		let policy = self.reconnect.clone().unwrap_or_default();
+		policy.run(|| self.connect_inner())?;
+		self.health = Health::Healthy;
+		self.read_failures = 0;
+
+		Ok(())
+	}
+
+	#[cfg(native_vchiq_instance)]
+	fn connect_inner(&mut self) -> Result<(), GencmdInitError> {
+		self.backend = Some(VchiqBackend::new()?);
+
+		Ok(())
+	}
+
+	#[cfg(not(native_vchiq_instance))]
+	fn connect_inner(&mut self) -> Result<(), GencmdInitError> {
+		let (instance, connection) = Self::connect_ffi()?;
+		self.instance = instance;
+		self.connection = connection;
+
+		Ok(())
+	}
+
+	/// Tears down a lost connection, logging errors instead of stopping at them.
+	#[cfg(native_vchiq_instance)]
+	fn teardown(&mut self) {
+		if let Some(mut backend) = self.backend.take() {
+			if let Err(err) = backend.close() {
+				log::error!("failed to tear down the lost connection: {}", err);
+			}
+		}
+	}
+
+	/// Tears down a lost connection, logging errors instead of stopping at them.
+	#[cfg(not(native_vchiq_instance))]
+	fn teardown(&mut self) {
+		self.watchdog = None;
+		unsafe { ffi::vc_gencmd_stop() };
+
+		let result = unsafe { ffi::vchi_disconnect(self.instance) };
+		if result != 0 {
+			log::error!(
+				"failed to tear down the lost connection: {}",
+				GencmdDeinitError::VchiDisconnect
+			);
+		}
+
+		// vcos is initialized again by the reconnect, whether or not the disconnect succeeded
+		unsafe { ffi::vcos_deinit() };
+
+		self.instance = std::ptr::null_mut();
+	}
+
+	/// Reconnects before a command if the connection is suspected to be lost.
+	fn ensure_connected(&mut self) -> Result<(), GencmdCmdError> {
+		match (self.health, self.reconnect.is_some()) {
+			(Health::Healthy, _) | (Health::Suspect, false) => Ok(()),
+			(Health::Lost, false) => Err(GencmdCmdError::Send),
+			(_, true) => self.reconnect().map_err(GencmdCmdError::Reconnect),
+		}
+	}
+
+	pub fn read_timeout(&self) -> Option<Duration> {
+		self.read_timeout
+	}
+
+	/// Sets the default timeout of [`retrieve_response`](Self::retrieve_response), `None` blocks indefinitely.
+	pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+		self.read_timeout = timeout;
+	}
+
+	/// Returns true if a read timed out and the firmware has not answered it yet.
+	///
+	/// Sending another command first waits up to the read timeout for the stale response and discards it,
+	/// failing with [`GencmdCmdError::Timeout`] if it still does not arrive.
+	#[cfg(not(native_vchiq_instance))]
+	pub fn needs_recovery(&self) -> bool {
+		self.watchdog.as_ref().is_some_and(ReadWatchdog::is_pending)
+	}
+
+	/// Returns true if a read timed out and the firmware has not answered it yet.
+	#[cfg(native_vchiq_instance)]
+	pub fn needs_recovery(&self) -> bool {
+		false
 	}
 
 	/// Sends a command to the instance.
@@ -69,8 +283,6 @@ impl GlobalInstance {
 	/// Looks like the response must be picked up before another thread issues a send, otherwise
 	/// the entire _system_ gets broken and all communication with vc gencmd starts going haywire.
 	pub unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
-		const FORMAT: &'static [u8] = b"%s\0";
-
 		if self.is_deinitialized() {
 			panic!("This instance has been deinitialized");
 		}
@@ -79,6 +291,32 @@ impl GlobalInstance {
 			return Err(GencmdCmdError::CommandTooLong);
 		}
 
+		self.ensure_connected()?;
+
+		match self.send_command_inner(command) {
+			Err(GencmdCmdError::Send) if self.reconnect.is_some() => {
+				log::warn!("sending failed, the connection might be lost");
+				self.reconnect().map_err(GencmdCmdError::Reconnect)?;
+
+				self.send_command_inner(command)
+			}
+			result => result,
+		}
+	}
+
+	#[cfg(native_vchiq_instance)]
+	unsafe fn send_command_inner(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		self.backend.as_mut().unwrap().send_command(command)
+	}
+
+	#[cfg(not(native_vchiq_instance))]
+	unsafe fn send_command_inner(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		const FORMAT: &'static [u8] = b"%s\0";
+
+		if let Some(ref mut watchdog) = self.watchdog {
+			watchdog.recover(self.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT))?;
+		}
+
 		// SAFETY: Things are initialized, the strings are null terminated,
 		// the format takes one string argument (internally calls vsnprintf)
 		// There are also no races because internally this locks a mutex.
@@ -96,17 +334,107 @@ impl GlobalInstance {
 		Ok(())
 	}
 
-	/// Retrieves the response from the instance.
+	/// Retrieves the response from the instance, giving up after the [read timeout](Self::set_read_timeout).
 	///
 	/// Returns number of bytes read into `buffer` (excluding the null terminator).
 	///
 	/// ### Panic
 	/// Will panic if this instance has been deinitialized.
 	pub fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		let timeout = self.read_timeout;
+
+		self.retrieve_response_with(buffer, timeout)
+	}
+
+	/// Retrieves the response from the instance, giving up after `timeout`.
+	///
+	/// The read runs on a separate thread. On expiry [`GencmdCmdError::Timeout`] is returned and the instance
+	/// [needs recovery](Self::needs_recovery) so that the late response is not mistaken for the response to the next command.
+	///
+	/// ### Panic
+	/// Will panic if this instance has been deinitialized.
+	pub fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		self.retrieve_response_with(buffer, Some(timeout))
+	}
+
+	fn retrieve_response_with(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Option<Duration>,
+	) -> Result<usize, GencmdCmdError> {
 		if self.is_deinitialized() {
 			panic!("This instance has been deinitialized");
 		}
 
+		let len = match self.retrieve_response_inner(buffer, timeout) {
+			Err(GencmdCmdError::Read) => {
+				self.read_failed();
+				return Err(GencmdCmdError::Read);
+			}
+			result => result?,
+		};
+		self.read_failures = 0;
+
+		log::debug!(
+			"retrieved vc response: {:?}",
+			CStr::from_bytes_with_nul(&buffer[..=len]).unwrap()
+		);
+
+		Ok(len)
+	}
+
+	/// Only repeated read failures suggest a lost connection, a single one may be caused by the response itself.
+	fn read_failed(&mut self) {
+		self.read_failures += 1;
+
+		let threshold = match self.reconnect {
+			Some(ref policy) => policy.read_failures(),
+			None => ReconnectPolicy::DEFAULT_READ_FAILURES,
+		};
+		if self.read_failures >= threshold && self.health == Health::Healthy {
+			log::warn!(
+				"{} reads failed in a row, the connection might be lost",
+				self.read_failures
+			);
+			self.health = Health::Suspect;
+		}
+	}
+
+	#[cfg(native_vchiq_instance)]
+	fn retrieve_response_inner(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Option<Duration>,
+	) -> Result<usize, GencmdCmdError> {
+		let backend = self.backend.as_mut().unwrap();
+
+		match timeout {
+			Some(timeout) => backend.retrieve_response_timeout(buffer, timeout),
+			None => backend.retrieve_response(buffer),
+		}
+	}
+
+	#[cfg(not(native_vchiq_instance))]
+	fn retrieve_response_inner(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Option<Duration>,
+	) -> Result<usize, GencmdCmdError> {
+		match timeout {
+			Some(timeout) => self
+				.watchdog
+				.get_or_insert_with(|| ReadWatchdog::spawn(Self::read_ffi))
+				.read(buffer, timeout),
+			None => Self::read_ffi(buffer),
+		}
+	}
+
+	#[cfg(not(native_vchiq_instance))]
+	fn read_ffi(buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
 		// SAFETY: we have mutable access to buffer and pass in the correct buffer len
 		// There are also no races because internally this locks a mutex.
 		let result = unsafe {
@@ -120,19 +448,25 @@ impl GlobalInstance {
 		}
 
 		// strlen, but sane
-		let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
-
-		log::debug!(
-			"retrieved vc response: {:?}",
-			CStr::from_bytes_with_nul(&buffer[..=len]).unwrap()
-		);
+		match buffer.iter().position(|&b| b == 0) {
+			Some(len) => Ok(len),
+			None => {
+				log::error!("vc response is not null terminated");
+				Err(GencmdCmdError::Read)
+			}
+		}
+	}
 
-		Ok(len)
+	/// Returns true if `self.deinit` has been called at least once on this instance.
+	#[cfg(native_vchiq_instance)]
+	pub fn is_deinitialized(&self) -> bool {
+		self.backend.is_none() && self.health != Health::Lost
 	}
 
 	/// Returns true if `self.deinit` has been called at least once on this instance.
+	#[cfg(not(native_vchiq_instance))]
 	pub fn is_deinitialized(&self) -> bool {
-		self.instance == std::ptr::null_mut()
+		self.instance.is_null() && self.health != Health::Lost
 	}
 
 	/// Deinitializes `self`, returning a potential error.
@@ -155,6 +489,29 @@ impl GlobalInstance {
 
 		log::info!("Deinitializing videocore gencmd instance");
 
+		// a lost connection has already been torn down
+		if self.health != Health::Lost {
+			self.deinit_inner()?;
+		}
+		self.health = Health::Healthy;
+
+		ONE_INSTANCE.store(false, AtomicOrdering::Release);
+
+		Ok(())
+	}
+
+	#[cfg(native_vchiq_instance)]
+	fn deinit_inner(&mut self) -> Result<(), GencmdDeinitError> {
+		self.backend.as_mut().unwrap().close()?;
+		self.backend = None;
+
+		Ok(())
+	}
+
+	#[cfg(not(native_vchiq_instance))]
+	fn deinit_inner(&mut self) -> Result<(), GencmdDeinitError> {
+		// a read stuck in the firmware is released by the stop below
+		self.watchdog = None;
 		unsafe { ffi::vc_gencmd_stop() };
 
 		let result = unsafe { ffi::vchi_disconnect(self.instance) };
@@ -166,13 +523,36 @@ impl GlobalInstance {
 
 		self.instance = std::ptr::null_mut();
 
-		ONE_INSTANCE.store(false, AtomicOrdering::Release);
-
 		Ok(())
 	}
 }
 // SAFETY: The vc state is process-wide
 unsafe impl Send for GlobalInstance {}
+impl GencmdBackend for GlobalInstance {
+	unsafe fn send_command(&mut self, command: &CStr) -> Result<(), GencmdCmdError> {
+		GlobalInstance::send_command(self, command)
+	}
+
+	fn retrieve_response(&mut self, buffer: &mut [u8]) -> Result<usize, GencmdCmdError> {
+		GlobalInstance::retrieve_response(self, buffer)
+	}
+
+	fn retrieve_response_timeout(
+		&mut self,
+		buffer: &mut [u8],
+		timeout: Duration,
+	) -> Result<usize, GencmdCmdError> {
+		GlobalInstance::retrieve_response_timeout(self, buffer, timeout)
+	}
+
+	fn supports_timeout(&self) -> bool {
+		true
+	}
+
+	fn shared_capabilities(&self) -> Option<&OnceLock<Capabilities>> {
+		Some(&self.capabilities)
+	}
+}
 impl Drop for GlobalInstance {
 	fn drop(&mut self) {
 		match self.deinit_ref_mut() {
@@ -184,31 +564,3 @@ impl Drop for GlobalInstance {
 		}
 	}
 }
-
-// these tests will only pass if there are no other tests that use the global singleton (such as with --test-threads 1)
-#[cfg(test)]
-mod test {
-	use super::{GencmdInitError, GlobalInstance};
-
-	#[test]
-	#[ignore]
-	fn global_instance_is_unique() {
-		let instance = GlobalInstance::new().unwrap();
-
-		match GlobalInstance::new() {
-			Err(GencmdInitError::AlreadyInitialized) => (),
-			_ => panic!("global instance must be unique"),
-		}
-
-		instance.deinit().unwrap();
-	}
-
-	#[test]
-	#[ignore]
-	fn global_instance_is_unique_respects_drop() {
-		let instance = GlobalInstance::new().unwrap();
-		std::mem::drop(instance);
-
-		GlobalInstance::new().unwrap();
-	}
-}
@@ -0,0 +1,150 @@
+//! Reconnection of a [`GlobalInstance`](super::GlobalInstance) whose connection was lost.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::error::GencmdInitError;
+
+/// Notification about a reconnection of the instance.
+#[derive(Debug)]
+pub enum ReconnectEvent<'a> {
+	/// The connection was reestablished after `attempts` attempts.
+	Reconnected { attempts: u32 },
+	/// All `attempts` failed, the last one with `error`.
+	Failed {
+		attempts: u32,
+		error: &'a GencmdInitError,
+	},
+}
+
+type Callback = Arc<dyn Fn(&ReconnectEvent) + Send + Sync>;
+
+/// How a lost connection is reestablished.
+///
+/// When sending a command fails or `read_failures` responses in a row cannot be read, the connection is considered lost.
+/// A single failed read (e.g. a buffer too small for the response) is not enough. Before the next command is sent,
+/// the instance is deinitialized and initialized again, making up to `max_attempts` attempts `delay` apart.
+#[derive(Clone)]
+pub struct ReconnectPolicy {
+	max_attempts: u32,
+	delay: Duration,
+	read_failures: u32,
+	callback: Option<Callback>,
+}
+impl ReconnectPolicy {
+	pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+	pub const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+	pub const DEFAULT_READ_FAILURES: u32 = 3;
+
+	pub const fn new() -> Self {
+		ReconnectPolicy {
+			max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+			delay: Self::DEFAULT_DELAY,
+			read_failures: Self::DEFAULT_READ_FAILURES,
+			callback: None,
+		}
+	}
+
+	/// Sets the number of attempts per reconnection, at least one attempt is always made.
+	pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+		self.max_attempts = max_attempts;
+		self
+	}
+
+	/// Sets the delay between attempts.
+	pub fn with_delay(mut self, delay: Duration) -> Self {
+		self.delay = delay;
+		self
+	}
+
+	/// Sets the number of consecutive failed reads after which the connection is considered lost, at least one.
+	pub fn with_read_failures(mut self, read_failures: u32) -> Self {
+		self.read_failures = read_failures.max(1);
+		self
+	}
+
+	/// Calls `callback` after each reconnection, successful or not.
+	pub fn on_reconnect(
+		mut self,
+		callback: impl Fn(&ReconnectEvent) + Send + Sync + 'static,
+	) -> Self {
+		self.callback = Some(Arc::new(callback));
+		self
+	}
+
+	pub fn max_attempts(&self) -> u32 {
+		self.max_attempts
+	}
+
+	pub fn delay(&self) -> Duration {
+		self.delay
+	}
+
+	pub fn read_failures(&self) -> u32 {
+		self.read_failures
+	}
+
+	/// Calls `connect` until it succeeds or the attempts run out, notifying the callback.
+	pub(crate) fn run(
+		&self,
+		mut connect: impl FnMut() -> Result<(), GencmdInitError>,
+	) -> Result<(), GencmdInitError> {
+		let mut attempts = 0;
+
+		loop {
+			attempts += 1;
+
+			match connect() {
+				Ok(()) => {
+					log::info!("reconnected after {} attempts", attempts);
+					self.notify(&ReconnectEvent::Reconnected { attempts });
+
+					return Ok(());
+				}
+				Err(error) if attempts >= self.max_attempts => {
+					log::error!("failed to reconnect after {} attempts: {}", attempts, error);
+					self.notify(&ReconnectEvent::Failed {
+						attempts,
+						error: &error,
+					});
+
+					return Err(error);
+				}
+				Err(error) => {
+					log::warn!("reconnect attempt {} failed: {}", attempts, error);
+					std::thread::sleep(self.delay);
+				}
+			}
+		}
+	}
+
+	fn notify(&self, event: &ReconnectEvent) {
+		if let Some(ref callback) = self.callback {
+			callback(event);
+		}
+	}
+}
+impl Default for ReconnectPolicy {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+impl std::fmt::Debug for ReconnectPolicy {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("ReconnectPolicy")
+			.field("max_attempts", &self.max_attempts)
+			.field("delay", &self.delay)
+			.field("read_failures", &self.read_failures)
+			.field("callback", &self.callback.is_some())
+			.finish()
+	}
+}
+
+/// State of the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Health {
+	Healthy,
+	/// Sending failed or reads keep failing, the connection is reestablished before the next command.
+	Suspect,
+	/// The connection was torn down and could not be reestablished.
+	Lost,
+}
@@ -0,0 +1,104 @@
+//! Response reads which can time out.
+//!
+//! A blocking read cannot be cancelled, so it runs on a separate thread and the caller stops waiting for it after a timeout.
+//! The abandoned read is still pending on the firmware and must complete before another command is sent,
+//! otherwise it would consume the response to that command.
+
+use std::{
+	sync::mpsc::{self, RecvTimeoutError},
+	time::Duration,
+};
+
+use crate::error::GencmdCmdError;
+
+type ReadResult = (Result<usize, GencmdCmdError>, Vec<u8>);
+
+pub(crate) struct ReadWatchdog {
+	requests: mpsc::Sender<Vec<u8>>,
+	responses: mpsc::Receiver<ReadResult>,
+	pending: bool,
+	spare: Vec<u8>,
+}
+impl ReadWatchdog {
+	pub fn spawn(read: fn(&mut [u8]) -> Result<usize, GencmdCmdError>) -> Self {
+		let (requests, request_receiver) = mpsc::channel::<Vec<u8>>();
+		let (response_sender, responses) = mpsc::channel();
+
+		std::thread::Builder::new()
+			.name("gencmd-read".to_string())
+			.spawn(move || {
+				for mut buffer in request_receiver {
+					let result = read(&mut buffer);
+					if response_sender.send((result, buffer)).is_err() {
+						break;
+					}
+				}
+			})
+			.expect("failed to spawn gencmd read thread");
+
+		ReadWatchdog {
+			requests,
+			responses,
+			pending: false,
+			spare: Vec::new(),
+		}
+	}
+
+	/// Returns true if a read timed out and has not completed yet.
+	pub fn is_pending(&self) -> bool {
+		self.pending
+	}
+
+	/// Waits up to `timeout` for a read that timed out earlier and discards its response.
+	pub fn recover(&mut self, timeout: Duration) -> Result<(), GencmdCmdError> {
+		if !self.pending {
+			return Ok(());
+		}
+
+		let (result, buffer) = self.wait(timeout)?;
+		log::warn!(
+			"discarded stale vc response: {:?}",
+			result.map(|len| String::from_utf8_lossy(&buffer[..len]).into_owned())
+		);
+		self.spare = buffer;
+
+		Ok(())
+	}
+
+	/// Reads the response into `buffer`, giving up after `timeout`.
+	pub fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> Result<usize, GencmdCmdError> {
+		debug_assert!(!self.pending, "read started while another is pending");
+
+		let mut request = std::mem::take(&mut self.spare);
+		request.clear();
+		request.resize(buffer.len(), 0);
+		self.requests
+			.send(request)
+			.map_err(|_| GencmdCmdError::Read)?;
+		self.pending = true;
+
+		let (result, response) = self.wait(timeout)?;
+		let len = result?;
+		buffer[..=len].copy_from_slice(&response[..=len]);
+		self.spare = response;
+
+		Ok(len)
+	}
+
+	fn wait(&mut self, timeout: Duration) -> Result<ReadResult, GencmdCmdError> {
+		match self.responses.recv_timeout(timeout) {
+			Ok(response) => {
+				self.pending = false;
+				Ok(response)
+			}
+			Err(RecvTimeoutError::Timeout) => {
+				log::error!("vc response not received within {:?}", timeout);
+				Err(GencmdCmdError::Timeout(timeout))
+			}
+			Err(RecvTimeoutError::Disconnected) => {
+				self.pending = false;
+				Err(GencmdCmdError::Read)
+			}
+		}
+	}
+}
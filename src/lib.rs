@@ -19,7 +19,8 @@
 //! Attempting to create multiple instances returns an error. If you need access from multiple threads simultaneously
 //! consider using the global singleton or implementing a similar solution yourself.
 //!
-//! ```
+#![cfg_attr(global_instance_available, doc = "```")]
+#![cfg_attr(not(global_instance_available), doc = "```ignore")]
 //! use videocore_gencmd::prelude::*;
 //! fn main() -> Result<(), Box<dyn std::error::Error>> {
 //! 	let mut gencmd = GencmdUnique::new()?;
@@ -38,7 +39,14 @@
 //! to invoke gencmd interface from multiple threads at one time. For this there is the `global_singleton` feature
 //! that exposes a global, lazy-initialized, weak-pointed singleton and a convenience [`GencmdGlobal`](gencmd::global::GencmdGlobal).
 //!
-//! ```
+#![cfg_attr(
+	all(global_instance_available, feature = "global_singleton"),
+	doc = "```"
+)]
+#![cfg_attr(
+	not(all(global_instance_available, feature = "global_singleton")),
+	doc = "```ignore"
+)]
 //! use videocore_gencmd::prelude::*;
 //! fn main() {
 //! 	let temperature_measurer =
@@ -78,6 +86,31 @@
 //! }
 //! ```
 //!
+//! Alternatively a [`GencmdHandle`](gencmd::handle::GencmdHandle) can be cloned into each thread. It sends the commands through
+//! a single worker thread which owns the instance, so there is no mutex to poison and no per-thread buffer.
+//!
+//! ### Backends
+//!
+//! Commands are transported by a [`GencmdBackend`](backend::GencmdBackend). By default this is the [`GlobalInstance`](global::GlobalInstance)
+//! which uses the VideoCore FFI, but [`Gencmd`](gencmd::Gencmd), [`GencmdUnique`](gencmd::unique::GencmdUnique) and `GencmdGlobal` are generic over
+//! the backend, so other transports (such as the [`MockBackend`](backend::mock::MockBackend)) can be used in the same build.
+//! The [`SubprocessBackend`](backend::subprocess::SubprocessBackend) runs an installed `vcgencmd` executable and parses its output.
+//! Where no VideoCore interface is available, the [`SysfsBackend`](backend::sysfs::SysfsBackend) answers a few commands from sysfs.
+//! The [`SimulatedBackend`](backend::simulated::SimulatedBackend) models specific boards, including a thermal and throttling model driven by a load input.
+//! Sessions can be recorded into transcripts and replayed without the hardware using the [`transcript`](backend::transcript) backends.
+//!
+//! ```
+//! use videocore_gencmd::{backend::mock::MockBackend, prelude::*};
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let mut gencmd = GencmdUnique::from_backend(MockBackend::new());
+//!
+//!     let temperature = gencmd.send_cmd::<CmdMeasureTemp>()?;
+//!     assert_eq!(temperature, 45.6);
+//!
+//!     Ok(())
+//! }
+//! ```
+//!
 //! ## Features
 //!
 //! ### `run_bindgen`
@@ -89,6 +122,11 @@
 //!
 //! FFI bindings naturally expect a library to link against. For testing purposes this is not always ideal, so this feature instead
 //! exposes C ABI functions that match the bindings and mock the interface to some extent.
+//! The responses can be configured at runtime through `ffi::mock::MOCK_GENCMD` and failures injected through `ffi::mock::MOCK_FAULTS`.
+//!
+//! ### `mock_regex`
+//!
+//! Allows matching commands with regular expressions in [`MockGencmd`](backend::mock::MockGencmd) rules.
 //!
 //! ### `cli_app`
 //!
@@ -98,18 +136,57 @@
 //!
 //! Enables the global singleton implementation as described above.
 //!
+//! ### `vcio_backend`
+//!
+//! Enables the [`VcioBackend`](backend::vcio::VcioBackend) which sends commands through the firmware mailbox (`/dev/vcio`) and needs no C libraries.
+//! Linking of the C libraries can be disabled by setting the `VIDEOCORE_GENCMD_NO_LINK` environment variable at build time.
+//!
+//! ### `native_vchiq`
+//!
+//! Implements the vchiq protocol in Rust over the `/dev/vchiq` ioctls (see [`VchiqBackend`](backend::vchiq::VchiqBackend)) and uses it
+//! behind the [`GlobalInstance`](global::GlobalInstance) instead of the FFI. The C libraries are not linked in this case.
+//! The `mock_vc_ffi` feature takes precedence over this one.
+//!
+//! ### `dlopen_vc_ffi`
+//!
+//! Loads the VideoCore libraries at runtime (see the `ffi::dynamic` module) instead of linking them. If the libraries are missing,
+//! [`GlobalInstance::new`](global::GlobalInstance::new) returns [`GencmdInitError::LibraryLoad`](error::GencmdInitError::LibraryLoad)
+//! so that callers can fall back to another backend. The `mock_vc_ffi` feature takes precedence over this one.
+//!
+//! ### `remote`
+//!
+//! Enables the [`remote`] module with a TCP and Unix socket server and the matching [`RemoteBackend`](backend::remote::RemoteBackend) client.
+//! Together with `cli_app` this also builds the `vcgencmd-server` binary, which can also run as a local daemon sharing the firmware between processes.
+//!
+//! ### `process_lock`
+//!
+//! Enables the [`lock`] module with an advisory file lock that [`Gencmd`](gencmd::Gencmd) can take around each send/read pair
+//! (see [`Gencmd::set_process_lock`](gencmd::Gencmd::set_process_lock)) so that multiple processes can share the firmware without a daemon.
+//! Contention past the timeout is reported as [`GencmdCmdError::LockTimeout`](error::GencmdCmdError::LockTimeout).
+//!
+//! ### `async_api`
+//!
+//! Enables [`AsyncGencmd`](gencmd::asynchronous::AsyncGencmd), which owns the backend on a worker thread so that the blocking
+//! reads do not stall async executors. It works with any runtime.
+//!
 //! ### `serde_models`
 //!
 //! Derive serde `Serialize` and `Deserialize` for custom command response models.
 
+pub mod backend;
 pub mod error;
 pub mod ffi;
 pub mod gencmd;
+#[cfg(global_instance_available)]
 pub mod global;
+#[cfg(feature = "process_lock")]
+pub mod lock;
+#[cfg(feature = "remote")]
+pub mod remote;
 
 pub mod prelude;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "global_singleton", global_instance_available))]
 mod test {
 	use std::sync::Once;
 
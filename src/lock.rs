@@ -0,0 +1,190 @@
+//! Advisory lock serializing gencmd access across processes.
+//!
+//! The firmware has a single send/read channel, so two processes sending commands at the same time can receive each other's
+//! responses. Processes which all use a [`ProcessLock`] on the same path never interleave their send/read pairs.
+//!
+//! The lock uses `flock`, so it is released automatically when a process exits.
+
+use std::{
+	fs::{File, OpenOptions, Permissions},
+	os::unix::{fs::PermissionsExt, io::AsRawFd},
+	path::{Path, PathBuf},
+	time::{Duration, Instant},
+};
+
+use crate::error::GencmdCmdError;
+
+/// Default path of the lock file.
+pub const DEFAULT_LOCK_PATH: &str = "/run/lock/vcgencmd.lock";
+/// Default time to wait for the lock before giving up.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// An open lock file.
+///
+/// Note that `flock` locks belong to the open file, so the lock does not exclude clones of the same [`ProcessLock`]
+/// (which share the file) from each other. Use a mutex to serialize threads within the process.
+#[derive(Debug)]
+pub struct ProcessLock {
+	file: File,
+	path: PathBuf,
+	timeout: Duration,
+}
+impl ProcessLock {
+	/// Opens the lock file at [`DEFAULT_LOCK_PATH`].
+	pub fn new() -> std::io::Result<Self> {
+		Self::open(DEFAULT_LOCK_PATH)
+	}
+
+	/// Opens (or creates) the lock file at `path`.
+	///
+	/// A created file is made readable and writable by everyone regardless of the umask, so that processes of other users
+	/// can take the lock too. The permissions of an existing file are left alone.
+	pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+		let path = path.as_ref();
+		let file = match OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create_new(true)
+			.open(path)
+		{
+			Ok(file) => {
+				// the mode passed to open is filtered by the umask
+				file.set_permissions(Permissions::from_mode(0o666))?;
+				file
+			}
+			Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+				OpenOptions::new().read(true).write(true).open(path)?
+			}
+			Err(err) => return Err(err),
+		};
+
+		Ok(ProcessLock {
+			file,
+			path: path.to_path_buf(),
+			timeout: DEFAULT_TIMEOUT,
+		})
+	}
+
+	/// Sets how long [`acquire`](Self::acquire) waits for the lock.
+	pub fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	pub fn timeout(&self) -> Duration {
+		self.timeout
+	}
+
+	/// Takes the lock, waiting up to the timeout if another process holds it.
+	///
+	/// Returns [`GencmdCmdError::LockTimeout`] if the lock could not be taken in time.
+	pub fn acquire(&self) -> Result<ProcessLockGuard<'_>, GencmdCmdError> {
+		let deadline = Instant::now() + self.timeout;
+
+		loop {
+			// SAFETY: the file descriptor is valid for the lifetime of self
+			let result =
+				unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+			if result == 0 {
+				return Ok(ProcessLockGuard(self));
+			}
+
+			let err = std::io::Error::last_os_error();
+			match err.raw_os_error() {
+				Some(libc::EWOULDBLOCK) => (),
+				Some(libc::EINTR) => continue,
+				_ => return Err(GencmdCmdError::Lock(err)),
+			}
+
+			let now = Instant::now();
+			if now >= deadline {
+				log::warn!("timed out waiting for lock {:?}", self.path);
+				return Err(GencmdCmdError::LockTimeout(self.timeout));
+			}
+			std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+		}
+	}
+}
+
+/// Holds the lock until dropped.
+#[derive(Debug)]
+pub struct ProcessLockGuard<'a>(&'a ProcessLock);
+impl Drop for ProcessLockGuard<'_> {
+	fn drop(&mut self) {
+		// SAFETY: the file descriptor is valid for the lifetime of the lock
+		if unsafe { libc::flock(self.0.file.as_raw_fd(), libc::LOCK_UN) } != 0 {
+			log::error!(
+				"failed to unlock {:?}: {}",
+				self.0.path,
+				std::io::Error::last_os_error()
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::Duration;
+
+	use super::ProcessLock;
+	use crate::{backend::mock::MockBackend, error::GencmdCmdError, gencmd::Gencmd};
+
+	#[test]
+	fn times_out_on_contention() {
+		let path = std::env::temp_dir().join(format!("vcgencmd-test-{}.lock", std::process::id()));
+
+		// separate opens of the same file contend like separate processes would
+		let holder = ProcessLock::open(&path).unwrap();
+		let lock = ProcessLock::open(&path)
+			.unwrap()
+			.with_timeout(Duration::from_millis(20));
+
+		let guard = holder.acquire().unwrap();
+		assert!(matches!(
+			lock.acquire(),
+			Err(GencmdCmdError::LockTimeout(_))
+		));
+
+		let mut gencmd = Gencmd::new();
+		gencmd.set_process_lock(Some(lock));
+		assert!(matches!(
+			gencmd.send_cmd_raw(&mut MockBackend::new(), "measure_temp"),
+			Err(GencmdCmdError::LockTimeout(_))
+		));
+
+		drop(guard);
+		assert_eq!(
+			gencmd
+				.send_cmd_raw(&mut MockBackend::new(), "measure_temp")
+				.unwrap(),
+			"temp=45.6'C"
+		);
+
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn creates_file_for_everyone() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let path =
+			std::env::temp_dir().join(format!("vcgencmd-test-mode-{}.lock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+
+		// the default umask of 0o022 would leave 0o644
+		let lock = ProcessLock::open(&path).unwrap();
+		let mode = std::fs::metadata(lock.path()).unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, 0o666);
+
+		// the existing file is opened as is
+		assert!(ProcessLock::open(&path).is_ok());
+
+		std::fs::remove_file(&path).unwrap();
+	}
+}
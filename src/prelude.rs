@@ -1,8 +1,17 @@
 pub use crate::{
+	backend::GencmdBackend,
 	error::*,
-	gencmd::{commands::*, unique::GencmdUnique, Gencmd},
-	global::GlobalInstance,
+	gencmd::{
+		capabilities::Capabilities, commands::*, handle::GencmdHandle, retry::RetryPolicy,
+		throttle::ThrottleTracker, unique::GencmdUnique, Gencmd,
+	},
 };
 
+#[cfg(global_instance_available)]
+pub use crate::global::GlobalInstance;
+
 #[cfg(feature = "global_singleton")]
 pub use crate::gencmd::global::GencmdGlobal;
+
+#[cfg(feature = "async_api")]
+pub use crate::gencmd::asynchronous::AsyncGencmd;
@@ -0,0 +1,30 @@
+//! Access to gencmd over the network.
+//!
+//! The [`GencmdServer`](server::GencmdServer) exposes a backend using the framed protocol described in [`protocol`],
+//! optionally requiring a shared secret and restricting the allowed commands. The [`RemoteBackend`](crate::backend::remote::RemoteBackend)
+//! is the client, so the typed commands work the same across the network.
+//!
+//! The same server can listen on a Unix socket instead, acting as a local daemon which owns the only instance
+//! and serializes the commands of all processes on the system.
+
+use std::{net::TcpStream, time::Duration};
+
+pub mod protocol;
+pub mod server;
+
+/// A stream whose reads can time out, such as a socket.
+pub trait ReadTimeout {
+	/// Sets the timeout of subsequent reads, `None` lets them block forever.
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+impl ReadTimeout for TcpStream {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+		TcpStream::set_read_timeout(self, timeout)
+	}
+}
+#[cfg(unix)]
+impl ReadTimeout for std::os::unix::net::UnixStream {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+		std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+	}
+}
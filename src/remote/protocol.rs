@@ -0,0 +1,273 @@
+//! The framed request/response protocol.
+//!
+//! Every message is a frame: a big-endian `u32` payload length followed by the payload. The first byte of the payload is the message tag.
+//!
+//! Requests:
+//! * `0x01` followed by the shared secret authenticates the connection.
+//! * `0x02` followed by the command sends a command.
+//!
+//! Responses:
+//! * `0x00` followed by the raw response (including error responses of the firmware).
+//! * `0x01` followed by a [`RemoteError`] code and an optional message.
+
+use std::io::{Read, Write};
+
+use thiserror::Error;
+
+/// Maximum accepted payload length.
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+/// Default TCP port of the server.
+pub const DEFAULT_PORT: u16 = 7180;
+/// Default path of the local daemon socket.
+pub const DEFAULT_SOCKET_PATH: &str = "/run/vcgencmd.sock";
+
+const REQUEST_AUTH: u8 = 0x01;
+const REQUEST_COMMAND: u8 = 0x02;
+const RESPONSE_OK: u8 = 0x00;
+const RESPONSE_ERROR: u8 = 0x01;
+
+#[derive(Error, Debug)]
+pub enum ProtocolError {
+	#[error(transparent)]
+	Io(#[from] std::io::Error),
+	#[error("Frame of {0} bytes exceeds the maximum length")]
+	FrameTooLong(usize),
+	#[error("Invalid message: {0}")]
+	Invalid(String),
+}
+
+/// Errors reported by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteError {
+	Send,
+	Read,
+	CommandTooLong,
+	/// The command is not in the allowlist of the server.
+	NotAllowed,
+	/// The connection has not been authenticated or the secret is wrong.
+	Unauthorized,
+	Other(String),
+}
+impl RemoteError {
+	const fn code(&self) -> u8 {
+		match self {
+			RemoteError::Send => 1,
+			RemoteError::Read => 2,
+			RemoteError::CommandTooLong => 3,
+			RemoteError::NotAllowed => 4,
+			RemoteError::Unauthorized => 5,
+			RemoteError::Other(_) => 255,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+	Auth(String),
+	Command(String),
+}
+impl Request {
+	pub fn encode(&self) -> Vec<u8> {
+		let (tag, body) = match self {
+			Request::Auth(secret) => (REQUEST_AUTH, secret),
+			Request::Command(command) => (REQUEST_COMMAND, command),
+		};
+
+		let mut payload = Vec::with_capacity(1 + body.len());
+		payload.push(tag);
+		payload.extend_from_slice(body.as_bytes());
+
+		payload
+	}
+
+	pub fn decode(payload: &[u8]) -> Result<Self, ProtocolError> {
+		let (&tag, body) = payload
+			.split_first()
+			.ok_or_else(|| ProtocolError::Invalid("empty request".to_string()))?;
+		let body = std::str::from_utf8(body)
+			.map_err(|err| ProtocolError::Invalid(err.to_string()))?
+			.to_string();
+
+		match tag {
+			REQUEST_AUTH => Ok(Request::Auth(body)),
+			REQUEST_COMMAND => Ok(Request::Command(body)),
+			tag => Err(ProtocolError::Invalid(format!(
+				"unknown request tag {:#x}",
+				tag
+			))),
+		}
+	}
+
+	pub fn write_to(&self, writer: &mut impl Write) -> Result<(), ProtocolError> {
+		write_frame(writer, &self.encode())
+	}
+
+	/// Reads a request, returning `None` if the stream ended before it.
+	pub fn read_from(reader: &mut impl Read) -> Result<Option<Self>, ProtocolError> {
+		match read_frame(reader)? {
+			Some(payload) => Self::decode(&payload).map(Some),
+			None => Ok(None),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+	Ok(Vec<u8>),
+	Error(RemoteError),
+}
+impl Response {
+	pub fn encode(&self) -> Vec<u8> {
+		match self {
+			Response::Ok(response) => {
+				let mut payload = Vec::with_capacity(1 + response.len());
+				payload.push(RESPONSE_OK);
+				payload.extend_from_slice(response);
+
+				payload
+			}
+			Response::Error(error) => {
+				let mut payload = vec![RESPONSE_ERROR, error.code()];
+				if let RemoteError::Other(message) = error {
+					payload.extend_from_slice(message.as_bytes());
+				}
+
+				payload
+			}
+		}
+	}
+
+	pub fn decode(payload: &[u8]) -> Result<Self, ProtocolError> {
+		match payload {
+			[RESPONSE_OK, response @ ..] => Ok(Response::Ok(response.to_vec())),
+			[RESPONSE_ERROR, code, message @ ..] => {
+				let error = match code {
+					1 => RemoteError::Send,
+					2 => RemoteError::Read,
+					3 => RemoteError::CommandTooLong,
+					4 => RemoteError::NotAllowed,
+					5 => RemoteError::Unauthorized,
+					_ => RemoteError::Other(String::from_utf8_lossy(message).into_owned()),
+				};
+
+				Ok(Response::Error(error))
+			}
+			_ => Err(ProtocolError::Invalid("malformed response".to_string())),
+		}
+	}
+
+	pub fn write_to(&self, writer: &mut impl Write) -> Result<(), ProtocolError> {
+		write_frame(writer, &self.encode())
+	}
+
+	pub fn read_from(reader: &mut impl Read) -> Result<Self, ProtocolError> {
+		match read_frame(reader)? {
+			Some(payload) => Self::decode(&payload),
+			None => Err(ProtocolError::Io(std::io::ErrorKind::UnexpectedEof.into())),
+		}
+	}
+}
+
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> Result<(), ProtocolError> {
+	if payload.len() > MAX_FRAME_LEN {
+		return Err(ProtocolError::FrameTooLong(payload.len()));
+	}
+
+	let mut frame = Vec::with_capacity(4 + payload.len());
+	frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+	frame.extend_from_slice(payload);
+	writer.write_all(&frame)?;
+	writer.flush()?;
+
+	Ok(())
+}
+
+/// Reads a frame, returning `None` if the stream ended cleanly before it.
+pub fn read_frame(reader: &mut impl Read) -> Result<Option<Vec<u8>>, ProtocolError> {
+	let mut len = [0u8; 4];
+	match reader.read_exact(&mut len) {
+		Ok(()) => (),
+		Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+		Err(err) => return Err(err.into()),
+	}
+
+	let len = u32::from_be_bytes(len) as usize;
+	if len > MAX_FRAME_LEN {
+		return Err(ProtocolError::FrameTooLong(len));
+	}
+
+	let mut payload = vec![0u8; len];
+	reader.read_exact(&mut payload)?;
+
+	Ok(Some(payload))
+}
+
+/// Splits the first frame off `buffer`, returning its payload and the length of the whole frame, or `None` if the frame is incomplete.
+pub fn split_frame(buffer: &[u8]) -> Result<Option<(&[u8], usize)>, ProtocolError> {
+	let (len, rest) = match buffer.split_first_chunk::<4>() {
+		Some((len, rest)) => (u32::from_be_bytes(*len) as usize, rest),
+		None => return Ok(None),
+	};
+	if len > MAX_FRAME_LEN {
+		return Err(ProtocolError::FrameTooLong(len));
+	}
+
+	Ok(rest.get(..len).map(|payload| (payload, 4 + len)))
+}
+
+#[cfg(test)]
+mod test {
+	use super::{read_frame, split_frame, RemoteError, Request, Response, MAX_FRAME_LEN};
+
+	#[test]
+	fn messages_round_trip() {
+		let mut buffer = Vec::new();
+		Request::Command("measure_clock arm".to_string())
+			.write_to(&mut buffer)
+			.unwrap();
+		Response::Ok(b"frequency(48)=600000000".to_vec())
+			.write_to(&mut buffer)
+			.unwrap();
+		Response::Error(RemoteError::Other("busy".to_string()))
+			.write_to(&mut buffer)
+			.unwrap();
+
+		let mut reader = buffer.as_slice();
+		assert_eq!(
+			Request::read_from(&mut reader).unwrap(),
+			Some(Request::Command("measure_clock arm".to_string()))
+		);
+		assert_eq!(
+			Response::read_from(&mut reader).unwrap(),
+			Response::Ok(b"frequency(48)=600000000".to_vec())
+		);
+		assert_eq!(
+			Response::read_from(&mut reader).unwrap(),
+			Response::Error(RemoteError::Other("busy".to_string()))
+		);
+		assert_eq!(Request::read_from(&mut reader).unwrap(), None);
+	}
+
+	#[test]
+	fn rejects_oversized_frames() {
+		let frame = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+
+		assert!(read_frame(&mut frame.as_slice()).is_err());
+		assert!(split_frame(&frame).is_err());
+	}
+
+	#[test]
+	fn splits_complete_frames_only() {
+		let mut buffer = Vec::new();
+		Response::Ok(b"temp=42.0'C".to_vec())
+			.write_to(&mut buffer)
+			.unwrap();
+
+		assert_eq!(split_frame(&buffer[..3]).unwrap(), None);
+		assert_eq!(split_frame(&buffer[..buffer.len() - 1]).unwrap(), None);
+		assert_eq!(
+			split_frame(&buffer).unwrap(),
+			Some((&buffer[4..], buffer.len()))
+		);
+	}
+}
@@ -0,0 +1,559 @@
+//! Server side of the protocol.
+
+use std::{
+	ffi::CString,
+	io::{Read, Write},
+	net::TcpListener,
+	ops::DerefMut,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Mutex,
+	},
+	time::{Duration, Instant},
+};
+
+use super::{
+	protocol::{ProtocolError, RemoteError, Request, Response},
+	ReadTimeout,
+};
+use crate::{
+	backend::GencmdBackend, error::GencmdCmdError, ffi, gencmd::capabilities::Capabilities,
+};
+
+/// Default time a connection may stay silent before it is closed.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+/// Default time a client has to authenticate after connecting.
+pub const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Default number of connections handled at the same time.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+/// Default time the backend has to respond to a command.
+pub const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves commands from a shared backend to remote clients.
+///
+/// Each connection is handled on its own thread and commands of all connections are serialized by the backend mutex.
+/// Connections beyond [`with_max_connections`](Self::with_max_connections) are closed right away.
+pub struct GencmdServer<B: GencmdBackend> {
+	backend: Arc<Mutex<B>>,
+	secret: Option<String>,
+	allowlist: Option<Capabilities>,
+	idle_timeout: Option<Duration>,
+	auth_timeout: Duration,
+	max_connections: usize,
+	connections: AtomicUsize,
+	response_timeout: Option<Duration>,
+}
+impl<B: GencmdBackend> GencmdServer<B> {
+	pub fn new(backend: Arc<Mutex<B>>) -> Self {
+		GencmdServer {
+			backend,
+			secret: None,
+			allowlist: None,
+			idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+			auth_timeout: DEFAULT_AUTH_TIMEOUT,
+			max_connections: DEFAULT_MAX_CONNECTIONS,
+			connections: AtomicUsize::new(0),
+			response_timeout: Some(DEFAULT_RESPONSE_TIMEOUT),
+		}
+	}
+
+	/// Serves the backend of `gencmd`.
+	#[cfg(feature = "global_singleton")]
+	pub fn from_global<G: std::borrow::BorrowMut<crate::gencmd::Gencmd>>(
+		gencmd: &crate::gencmd::global::GencmdGlobal<G, B>,
+	) -> Self {
+		Self::new(gencmd.1.clone())
+	}
+
+	/// Requires clients to authenticate with `secret` before sending commands.
+	pub fn with_secret(mut self, secret: impl Into<String>) -> Self {
+		self.secret = Some(secret.into());
+		self
+	}
+
+	/// Only allows commands whose name is in `allowlist`.
+	pub fn with_allowlist(mut self, allowlist: Capabilities) -> Self {
+		self.allowlist = Some(allowlist);
+		self
+	}
+
+	/// Closes served connections which send nothing for `timeout`, `None` keeps them open forever.
+	pub fn with_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+		self.idle_timeout = timeout;
+		self
+	}
+
+	/// Closes served connections which have not authenticated within `timeout` of connecting.
+	///
+	/// Only applies if a secret is set.
+	pub fn with_auth_timeout(mut self, timeout: Duration) -> Self {
+		self.auth_timeout = timeout;
+		self
+	}
+
+	/// Limits the number of connections served at the same time.
+	pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+		self.max_connections = max_connections;
+		self
+	}
+
+	/// Fails commands whose response does not arrive within `timeout`, `None` waits forever.
+	///
+	/// With a timeout, commands are refused if the backend does not [support timeouts](GencmdBackend::supports_timeout).
+	pub fn with_response_timeout(mut self, timeout: Option<Duration>) -> Self {
+		self.response_timeout = timeout;
+		self
+	}
+
+	/// Handles requests on `stream` until the client disconnects.
+	///
+	/// No timeouts are applied, those are set up by [`serve_tcp`](Self::serve_tcp) and [`serve_unix`](Self::serve_unix).
+	pub fn handle_connection(&self, mut stream: impl Read + Write) -> Result<(), ProtocolError> {
+		self.handle_requests(&mut stream, |_| ())
+	}
+
+	fn handle_timed<S: Read + Write + ReadTimeout>(&self, stream: S) -> Result<(), ProtocolError> {
+		let mut stream = DeadlineStream {
+			stream,
+			idle_timeout: self.idle_timeout,
+			deadline: self
+				.secret
+				.as_ref()
+				.map(|_| Instant::now() + self.auth_timeout),
+		};
+
+		self.handle_requests(&mut stream, |stream| stream.deadline = None)
+	}
+
+	fn handle_requests<S: Read + Write>(
+		&self,
+		stream: &mut S,
+		mut on_authenticated: impl FnMut(&mut S),
+	) -> Result<(), ProtocolError> {
+		let mut authenticated = self.secret.is_none();
+
+		while let Some(request) = Request::read_from(stream)? {
+			let response = match request {
+				Request::Auth(secret) => match self.secret {
+					Some(ref expected)
+						if !constant_time_eq(expected.as_bytes(), secret.as_bytes()) =>
+					{
+						log::warn!("client failed to authenticate");
+						Response::Error(RemoteError::Unauthorized).write_to(stream)?;
+						return Ok(());
+					}
+					_ => {
+						authenticated = true;
+						on_authenticated(stream);
+						Response::Ok(Vec::new())
+					}
+				},
+				Request::Command(_) if !authenticated => {
+					log::warn!("client sent a command without authenticating");
+					Response::Error(RemoteError::Unauthorized).write_to(stream)?;
+					return Ok(());
+				}
+				Request::Command(command) => self.execute(&command),
+			};
+
+			response.write_to(stream)?;
+		}
+
+		Ok(())
+	}
+
+	/// Sends `command` to the backend and returns the raw response.
+	pub fn execute(&self, command: &str) -> Response {
+		if let Some(ref allowlist) = self.allowlist {
+			if !allowlist.supports_command(command) {
+				log::warn!("rejecting command not in allowlist: {:?}", command);
+				return Response::Error(RemoteError::NotAllowed);
+			}
+		}
+
+		let command = match CString::new(command) {
+			Ok(command) => command,
+			Err(err) => return Response::Error(RemoteError::Other(err.to_string())),
+		};
+
+		let mut buffer = vec![0u8; ffi::GENCMDSERVICE_MSGFIFO_SIZE as usize];
+		let mut lock = self.backend.lock().expect("mutex poisoned");
+		let backend = lock.deref_mut();
+		if self.response_timeout.is_some() && !backend.supports_timeout() {
+			return Response::Error(RemoteError::Other(
+				GencmdCmdError::TimeoutUnsupported.to_string(),
+			));
+		}
+
+		// SAFETY: the response is retrieved right under while holding the lock
+		let result =
+			unsafe { backend.send_command(&command) }.and_then(|_| match self.response_timeout {
+				Some(timeout) => backend.retrieve_response_timeout(&mut buffer, timeout),
+				None => backend.retrieve_response(&mut buffer),
+			});
+
+		match result {
+			Ok(len) => {
+				buffer.truncate(len);
+				Response::Ok(buffer)
+			}
+			Err(err) => Response::Error(match err {
+				GencmdCmdError::Send => RemoteError::Send,
+				GencmdCmdError::Read => RemoteError::Read,
+				GencmdCmdError::CommandTooLong => RemoteError::CommandTooLong,
+				err => RemoteError::Other(err.to_string()),
+			}),
+		}
+	}
+}
+impl<B: GencmdBackend + Send + 'static> GencmdServer<B> {
+	/// Accepts connections on `listener` and handles each on a new thread.
+	///
+	/// Only returns if accepting fails.
+	pub fn serve_tcp(self: Arc<Self>, listener: TcpListener) -> std::io::Result<()> {
+		log::info!("serving gencmd on {}", listener.local_addr()?);
+
+		loop {
+			let (stream, address) = listener.accept()?;
+			let slot = match ConnectionSlot::acquire(&self) {
+				Some(slot) => slot,
+				None => {
+					log::warn!("refusing connection from {}: too many connections", address);
+					continue;
+				}
+			};
+			log::debug!("accepted connection from {}", address);
+
+			std::thread::spawn(move || {
+				if let Err(err) = slot.0.handle_timed(stream) {
+					log::warn!("connection from {} failed: {}", address, err);
+				}
+				log::debug!("connection from {} closed", address);
+			});
+		}
+	}
+
+	/// Accepts connections on `listener` and handles each on a new thread.
+	///
+	/// This lets a single daemon own the backend and serialize the commands of all local processes.
+	///
+	/// Only returns if accepting fails.
+	#[cfg(unix)]
+	pub fn serve_unix(
+		self: Arc<Self>,
+		listener: std::os::unix::net::UnixListener,
+	) -> std::io::Result<()> {
+		log::info!("serving gencmd on {:?}", listener.local_addr()?);
+
+		loop {
+			let (stream, _) = listener.accept()?;
+			let slot = match ConnectionSlot::acquire(&self) {
+				Some(slot) => slot,
+				None => {
+					log::warn!("refusing local connection: too many connections");
+					continue;
+				}
+			};
+			log::debug!("accepted local connection");
+
+			std::thread::spawn(move || {
+				if let Err(err) = slot.0.handle_timed(stream) {
+					log::warn!("local connection failed: {}", err);
+				}
+				log::debug!("local connection closed");
+			});
+		}
+	}
+}
+
+/// Counts a connection against the limit of the server until dropped.
+struct ConnectionSlot<B: GencmdBackend>(Arc<GencmdServer<B>>);
+impl<B: GencmdBackend> ConnectionSlot<B> {
+	fn acquire(server: &Arc<GencmdServer<B>>) -> Option<Self> {
+		server
+			.connections
+			.fetch_update(Ordering::AcqRel, Ordering::Acquire, |connections| {
+				(connections < server.max_connections).then_some(connections + 1)
+			})
+			.ok()
+			.map(|_| ConnectionSlot(server.clone()))
+	}
+}
+impl<B: GencmdBackend> Drop for ConnectionSlot<B> {
+	fn drop(&mut self) {
+		self.0.connections.fetch_sub(1, Ordering::AcqRel);
+	}
+}
+
+/// Times out reads after the idle timeout, or once `deadline` has passed.
+struct DeadlineStream<S> {
+	stream: S,
+	idle_timeout: Option<Duration>,
+	deadline: Option<Instant>,
+}
+impl<S: Read + ReadTimeout> Read for DeadlineStream<S> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let timeout = match self.deadline {
+			Some(deadline) => {
+				let remaining = deadline.saturating_duration_since(Instant::now());
+				if remaining.is_zero() {
+					return Err(std::io::ErrorKind::TimedOut.into());
+				}
+
+				Some(
+					self.idle_timeout
+						.map_or(remaining, |idle| idle.min(remaining)),
+				)
+			}
+			None => self.idle_timeout,
+		};
+
+		self.stream.set_read_timeout(timeout)?;
+		self.stream.read(buf)
+	}
+}
+impl<S: Write> Write for DeadlineStream<S> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.stream.write(buf)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.stream.flush()
+	}
+}
+
+/// Binds the daemon socket at `path`, replacing a socket left behind by a daemon which is no longer running.
+///
+/// Fails with [`AddrInUse`](std::io::ErrorKind::AddrInUse) if another daemon still accepts connections on `path`
+/// and with [`AlreadyExists`](std::io::ErrorKind::AlreadyExists) if `path` is not a socket, neither of which is removed.
+///
+/// The socket is made accessible to all users regardless of the umask, restrict access with a secret or the permissions of its directory.
+#[cfg(unix)]
+pub fn bind_unix(
+	path: impl AsRef<std::path::Path>,
+) -> std::io::Result<std::os::unix::net::UnixListener> {
+	use std::{
+		fs::Permissions,
+		os::unix::{
+			fs::{FileTypeExt, PermissionsExt},
+			net::{UnixListener, UnixStream},
+		},
+	};
+
+	let path = path.as_ref();
+	match std::fs::symlink_metadata(path) {
+		Ok(metadata) if !metadata.file_type().is_socket() => {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::AlreadyExists,
+				format!("{} exists and is not a socket", path.display()),
+			));
+		}
+		Ok(_) => {
+			if UnixStream::connect(path).is_ok() {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::AddrInUse,
+					format!("a daemon is already running on {}", path.display()),
+				));
+			}
+
+			log::info!("removing stale socket {}", path.display());
+			std::fs::remove_file(path)?;
+		}
+		Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+		Err(err) => return Err(err),
+	}
+
+	let listener = UnixListener::bind(path)?;
+	std::fs::set_permissions(path, Permissions::from_mode(0o666))?;
+
+	Ok(listener)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false;
+	}
+
+	a.iter().zip(b).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		io::Read,
+		net::{SocketAddr, TcpListener, TcpStream},
+		sync::{Arc, Mutex},
+		time::{Duration, Instant},
+	};
+
+	use super::{bind_unix, GencmdServer};
+	use crate::{
+		backend::{mock::MockBackend, remote::RemoteBackend},
+		error::{GencmdCmdError, GencmdInitError},
+		gencmd::{
+			capabilities::Capabilities,
+			commands::{CmdGetThrottled, CmdMeasureTemp},
+			unique::GencmdUnique,
+		},
+	};
+
+	fn spawn_server(server: GencmdServer<MockBackend>) -> SocketAddr {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+
+		let server = Arc::new(server);
+		std::thread::spawn(move || server.serve_tcp(listener));
+
+		address
+	}
+
+	#[test]
+	fn serves_commands_over_loopback() {
+		let address = spawn_server(GencmdServer::new(Arc::new(Mutex::new(MockBackend::new()))));
+
+		let mut gencmd = GencmdUnique::from_backend(RemoteBackend::connect(address, None).unwrap());
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 45.6);
+		assert!(matches!(
+			gencmd.send_cmd_raw("not_a_command"),
+			Err(GencmdCmdError::ErrorResponse(_))
+		));
+	}
+
+	#[test]
+	fn enforces_secret_and_allowlist() {
+		let address = spawn_server(
+			GencmdServer::new(Arc::new(Mutex::new(MockBackend::new())))
+				.with_secret("hunter2")
+				.with_allowlist(Capabilities::from_commands(["measure_temp"])),
+		);
+
+		assert!(matches!(
+			RemoteBackend::connect(address, Some("wrong")),
+			Err(GencmdInitError::Unauthorized)
+		));
+
+		let mut unauthenticated =
+			GencmdUnique::from_backend(RemoteBackend::connect(address, None).unwrap());
+		assert!(matches!(
+			unauthenticated.send_cmd::<CmdMeasureTemp>(),
+			Err(GencmdCmdError::Remote(_))
+		));
+
+		let mut gencmd =
+			GencmdUnique::from_backend(RemoteBackend::connect(address, Some("hunter2")).unwrap());
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 45.6);
+		assert!(matches!(
+			gencmd.send_cmd::<CmdGetThrottled>(),
+			Err(GencmdCmdError::Unsupported(_))
+		));
+	}
+
+	#[test]
+	fn closes_unauthenticated_and_excess_connections() {
+		let address = spawn_server(
+			GencmdServer::new(Arc::new(Mutex::new(MockBackend::new())))
+				.with_secret("hunter2")
+				.with_auth_timeout(Duration::from_millis(100))
+				.with_max_connections(1),
+		);
+
+		// a silent client takes the only slot
+		let mut silent = TcpStream::connect(address).unwrap();
+		silent
+			.set_read_timeout(Some(Duration::from_secs(5)))
+			.unwrap();
+		assert!(RemoteBackend::connect(address, Some("hunter2")).is_err());
+
+		// and is disconnected once it failed to authenticate in time
+		assert_eq!(silent.read(&mut [0u8; 1]).unwrap(), 0);
+
+		let deadline = Instant::now() + Duration::from_secs(5);
+		let backend = loop {
+			match RemoteBackend::connect(address, Some("hunter2")) {
+				Ok(backend) => break backend,
+				Err(_) if Instant::now() < deadline => {
+					std::thread::sleep(Duration::from_millis(10))
+				}
+				Err(err) => panic!("slot was not released: {}", err),
+			}
+		};
+		let mut gencmd = GencmdUnique::from_backend(backend);
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 45.6);
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn serves_multiple_local_clients() {
+		let path = std::env::temp_dir().join(format!("vcgencmd-test-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+		let server = Arc::new(GencmdServer::new(Arc::new(Mutex::new(MockBackend::new()))));
+		std::thread::spawn(move || server.serve_unix(listener));
+
+		let clients: Vec<_> = (0..4)
+			.map(|_| {
+				let path = path.clone();
+				std::thread::spawn(move || {
+					let mut gencmd = GencmdUnique::from_backend(
+						RemoteBackend::connect_unix(path, None).unwrap(),
+					);
+					for _ in 0..16 {
+						assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 45.6);
+					}
+				})
+			})
+			.collect();
+		for client in clients {
+			client.join().unwrap();
+		}
+
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn refuses_to_replace_running_daemon() {
+		let path =
+			std::env::temp_dir().join(format!("vcgencmd-test-{}-bind.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+
+		// a stale socket is replaced
+		drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+		let listener = bind_unix(&path).unwrap();
+
+		let server = Arc::new(GencmdServer::new(Arc::new(Mutex::new(MockBackend::new()))));
+		std::thread::spawn(move || server.serve_unix(listener));
+
+		// a second daemon must not take the socket of the running one
+		let err = bind_unix(&path).unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+		let mut gencmd =
+			GencmdUnique::from_backend(RemoteBackend::connect_unix(&path, None).unwrap());
+		assert_eq!(gencmd.send_cmd::<CmdMeasureTemp>().unwrap(), 45.6);
+		std::fs::remove_file(&path).unwrap();
+
+		// other files are left alone
+		std::fs::write(&path, "not a socket").unwrap();
+		let err = bind_unix(&path).unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn creates_socket_for_everyone() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let path =
+			std::env::temp_dir().join(format!("vcgencmd-test-{}-mode.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+
+		let _listener = bind_unix(&path).unwrap();
+		let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, 0o666);
+
+		std::fs::remove_file(&path).unwrap();
+	}
+}
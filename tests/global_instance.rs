@@ -0,0 +1,102 @@
+//! Tests of the global instance itself.
+//!
+//! They run in their own process because the unit tests keep a global instance alive, and take [`GLOBAL`]
+//! so that only one of them initializes the process-wide vc state at a time. The mock is required so that they
+//! don't depend on the hardware.
+#![cfg(feature = "mock_vc_ffi")]
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use videocore_gencmd::{error::GencmdInitError, global::GlobalInstance};
+
+static GLOBAL: Mutex<()> = Mutex::new(());
+
+fn lock_global() -> MutexGuard<'static, ()> {
+	GLOBAL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[test]
+fn global_instance_is_unique() {
+	let _guard = lock_global();
+	let instance = GlobalInstance::new().unwrap();
+
+	match GlobalInstance::new() {
+		Err(GencmdInitError::AlreadyInitialized) => (),
+		_ => panic!("global instance must be unique"),
+	}
+
+	instance.deinit().unwrap();
+}
+
+#[test]
+fn global_instance_reports_init_faults() {
+	use videocore_gencmd::{
+		error::GencmdDeinitError,
+		ffi::{
+			mock::{MockFault, MOCK_FAULTS},
+			VCOS_STATUS_T,
+		},
+	};
+
+	let _guard = lock_global();
+	for status in [
+		VCOS_STATUS_T::VCOS_EAGAIN,
+		VCOS_STATUS_T::VCOS_ENOENT,
+		VCOS_STATUS_T::VCOS_ENOSPC,
+		VCOS_STATUS_T::VCOS_EINVAL,
+		VCOS_STATUS_T::VCOS_EACCESS,
+		VCOS_STATUS_T::VCOS_ENOMEM,
+		VCOS_STATUS_T::VCOS_ENOSYS,
+		VCOS_STATUS_T::VCOS_EEXIST,
+		VCOS_STATUS_T::VCOS_ENXIO,
+		VCOS_STATUS_T::VCOS_EINTR,
+	] {
+		MOCK_FAULTS.inject(MockFault::VcosInit(status));
+		match GlobalInstance::new() {
+			Err(GencmdInitError::VcosInit(err)) => {
+				assert_eq!(err as u8 as u32, status.0)
+			}
+			other => panic!(
+				"expected vcos error for {:?}, got {:?}",
+				status,
+				other.err()
+			),
+		}
+		MOCK_FAULTS.clear();
+	}
+
+	MOCK_FAULTS.inject(MockFault::VchiInitialise);
+	assert!(matches!(
+		GlobalInstance::new(),
+		Err(GencmdInitError::VchiInit)
+	));
+	MOCK_FAULTS.clear();
+
+	MOCK_FAULTS.inject(MockFault::VchiConnect);
+	assert!(matches!(
+		GlobalInstance::new(),
+		Err(GencmdInitError::VchiConnect)
+	));
+	MOCK_FAULTS.clear();
+
+	// failed initializations must not block new instances
+	let mut instance = GlobalInstance::new().unwrap();
+
+	MOCK_FAULTS.inject(MockFault::VchiDisconnect);
+	assert!(matches!(
+		instance.deinit_ref_mut(),
+		Err(GencmdDeinitError::VchiDisconnect)
+	));
+	MOCK_FAULTS.clear();
+
+	instance.deinit().unwrap();
+}
+
+#[test]
+fn global_instance_is_unique_respects_drop() {
+	let _guard = lock_global();
+	let instance = GlobalInstance::new().unwrap();
+	std::mem::drop(instance);
+
+	GlobalInstance::new().unwrap();
+}